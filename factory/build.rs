@@ -0,0 +1,34 @@
+use cargo_near_build::{bon, extended, BuildOpts};
+
+fn main() {
+    // the fungible token crate is the root of the workspace
+    let workdir = "..";
+    let manifest = camino::Utf8PathBuf::from(workdir).join("Cargo.toml");
+
+    let build_opts = BuildOpts::builder()
+        .manifest_path(manifest)
+        .no_abi(true)
+        .no_embed_abi(true)
+        .override_nep330_contract_path(".")
+        // a distinct target is needed to avoid deadlock during build
+        .override_cargo_target_dir("../target/build-rs-fungible-token")
+        .build();
+
+    let build_script_opts = extended::BuildScriptOpts::builder()
+        .rerun_if_changed_list(bon::vec!["../src", "../Cargo.toml"])
+        .build_skipped_when_env_is(vec![
+            // shorter build for `cargo check`, `cargo clippy` and unit tests
+            ("PROFILE", "debug"),
+            (cargo_near_build::env_keys::BUILD_RS_ABI_STEP_HINT, "true"),
+        ])
+        .stub_path("../target/fungible-token-stub.bin")
+        .result_env_key("BUILD_RS_SUB_BUILD_FUNGIBLE_TOKEN")
+        .build();
+
+    let extended_opts = extended::BuildOptsExtended::builder()
+        .build_opts(build_opts)
+        .build_script_opts(build_script_opts)
+        .build();
+
+    cargo_near_build::extended::build(extended_opts).expect("fungible token build error");
+}
@@ -0,0 +1,332 @@
+/*!
+Factory deploying configured instances of the fungible token.
+
+`create_token` creates the `<symbol>.<factory-account-id>` subaccount, deploys the fungible token
+contract embedded in the factory on it, and initializes it with `new`. The attached deposit pays
+for the registry entry of the token, and the rest is transferred to the new account to cover the
+storage of the contract. If the token can't be created, the entry is removed and the deposit is
+refunded.
+
+The embedded contract is built from the root of the workspace by `build.rs`. Debug builds embed an
+empty stub instead, so only release builds of the factory can create tokens.
+*/
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::{
+    env, log, near, require, AccountId, Gas, NearToken, PanicOnDefault, Promise, PromiseResult,
+    StorageUsage,
+};
+
+/// Validation of the metadata shared with the token contract, so that tokens which `new` would
+/// reject aren't created. Depending on the token crate would link its contract methods too.
+#[path = "../../src"]
+mod fungible_token {
+    pub mod metadata;
+}
+
+const FUNGIBLE_TOKEN_WASM: &[u8] = include_bytes!(env!("BUILD_RS_SUB_BUILD_FUNGIBLE_TOKEN"));
+
+const GAS_FOR_NEW: Gas = Gas::from_tgas(50);
+const GAS_FOR_RESOLVE_CREATE_TOKEN: Gas = Gas::from_tgas(10);
+
+/// Storage of the token account and its state besides the contract code and the metadata.
+const TOKEN_STORAGE_USAGE: StorageUsage = 1_000;
+/// Storage of a registry entry besides the account ids, name and symbol.
+const REGISTRY_ENTRY_STORAGE_USAGE: StorageUsage = 200;
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct TokenInfo {
+    pub token_id: AccountId,
+    pub owner_id: AccountId,
+    pub name: String,
+    pub symbol: String,
+    pub total_supply: U128,
+    /// Block timestamp in nanoseconds of the creation.
+    pub created_at: u64,
+}
+
+#[derive(PanicOnDefault)]
+#[near(contract_state)]
+pub struct TokenFactory {
+    owner_id: AccountId,
+    /// Token account id -> token.
+    tokens: UnorderedMap<AccountId, TokenInfo>,
+}
+
+#[near]
+impl TokenFactory {
+    #[init]
+    pub fn new(owner_id: AccountId) -> Self {
+        require!(!env::state_exists(), "Already initialized");
+        Self {
+            owner_id,
+            tokens: UnorderedMap::new(b"t"),
+        }
+    }
+
+    /// Deploys a token on `<symbol>.<factory-account-id>`, initialized with the given total supply
+    /// owned by `owner_id` and the given metadata. Can only be called by the owner of the
+    /// factory. The attached deposit must be at least `get_required_deposit`.
+    #[payable]
+    pub fn create_token(
+        &mut self,
+        owner_id: AccountId,
+        total_supply: U128,
+        metadata: FungibleTokenMetadata,
+    ) -> Promise {
+        require!(
+            env::predecessor_account_id() == self.owner_id,
+            "Only the owner can create tokens"
+        );
+        fungible_token::metadata::assert_valid_metadata(&metadata);
+        let token_id = token_account_id(&metadata.symbol);
+        require!(
+            self.tokens.get(&token_id).is_none(),
+            format!("Token @{} already exists", token_id)
+        );
+        let required_deposit = self.get_required_deposit(metadata.clone());
+        let deposit = env::attached_deposit();
+        require!(
+            deposit >= required_deposit,
+            format!(
+                "Requires attached deposit of at least {}",
+                required_deposit.exact_amount_display()
+            )
+        );
+
+        let initial_storage_usage = env::storage_usage();
+        self.tokens.insert(
+            &token_id,
+            &TokenInfo {
+                token_id: token_id.clone(),
+                owner_id: owner_id.clone(),
+                name: metadata.name.clone(),
+                symbol: metadata.symbol.clone(),
+                total_supply,
+                created_at: env::block_timestamp(),
+            },
+        );
+        let registry_cost = env::storage_byte_cost()
+            .saturating_mul((env::storage_usage() - initial_storage_usage).into());
+
+        let args = json!({
+            "owner_id": owner_id,
+            "total_supply": total_supply,
+            "metadata": metadata,
+        });
+        Promise::new(token_id.clone())
+            .create_account()
+            .transfer(deposit.saturating_sub(registry_cost))
+            .deploy_contract(FUNGIBLE_TOKEN_WASM.to_vec())
+            .function_call(
+                "new".to_string(),
+                args.to_string().into_bytes(),
+                NearToken::from_yoctonear(0),
+                GAS_FOR_NEW,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_CREATE_TOKEN)
+                    .resolve_create_token(token_id, env::predecessor_account_id(), deposit),
+            )
+    }
+
+    /// Removes the registry entry and refunds the deposit if the token couldn't be created.
+    /// Returns whether the token was created.
+    #[private]
+    pub fn resolve_create_token(
+        &mut self,
+        token_id: AccountId,
+        creator_id: AccountId,
+        deposit: NearToken,
+    ) -> bool {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                log!("Created token @{}", token_id);
+                true
+            }
+            PromiseResult::Failed => {
+                self.tokens.remove(&token_id);
+                Promise::new(creator_id).transfer(deposit);
+                log!("Failed to create token @{}", token_id);
+                false
+            }
+        }
+    }
+
+    /// Minimum deposit `create_token` requires for a token with the given metadata.
+    pub fn get_required_deposit(&self, metadata: FungibleTokenMetadata) -> NearToken {
+        let metadata_size = near_sdk::borsh::to_vec(&metadata).unwrap().len() as StorageUsage;
+        let token_storage_usage =
+            FUNGIBLE_TOKEN_WASM.len() as StorageUsage + metadata_size + TOKEN_STORAGE_USAGE;
+        let registry_storage_usage = REGISTRY_ENTRY_STORAGE_USAGE
+            + 3 * env::current_account_id().len() as StorageUsage
+            + (metadata.name.len() + metadata.symbol.len()) as StorageUsage;
+        env::storage_byte_cost()
+            .saturating_mul((token_storage_usage + registry_storage_usage).into())
+    }
+
+    pub fn get_token(&self, token_id: AccountId) -> Option<TokenInfo> {
+        self.tokens.get(&token_id)
+    }
+
+    pub fn get_tokens(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<TokenInfo> {
+        self.tokens
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u64::MAX) as usize)
+            .collect()
+    }
+
+    pub fn get_tokens_count(&self) -> u64 {
+        self.tokens.len()
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+}
+
+/// `<symbol>.<factory-account-id>`, with the symbol in lowercase.
+fn token_account_id(symbol: &str) -> AccountId {
+    let prefix = symbol.to_lowercase();
+    require!(
+        !prefix.is_empty()
+            && prefix
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'),
+        "The symbol should only contain letters, digits, '-' and '_'"
+    );
+    format!("{}.{}", prefix, env::current_account_id())
+        .parse()
+        .unwrap_or_else(|_| env::panic_str("The symbol doesn't make a valid account id"))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig};
+
+    use super::*;
+
+    fn metadata(symbol: &str) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: format!("{} token", symbol),
+            symbol: symbol.to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
+    }
+
+    fn setup() -> (TokenFactory, VMContextBuilder) {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id("factory.near".parse().unwrap())
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_near(10));
+        testing_env!(context.build());
+        (TokenFactory::new(accounts(0)), context)
+    }
+
+    #[test]
+    fn test_create_token() {
+        let (mut factory, _) = setup();
+
+        factory.create_token(accounts(1), U128(1_000), metadata("ABC"));
+        factory.create_token(accounts(2), U128(2_000), metadata("xyz-2"));
+
+        let token = factory
+            .get_token("abc.factory.near".parse().unwrap())
+            .unwrap();
+        assert_eq!(token.owner_id, accounts(1));
+        assert_eq!(token.total_supply.0, 1_000);
+        assert_eq!(factory.get_tokens_count(), 2);
+        assert_eq!(
+            factory.get_tokens(Some(1), Some(10))[0].token_id.as_str(),
+            "xyz-2.factory.near"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Token @abc.factory.near already exists")]
+    fn test_create_token_panics_on_existing_symbol() {
+        let (mut factory, _) = setup();
+
+        factory.create_token(accounts(1), U128(1_000), metadata("ABC"));
+        factory.create_token(accounts(1), U128(1_000), metadata("abc"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The symbol should only contain letters, digits, '-' and '_'")]
+    fn test_create_token_panics_on_invalid_symbol() {
+        let (mut factory, _) = setup();
+
+        factory.create_token(accounts(1), U128(1_000), metadata("A.B"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The icon should be a data URL")]
+    fn test_create_token_panics_on_invalid_icon() {
+        let (mut factory, _) = setup();
+
+        factory.create_token(
+            accounts(1),
+            U128(1_000),
+            FungibleTokenMetadata {
+                icon: Some("https://example.com/icon.png".to_string()),
+                ..metadata("ABC")
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of at least")]
+    fn test_create_token_panics_on_insufficient_deposit() {
+        let (mut factory, mut context) = setup();
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        factory.create_token(accounts(1), U128(1_000), metadata("ABC"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can create tokens")]
+    fn test_create_token_panics_on_non_owner() {
+        let (mut factory, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        factory.create_token(accounts(1), U128(1_000), metadata("ABC"));
+    }
+
+    #[test]
+    fn test_failed_creation_is_removed_from_registry() {
+        let (mut factory, mut context) = setup();
+        factory.create_token(accounts(1), U128(1_000), metadata("ABC"));
+
+        testing_env!(
+            context
+                .predecessor_account_id("factory.near".parse().unwrap())
+                .build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        let created = factory.resolve_create_token(
+            "abc.factory.near".parse().unwrap(),
+            accounts(0),
+            NearToken::from_near(10),
+        );
+
+        assert!(!created);
+        assert_eq!(factory.get_tokens_count(), 0);
+    }
+}
@@ -0,0 +1,297 @@
+/*!
+Privileged actions going through the council or the timelock.
+
+Minting, pausing, updating the metadata and upgrading the contract are too sensitive to be left
+to a single key. They are encoded as an `AdminAction`, which only runs once a proposal of the
+council collected enough confirmations, or once the delay of an action scheduled by the owner in
+the timelock has passed. So are the owner settings which can mint tokens or grant powers, like
+roles, migration sources or relayer keys, which the owner can't change directly with a council.
+
+Only one of the council and the timelock governs the admin actions, so the council can't set a
+timelock delay with `SetTimelockDelay`, nor can the timelock set a council with `SetCouncil`.
+
+The account which proposed or scheduled an action paid for its storage, which also pays for the
+storage used by the action when it runs. The rest is refunded.
+
+While the token is paused, no tokens can be transferred, locked in escrows, streams, HTLCs, stakes
+or the bridge, released from them, or borrowed with a flash loan, and no staking rewards can be
+claimed. Denied accounts can't do any of this, nor receive tokens. Minting through an admin action
+is not affected by either.
+*/
+use near_contract_standards::fungible_token::events::FtMint;
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::{env, log, near, require, AccountId, Promise, PublicKey, StorageUsage};
+
+use crate::limits::TransferLimits;
+use crate::metadata::assert_valid_metadata;
+use crate::migration::MigrationPayout;
+use crate::roles::Role;
+use crate::{Contract, ContractExt};
+
+#[derive(Clone)]
+#[near(serializers = [borsh, json])]
+pub enum AdminAction {
+    /// Mints `amount` to `account_id`, which should be registered.
+    Mint {
+        account_id: AccountId,
+        amount: U128,
+    },
+    SetPaused {
+        paused: bool,
+    },
+    SetMetadata {
+        metadata: FungibleTokenMetadata,
+    },
+    GrantRole {
+        role: Role,
+        account_id: AccountId,
+    },
+    RevokeRole {
+        role: Role,
+        account_id: AccountId,
+    },
+    /// Replaces the council and its threshold.
+    SetCouncil {
+        members: Vec<AccountId>,
+        threshold: u32,
+    },
+    /// Deploys `code` to the contract account. The state is kept as is.
+    Upgrade {
+        code: Base64VecU8,
+    },
+    /// Sets the fee of flash loans in basis points.
+    SetFlashFee {
+        fee: u32,
+    },
+    /// Sets the maximum amount of a flash loan, 0 disabling them.
+    SetFlashMaxLoan {
+        max_loan: U128,
+    },
+    Deny {
+        account_id: AccountId,
+    },
+    Undeny {
+        account_id: AccountId,
+    },
+    /// Sets the delay of the actions scheduled afterwards, in nanoseconds.
+    SetTimelockDelay {
+        delay: U64,
+    },
+    SetOwner {
+        owner_id: AccountId,
+    },
+    /// Sets the annual interest rate in basis points.
+    SetInterestApr {
+        apr: u32,
+    },
+    /// Emits `reward_rate` staking rewards per second until the block timestamp `emission_end`.
+    SetStakingEmission {
+        reward_rate: U128,
+        emission_end: U64,
+    },
+    /// Sets the number of epochs unstaked tokens take to become available.
+    SetStakingUnbondingEpochs {
+        unbonding_epochs: U64,
+    },
+    /// Accepts `token_id` as a migration source, or updates it.
+    SetMigrationSource {
+        token_id: AccountId,
+        numerator: U128,
+        denominator: U128,
+        payout: MigrationPayout,
+    },
+    AddBridgeRelayer {
+        public_key: PublicKey,
+    },
+    RemoveBridgeRelayer {
+        public_key: PublicKey,
+    },
+    SetBridgeThreshold {
+        threshold: u32,
+    },
+    /// Replaces the transfer limits, if they were enabled at initialization.
+    SetLimits {
+        limits: TransferLimits,
+    },
+}
+
+impl Contract {
+    pub(crate) fn internal_assert_not_paused(&self) {
+        require!(!self.paused, "The token is paused");
+    }
+
+    pub(crate) fn internal_assert_not_denied(&self, account_id: &AccountId) {
+        require!(
+            !self.denylist.contains(account_id),
+            format!("@{} is denied", account_id)
+        );
+    }
+
+    /// Requires the token not to be paused, and neither `sender_id` nor `receiver_id` to be
+    /// denied.
+    pub(crate) fn internal_assert_can_move(&self, sender_id: &AccountId, receiver_id: &AccountId) {
+        self.internal_assert_not_paused();
+        self.internal_assert_not_denied(sender_id);
+        self.internal_assert_not_denied(receiver_id);
+    }
+
+    /// Rejects the actions which would combine the council and the timelock, when they are
+    /// proposed or scheduled and again when they run.
+    pub(crate) fn internal_assert_admin_action(&self, action: &AdminAction) {
+        let combines = match action {
+            AdminAction::SetTimelockDelay { .. } => self.council.is_enabled(),
+            AdminAction::SetCouncil { .. } => self.timelock.delay.is_some(),
+            _ => false,
+        };
+        require!(!combines, "The council and the timelock can't be combined");
+    }
+
+    /// Executes `action`, whose proposal or scheduled action was removed since
+    /// `initial_storage_usage`. The storage it released pays for the storage used by the action,
+    /// and the rest is refunded to `account_id`, which paid for it.
+    pub(crate) fn internal_execute_paid_admin_action(
+        &mut self,
+        action: AdminAction,
+        initial_storage_usage: StorageUsage,
+        account_id: AccountId,
+    ) {
+        self.internal_execute_admin_action(action);
+        require!(
+            env::storage_usage() <= initial_storage_usage,
+            "The deposit of the action doesn't cover its storage"
+        );
+        self.internal_refund_storage(initial_storage_usage, account_id);
+    }
+
+    fn internal_execute_admin_action(&mut self, action: AdminAction) {
+        self.internal_assert_admin_action(&action);
+        match action {
+            AdminAction::Mint { account_id, amount } => {
+                require!(amount.0 > 0, "The amount should be a positive number");
+                self.internal_check_incoming(&account_id, amount.0);
+                self.internal_assert_verified(&account_id);
+                self.internal_deposit(&account_id, amount.0);
+                FtMint {
+                    owner_id: &account_id,
+                    amount,
+                    memo: Some("mint"),
+                }
+                .emit();
+            }
+            AdminAction::SetPaused { paused } => {
+                self.paused = paused;
+                log!("{} the token", if paused { "Paused" } else { "Unpaused" });
+            }
+            AdminAction::SetMetadata { metadata } => {
+                assert_valid_metadata(&metadata);
+                self.metadata.set(&metadata);
+                log!("Updated the metadata");
+            }
+            AdminAction::GrantRole { role, account_id } => {
+                self.internal_grant_role(role, account_id);
+            }
+            AdminAction::RevokeRole { role, account_id } => {
+                self.internal_revoke_role(role, account_id);
+            }
+            AdminAction::SetCouncil { members, threshold } => {
+                self.council.internal_set_members(members, threshold);
+                log!("Updated the council");
+            }
+            AdminAction::Upgrade { code } => {
+                Promise::new(env::current_account_id()).deploy_contract(code.into());
+                log!("Upgrading the contract");
+            }
+            AdminAction::SetFlashFee { fee } => {
+                self.flash_loans.internal_set_fee(fee);
+                log!("Set the flash loan fee to {} basis points", fee);
+            }
+            AdminAction::SetFlashMaxLoan { max_loan } => {
+                self.flash_loans.max_loan = max_loan.0;
+                log!("Set the maximum flash loan to {}", max_loan.0);
+            }
+            AdminAction::Deny { account_id } => {
+                require!(
+                    self.denylist.insert(&account_id),
+                    "The account is already denied"
+                );
+                log!("Denied @{}", account_id);
+            }
+            AdminAction::Undeny { account_id } => {
+                require!(
+                    self.denylist.remove(&account_id),
+                    "The account is not denied"
+                );
+                log!("Removed @{} from the denylist", account_id);
+            }
+            AdminAction::SetTimelockDelay { delay } => {
+                self.timelock.delay = Some(delay.0);
+                log!("Set the timelock delay to {}", delay.0);
+            }
+            AdminAction::SetOwner { owner_id } => {
+                self.internal_set_owner(owner_id);
+            }
+            AdminAction::SetInterestApr { apr } => {
+                self.internal_set_apr(apr);
+            }
+            AdminAction::SetStakingEmission {
+                reward_rate,
+                emission_end,
+            } => {
+                self.internal_set_staking_emission(reward_rate.0, emission_end.0);
+                log!(
+                    "Set the staking emission to {} per second until {}",
+                    reward_rate.0,
+                    emission_end.0
+                );
+            }
+            AdminAction::SetStakingUnbondingEpochs { unbonding_epochs } => {
+                self.staking.unbonding_epochs = unbonding_epochs.0;
+                log!(
+                    "Set the staking unbonding period to {} epochs",
+                    unbonding_epochs.0
+                );
+            }
+            AdminAction::SetMigrationSource {
+                token_id,
+                numerator,
+                denominator,
+                payout,
+            } => {
+                self.migration.internal_set_source(
+                    token_id.clone(),
+                    numerator,
+                    denominator,
+                    payout,
+                );
+                log!("Set @{} as a migration source", token_id);
+            }
+            AdminAction::AddBridgeRelayer { public_key } => {
+                self.bridge.internal_add_relayer(public_key);
+                log!("Added a bridge relayer");
+            }
+            AdminAction::RemoveBridgeRelayer { public_key } => {
+                self.bridge.internal_remove_relayer(&public_key);
+                log!("Removed a bridge relayer");
+            }
+            AdminAction::SetBridgeThreshold { threshold } => {
+                self.bridge.internal_set_threshold(threshold);
+                log!("Set the bridge threshold to {}", threshold);
+            }
+            AdminAction::SetLimits { limits } => {
+                self.limiter.internal_set_limits(limits);
+            }
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_denied(&self, account_id: AccountId) -> bool {
+        self.denylist.contains(&account_id)
+    }
+}
@@ -0,0 +1,203 @@
+/*!
+Allowlist of the contracts `ft_transfer_call` can send tokens to.
+
+The owner manages the list and switches the restriction on and off. While it is enabled,
+`ft_transfer_call` and `ft_transfer_call_with_gas` fail before any balance moves unless the
+receiver is on the list. Plain `ft_transfer`s are not restricted. The owner pays for the storage
+of an entry with the deposit attached to `allowlist_add`, and gets it back on `allowlist_remove`.
+*/
+use near_sdk::collections::UnorderedSet;
+use near_sdk::json_types::U64;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, IntoStorageKey};
+
+use crate::{Contract, ContractExt};
+
+#[near(event_json(standard = "ft-allowlist"))]
+pub enum AllowlistEvent {
+    #[event_version("1.0.0")]
+    ReceiverAdded { account_id: AccountId },
+    #[event_version("1.0.0")]
+    ReceiverRemoved { account_id: AccountId },
+    #[event_version("1.0.0")]
+    AllowlistEnabled { enabled: bool },
+}
+
+#[near]
+pub struct ReceiverAllowlist {
+    /// Whether `ft_transfer_call` is restricted to the receivers on the list.
+    pub enabled: bool,
+
+    pub receivers: UnorderedSet<AccountId>,
+}
+
+impl ReceiverAllowlist {
+    pub fn new<S>(prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            enabled: false,
+            receivers: UnorderedSet::new(prefix),
+        }
+    }
+
+    /// Panics if the restriction is enabled and `receiver_id` is not on the list.
+    pub(crate) fn internal_assert_allowed(&self, receiver_id: &AccountId) {
+        require!(
+            !self.enabled || self.receivers.contains(receiver_id),
+            format!("@{} is not an approved receiver", receiver_id)
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    /// Approves `account_id` as a receiver of `ft_transfer_call`. Can only be called by the owner.
+    /// The attached deposit must cover the storage of the entry, the excess is refunded.
+    #[payable]
+    pub fn allowlist_add(&mut self, account_id: AccountId) {
+        let initial_storage_usage = env::storage_usage();
+        self.assert_owner();
+        require!(
+            self.receiver_allowlist.receivers.insert(&account_id),
+            "The receiver is already approved"
+        );
+        self.internal_charge_storage(initial_storage_usage);
+        AllowlistEvent::ReceiverAdded { account_id }.emit();
+    }
+
+    /// Removes `account_id` from the approved receivers. Can only be called by the owner.
+    #[payable]
+    pub fn allowlist_remove(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let initial_storage_usage = env::storage_usage();
+        require!(
+            self.receiver_allowlist.receivers.remove(&account_id),
+            "The receiver is not approved"
+        );
+        self.internal_refund_storage(initial_storage_usage, env::predecessor_account_id());
+        AllowlistEvent::ReceiverRemoved { account_id }.emit();
+    }
+
+    /// Switches the restriction of `ft_transfer_call` to the approved receivers on or off. Can
+    /// only be called by the owner.
+    #[payable]
+    pub fn allowlist_set_enabled(&mut self, enabled: bool) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(
+            self.receiver_allowlist.enabled != enabled,
+            "The allowlist is already in this state"
+        );
+        self.receiver_allowlist.enabled = enabled;
+        AllowlistEvent::AllowlistEnabled { enabled }.emit();
+    }
+
+    pub fn allowlist_is_enabled(&self) -> bool {
+        self.receiver_allowlist.enabled
+    }
+
+    pub fn allowlist_contains(&self, account_id: AccountId) -> bool {
+        self.receiver_allowlist.receivers.contains(&account_id)
+    }
+
+    /// Returns the approved receivers, whether or not the restriction is enabled.
+    pub fn allowlist_receivers(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<AccountId> {
+        self.receiver_allowlist
+            .receivers
+            .iter()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.unwrap_or(10) as usize)
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const AMOUNT: u128 = 1_000;
+
+    /// Registers user1 and user2, approves user1 and enables the allowlist.
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        for account_id in [user1(), user2()].iter() {
+            testing_env!(context
+                .predecessor_account_id(account_id.clone())
+                .attached_deposit(contract.storage_balance_bounds().min)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.allowlist_add(user1());
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.allowlist_set_enabled(true);
+        (contract, context)
+    }
+
+    #[test]
+    fn test_transfer_call_to_approved_receiver() {
+        let (mut contract, _) = setup();
+
+        contract.ft_transfer_call(user1(), AMOUNT.into(), None, "".to_string());
+
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+        assert_eq!(contract.allowlist_receivers(None, None), vec![user1()]);
+    }
+
+    #[should_panic(expected = "is not an approved receiver")]
+    #[test]
+    fn test_transfer_call_panics_on_unapproved_receiver() {
+        let (mut contract, _) = setup();
+
+        contract.ft_transfer_call(user2(), AMOUNT.into(), None, "".to_string());
+    }
+
+    #[test]
+    fn test_remove_and_disable() {
+        let (mut contract, _) = setup();
+
+        contract.allowlist_remove(user1());
+        assert!(!contract.allowlist_contains(user1()));
+        assert!(get_logs()
+            .last()
+            .unwrap()
+            .contains(r#""event":"receiver_removed""#));
+
+        contract.allowlist_set_enabled(false);
+        assert!(!contract.allowlist_is_enabled());
+        contract.ft_transfer_call(user2(), AMOUNT.into(), None, "".to_string());
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - AMOUNT);
+    }
+
+    #[should_panic(expected = "Only the owner can call this method")]
+    #[test]
+    fn test_add_panics_on_non_owner() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.allowlist_add(user2());
+    }
+}
@@ -0,0 +1,621 @@
+/*!
+Lock/mint bridge adapter.
+
+`bridge_out` takes tokens out of the caller's balance and emits a `bridge_out` event, which the
+relayers pick up to deliver the tokens on the destination chain. `bridge_in` credits tokens sent
+from another chain, once enough of the registered relayers signed the transfer.
+
+The bridge works in one of two modes, chosen at initialization:
+  - `Lock`: tokens bridged out are held by the contract and released again by `bridge_in`. They
+    stay in the total supply, and `bridge_in` can't release more than is locked.
+  - `Burn`: tokens bridged out are burned and `bridge_in` mints new ones.
+
+Relayers sign the Borsh serialization of the contract account id followed by the
+`BridgeInMessage` with their ed25519 keys. Each message is identified by its source chain and
+nonce, and can only be processed once. The owner manages the relayer keys and the number of
+signatures required.
+*/
+use near_contract_standards::fungible_token::events::{FtBurn, FtMint};
+use near_sdk::borsh;
+use near_sdk::collections::{LookupSet, UnorderedSet};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::{
+    assert_one_yocto, env, near, require, AccountId, CurveType, IntoStorageKey, PublicKey,
+};
+use std::convert::TryInto;
+
+use crate::{Contract, ContractExt};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub enum BridgeMode {
+    Lock,
+    Burn,
+}
+
+/// Transfer from another chain, as signed by the relayers.
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct BridgeInMessage {
+    pub source_chain: String,
+    /// Nonce of the transfer on the source chain.
+    pub nonce: U64,
+    /// Sender address on the source chain.
+    pub sender: String,
+    pub recipient: AccountId,
+    pub amount: U128,
+}
+
+impl BridgeInMessage {
+    /// Bytes the relayers sign for this message to be accepted by the bridge at `bridge_id`.
+    pub fn signing_payload(&self, bridge_id: &AccountId) -> Vec<u8> {
+        borsh::to_vec(&(bridge_id, self)).unwrap_or_else(|_| env::abort())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [json])]
+pub struct RelayerSignature {
+    pub public_key: PublicKey,
+    pub signature: Base64VecU8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [json])]
+pub struct BridgeInProof {
+    pub message: BridgeInMessage,
+    pub signatures: Vec<RelayerSignature>,
+}
+
+#[near(event_json(standard = "ft-bridge"))]
+pub enum BridgeEvent {
+    #[event_version("1.0.0")]
+    BridgeOut {
+        nonce: U64,
+        sender_id: AccountId,
+        amount: U128,
+        destination_chain: String,
+        recipient: String,
+    },
+    #[event_version("1.0.0")]
+    BridgeIn {
+        source_chain: String,
+        nonce: U64,
+        sender: String,
+        recipient: AccountId,
+        amount: U128,
+    },
+}
+
+#[near]
+pub struct Bridge {
+    /// The bridge is disabled when not set.
+    pub mode: Option<BridgeMode>,
+
+    /// Public keys of the relayers.
+    pub relayers: UnorderedSet<PublicKey>,
+
+    /// Number of relayer signatures required by `bridge_in`.
+    pub threshold: u32,
+
+    /// Nonce assigned to the next `bridge_out`.
+    pub next_nonce: u64,
+
+    /// (source chain, nonce) of the processed `bridge_in` messages.
+    pub processed: LookupSet<(String, u64)>,
+
+    /// Amount held by the contract in the `Lock` mode.
+    pub locked: u128,
+}
+
+impl Bridge {
+    pub fn new<R, P>(relayers_prefix: R, processed_prefix: P, mode: Option<BridgeMode>) -> Self
+    where
+        R: IntoStorageKey,
+        P: IntoStorageKey,
+    {
+        Self {
+            mode,
+            relayers: UnorderedSet::new(relayers_prefix),
+            threshold: 0,
+            next_nonce: 0,
+            processed: LookupSet::new(processed_prefix),
+            locked: 0,
+        }
+    }
+
+    pub(crate) fn internal_add_relayer(&mut self, public_key: PublicKey) {
+        require!(
+            public_key.curve_type() == CurveType::ED25519,
+            "Relayer keys should be ed25519"
+        );
+        require!(
+            self.relayers.insert(&public_key),
+            "The key is already a relayer"
+        );
+    }
+
+    pub(crate) fn internal_remove_relayer(&mut self, public_key: &PublicKey) {
+        require!(self.relayers.remove(public_key), "The key is not a relayer");
+        require!(
+            self.relayers.len() >= u64::from(self.threshold),
+            "Can't have fewer relayers than the threshold"
+        );
+    }
+
+    pub(crate) fn internal_set_threshold(&mut self, threshold: u32) {
+        require!(threshold > 0, "The threshold should be a positive number");
+        require!(
+            u64::from(threshold) <= self.relayers.len(),
+            "The threshold can't exceed the number of relayers"
+        );
+        self.threshold = threshold;
+    }
+
+    fn internal_unwrap_mode(&self) -> BridgeMode {
+        self.mode
+            .unwrap_or_else(|| env::panic_str("The bridge is disabled"))
+    }
+
+    /// Panics unless `proof` is signed by at least `threshold` distinct relayers.
+    fn internal_verify(&self, proof: &BridgeInProof) {
+        require!(self.threshold > 0, "The bridge threshold is not set");
+        let payload = proof.message.signing_payload(&env::current_account_id());
+        let mut signers: Vec<&PublicKey> = Vec::with_capacity(proof.signatures.len());
+        for RelayerSignature {
+            public_key,
+            signature,
+        } in &proof.signatures
+        {
+            require!(
+                self.relayers.contains(public_key),
+                format!("{:?} is not a relayer", public_key)
+            );
+            require!(
+                !signers.contains(&public_key),
+                "Duplicate relayer signature"
+            );
+            require!(
+                public_key.curve_type() == CurveType::ED25519,
+                "Relayer keys should be ed25519"
+            );
+            let key: &[u8; 32] = public_key.as_bytes()[1..].try_into().unwrap();
+            let signature: &[u8; 64] = signature
+                .0
+                .as_slice()
+                .try_into()
+                .unwrap_or_else(|_| env::panic_str("Invalid signature length"));
+            require!(
+                env::ed25519_verify(signature, &payload, key),
+                "Invalid relayer signature"
+            );
+            signers.push(public_key);
+        }
+        require!(
+            signers.len() >= self.threshold as usize,
+            format!(
+                "Requires at least {} relayer signatures, got {}",
+                self.threshold,
+                signers.len()
+            )
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    /// Takes `amount` of the predecessor's tokens out to `recipient` on `destination_chain`.
+    /// Returns the nonce of the transfer.
+    #[payable]
+    pub fn bridge_out(
+        &mut self,
+        amount: U128,
+        destination_chain: String,
+        recipient: String,
+    ) -> U64 {
+        assert_one_yocto();
+        let mode = self.bridge.internal_unwrap_mode();
+        require!(
+            !destination_chain.is_empty() && !recipient.is_empty(),
+            "The destination chain and recipient should be set"
+        );
+        let sender_id = env::predecessor_account_id();
+        self.internal_assert_not_paused();
+        self.internal_assert_not_denied(&sender_id);
+        self.internal_check_outgoing(&sender_id, amount.0);
+        match mode {
+            BridgeMode::Lock => {
+                self.internal_lock(&sender_id, amount.0);
+                self.bridge.locked += amount.0;
+            }
+            BridgeMode::Burn => {
+                require!(amount.0 > 0, "The amount should be a positive number");
+                self.internal_withdraw(&sender_id, amount.0);
+                FtBurn {
+                    owner_id: &sender_id,
+                    amount,
+                    memo: Some("bridge out"),
+                }
+                .emit();
+            }
+        }
+
+        let nonce = U64(self.bridge.next_nonce);
+        self.bridge.next_nonce += 1;
+        BridgeEvent::BridgeOut {
+            nonce,
+            sender_id,
+            amount,
+            destination_chain,
+            recipient,
+        }
+        .emit();
+
+        nonce
+    }
+
+    /// Credits the tokens of a transfer from another chain to its recipient, who should be
+    /// registered. The attached deposit must cover the storage used to mark the transfer as
+    /// processed, the excess is refunded.
+    #[payable]
+    pub fn bridge_in(&mut self, proof: BridgeInProof) {
+        let initial_storage_usage = env::storage_usage();
+        let mode = self.bridge.internal_unwrap_mode();
+        self.bridge.internal_verify(&proof);
+        let BridgeInMessage {
+            source_chain,
+            nonce,
+            sender,
+            recipient,
+            amount,
+        } = proof.message;
+        require!(
+            self.bridge
+                .processed
+                .insert(&(source_chain.clone(), nonce.0)),
+            "The transfer was already processed"
+        );
+        self.internal_assert_not_paused();
+        self.internal_assert_not_denied(&recipient);
+        self.internal_assert_verified(&recipient);
+        self.internal_check_incoming(&recipient, amount.0);
+        match mode {
+            BridgeMode::Lock => {
+                self.bridge.locked = self
+                    .bridge
+                    .locked
+                    .checked_sub(amount.0)
+                    .unwrap_or_else(|| env::panic_str("Not enough tokens are locked"));
+                self.internal_unlock(&recipient, amount.0);
+            }
+            BridgeMode::Burn => {
+                self.internal_deposit(&recipient, amount.0);
+                FtMint {
+                    owner_id: &recipient,
+                    amount,
+                    memo: Some("bridge in"),
+                }
+                .emit();
+            }
+        }
+        self.internal_charge_storage(initial_storage_usage);
+
+        BridgeEvent::BridgeIn {
+            source_chain,
+            nonce,
+            sender,
+            recipient,
+            amount,
+        }
+        .emit();
+    }
+
+    /// Registers a relayer key. Can only be called by the owner, without a council or timelock.
+    /// The attached deposit must cover the storage of the key, the excess is refunded.
+    #[payable]
+    pub fn bridge_add_relayer(&mut self, public_key: PublicKey) {
+        let initial_storage_usage = env::storage_usage();
+        self.assert_owner_setting();
+        self.bridge.internal_add_relayer(public_key);
+        self.internal_charge_storage(initial_storage_usage);
+    }
+
+    /// Removes a relayer key. Can only be called by the owner, without a council or timelock, and
+    /// can't leave fewer relayers than the threshold.
+    #[payable]
+    pub fn bridge_remove_relayer(&mut self, public_key: PublicKey) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        let initial_storage_usage = env::storage_usage();
+        self.bridge.internal_remove_relayer(&public_key);
+        self.internal_refund_storage(initial_storage_usage, env::predecessor_account_id());
+    }
+
+    /// Sets the number of relayer signatures required by `bridge_in`. Can only be called by the
+    /// owner, without a council or timelock.
+    #[payable]
+    pub fn bridge_set_threshold(&mut self, threshold: u32) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        self.bridge.internal_set_threshold(threshold);
+    }
+
+    pub fn bridge_mode(&self) -> Option<BridgeMode> {
+        self.bridge.mode
+    }
+
+    pub fn bridge_relayers(&self) -> Vec<PublicKey> {
+        self.bridge.relayers.to_vec()
+    }
+
+    pub fn bridge_threshold(&self) -> u32 {
+        self.bridge.threshold
+    }
+
+    pub fn bridge_is_processed(&self, source_chain: String, nonce: U64) -> bool {
+        self.bridge.processed.contains(&(source_chain, nonce.0))
+    }
+
+    /// Amount held by the contract for tokens bridged out in the `Lock` mode.
+    pub fn bridge_locked(&self) -> U128 {
+        self.bridge.locked.into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::admin::AdminAction;
+    use crate::tests::{owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const AMOUNT: u128 = 1_000;
+    const CHAIN: &str = "ethereum";
+
+    fn relayer(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn relayer_key(seed: u8) -> PublicKey {
+        PublicKey::from_parts(
+            CurveType::ED25519,
+            relayer(seed).verifying_key().to_bytes().to_vec(),
+        )
+        .unwrap()
+    }
+
+    /// Bridge with relayers 1, 2 and 3 and a threshold of 2.
+    fn setup(mode: BridgeMode) -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            bridge_mode: Some(mode),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        for seed in 1..=3 {
+            contract.bridge_add_relayer(relayer_key(seed));
+        }
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.bridge_set_threshold(2);
+
+        (contract, context)
+    }
+
+    fn proof(nonce: u64, amount: u128, signers: &[u8]) -> BridgeInProof {
+        let message = BridgeInMessage {
+            source_chain: CHAIN.to_string(),
+            nonce: U64(nonce),
+            sender: "0xabc".to_string(),
+            recipient: user1(),
+            amount: U128(amount),
+        };
+        let payload = message.signing_payload(&crate::tests::current());
+        let signatures = signers
+            .iter()
+            .map(|&seed| RelayerSignature {
+                public_key: relayer_key(seed),
+                signature: relayer(seed).sign(&payload).to_bytes().to_vec().into(),
+            })
+            .collect();
+        BridgeInProof {
+            message,
+            signatures,
+        }
+    }
+
+    fn bridge_out(contract: &mut Contract, context: &mut VMContextBuilder, amount: u128) {
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.bridge_out(amount.into(), CHAIN.to_string(), "0xdef".to_string());
+    }
+
+    fn bridge_in(contract: &mut Contract, context: &mut VMContextBuilder, proof: BridgeInProof) {
+        testing_env!(context
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.bridge_in(proof);
+    }
+
+    #[should_panic(expected = "@bob is denied")]
+    #[test]
+    fn test_bridge_out_panics_on_denied_sender() {
+        let (mut contract, mut context) = setup(BridgeMode::Lock);
+
+        contract.denylist.insert(&owner());
+        bridge_out(&mut contract, &mut context, AMOUNT);
+    }
+
+    #[test]
+    fn test_lock_mode() {
+        let (mut contract, mut context) = setup(BridgeMode::Lock);
+
+        bridge_out(&mut contract, &mut context, AMOUNT);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - AMOUNT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.bridge_locked().0, AMOUNT);
+
+        bridge_in(&mut contract, &mut context, proof(0, AMOUNT, &[1, 3]));
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.bridge_locked().0, 0);
+        assert!(contract.bridge_is_processed(CHAIN.to_string(), U64(0)));
+    }
+
+    #[should_panic(expected = "Not enough tokens are locked")]
+    #[test]
+    fn test_lock_mode_panics_on_releasing_more_than_locked() {
+        let (mut contract, mut context) = setup(BridgeMode::Lock);
+
+        bridge_out(&mut contract, &mut context, AMOUNT);
+        bridge_in(&mut contract, &mut context, proof(0, AMOUNT + 1, &[1, 2]));
+    }
+
+    #[test]
+    fn test_burn_mode() {
+        let (mut contract, mut context) = setup(BridgeMode::Burn);
+
+        bridge_out(&mut contract, &mut context, AMOUNT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY - AMOUNT);
+
+        bridge_in(&mut contract, &mut context, proof(0, 2 * AMOUNT, &[2, 3]));
+        assert_eq!(contract.ft_balance_of(user1()).0, 2 * AMOUNT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + AMOUNT);
+    }
+
+    #[should_panic(expected = "The transfer was already processed")]
+    #[test]
+    fn test_bridge_in_panics_on_replay() {
+        let (mut contract, mut context) = setup(BridgeMode::Burn);
+
+        bridge_in(&mut contract, &mut context, proof(7, AMOUNT, &[1, 2]));
+        bridge_in(&mut contract, &mut context, proof(7, AMOUNT, &[2, 3]));
+    }
+
+    #[should_panic(expected = "Requires at least 2 relayer signatures, got 1")]
+    #[test]
+    fn test_bridge_in_panics_below_threshold() {
+        let (mut contract, mut context) = setup(BridgeMode::Burn);
+
+        bridge_in(&mut contract, &mut context, proof(0, AMOUNT, &[1]));
+    }
+
+    #[should_panic(expected = "Duplicate relayer signature")]
+    #[test]
+    fn test_bridge_in_panics_on_duplicate_signer() {
+        let (mut contract, mut context) = setup(BridgeMode::Burn);
+
+        bridge_in(&mut contract, &mut context, proof(0, AMOUNT, &[1, 1]));
+    }
+
+    #[should_panic(expected = "is not a relayer")]
+    #[test]
+    fn test_bridge_in_panics_on_unknown_signer() {
+        let (mut contract, mut context) = setup(BridgeMode::Burn);
+
+        bridge_in(&mut contract, &mut context, proof(0, AMOUNT, &[1, 4]));
+    }
+
+    #[should_panic(expected = "Invalid relayer signature")]
+    #[test]
+    fn test_bridge_in_panics_on_tampered_message() {
+        let (mut contract, mut context) = setup(BridgeMode::Burn);
+
+        let mut proof = proof(0, AMOUNT, &[1, 2]);
+        proof.message.amount = U128(AMOUNT * 1_000);
+        bridge_in(&mut contract, &mut context, proof);
+    }
+
+    #[should_panic(expected = "Only the owner can call this method")]
+    #[test]
+    fn test_add_relayer_panics_on_non_owner() {
+        let (mut contract, mut context) = setup(BridgeMode::Burn);
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.bridge_add_relayer(relayer_key(4));
+    }
+
+    #[should_panic(expected = "Can't have fewer relayers than the threshold")]
+    #[test]
+    fn test_remove_relayer_panics_below_threshold() {
+        let (mut contract, mut context) = setup(BridgeMode::Burn);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.bridge_remove_relayer(relayer_key(1));
+        contract.bridge_remove_relayer(relayer_key(2));
+    }
+
+    #[test]
+    fn test_relayers_change_through_timelock() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            bridge_mode: Some(BridgeMode::Burn),
+            timelock_delay: Some(U64(0)),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        let id = contract.schedule(AdminAction::AddBridgeRelayer {
+            public_key: relayer_key(1),
+        });
+        contract.execute(id);
+        assert_eq!(contract.bridge_relayers(), vec![relayer_key(1)]);
+
+        let id = contract.schedule(AdminAction::RemoveBridgeRelayer {
+            public_key: relayer_key(1),
+        });
+        contract.execute(id);
+        assert!(contract.bridge_relayers().is_empty());
+    }
+
+    #[should_panic(expected = "This setting is changed through the timelock")]
+    #[test]
+    fn test_remove_relayer_panics_with_timelock() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            bridge_mode: Some(BridgeMode::Burn),
+            timelock_delay: Some(U64(0)),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.bridge_remove_relayer(relayer_key(1));
+    }
+
+    #[should_panic(expected = "The bridge is disabled")]
+    #[test]
+    fn test_bridge_out_panics_when_disabled() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        bridge_out(&mut contract, &mut context, AMOUNT);
+    }
+}
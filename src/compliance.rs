@@ -0,0 +1,195 @@
+/*!
+Forced transfers for regulated assets.
+
+When switched on at initialization with `new_with_config` and `force_transfer`, accounts with the
+`Compliance` role can move tokens between any two accounts with `ft_force_transfer`, for example
+to execute a court order. Forced transfers ignore the pause, the denylist and the transfer limits,
+and require a reason code and a memo, which are part of the `ft_force_transfer` event emitted
+along with the usual `ft_transfer` event.
+
+The setting can't be changed after initialization, so holders of a deployment where
+`ft_force_transfer_enabled` is false know their balances can't be moved without them.
+*/
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId};
+
+use crate::roles::Role;
+use crate::{Contract, ContractExt};
+
+#[near(event_json(standard = "ft-compliance"))]
+pub enum ComplianceEvent {
+    #[event_version("1.0.0")]
+    FtForceTransfer {
+        compliance_id: AccountId,
+        old_owner_id: AccountId,
+        new_owner_id: AccountId,
+        amount: U128,
+        reason_code: String,
+        memo: String,
+    },
+}
+
+#[near]
+impl Contract {
+    /// Moves `amount` from `sender_id` to `receiver_id` regardless of the state of either account.
+    /// Can only be called by an account with the `Compliance` role, when forced transfers are
+    /// enabled.
+    #[payable]
+    pub fn ft_force_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        reason_code: String,
+        memo: String,
+    ) {
+        assert_one_yocto();
+        require!(self.force_transfer_enabled, "Forced transfers are disabled");
+        self.assert_role(Role::Compliance);
+        require!(
+            !reason_code.is_empty(),
+            "The reason code should not be empty"
+        );
+        require!(!memo.is_empty(), "The memo should not be empty");
+        require!(
+            sender_id != receiver_id,
+            "Sender and receiver should be different"
+        );
+        require!(amount.0 > 0, "The amount should be a positive number");
+        self.internal_assert_verified(&receiver_id);
+        self.internal_move(&sender_id, &receiver_id, amount.0, Some(memo.clone()));
+
+        ComplianceEvent::FtForceTransfer {
+            compliance_id: env::predecessor_account_id(),
+            old_owner_id: sender_id,
+            new_owner_id: receiver_id,
+            amount,
+            reason_code,
+            memo,
+        }
+        .emit();
+    }
+
+    pub fn ft_force_transfer_enabled(&self) -> bool {
+        self.force_transfer_enabled
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::admin::AdminAction;
+    use crate::tests::{owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    /// Registers user1, and grants the compliance role to user2 and denies the owner through the
+    /// timelock.
+    fn setup(force_transfer: bool) -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            force_transfer,
+            timelock_delay: Some(0.into()),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        for action in [
+            AdminAction::GrantRole {
+                role: Role::Compliance,
+                account_id: user2(),
+            },
+            AdminAction::Deny {
+                account_id: owner(),
+            },
+        ]
+        .iter()
+        {
+            let id = contract.schedule(action.clone());
+            contract.execute(id);
+        }
+
+        testing_env!(context
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        (contract, context)
+    }
+
+    #[test]
+    fn test_force_transfer_from_denied_account() {
+        let (mut contract, _) = setup(true);
+        assert!(contract.ft_force_transfer_enabled());
+
+        contract.ft_force_transfer(
+            owner(),
+            user1(),
+            U128(1_000),
+            "COURT-ORDER".to_string(),
+            "case 19".to_string(),
+        );
+        assert_eq!(contract.ft_balance_of(user1()).0, 1_000);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - 1_000);
+
+        let logs = get_logs();
+        assert!(logs[logs.len() - 2].contains(r#""event":"ft_transfer""#));
+        assert!(logs[logs.len() - 1].contains(r#""event":"ft_force_transfer""#));
+        assert!(logs[logs.len() - 1].contains(r#""reason_code":"COURT-ORDER""#));
+    }
+
+    #[should_panic(expected = "Forced transfers are disabled")]
+    #[test]
+    fn test_force_transfer_panics_when_disabled() {
+        let (mut contract, _) = setup(false);
+        assert!(!contract.ft_force_transfer_enabled());
+
+        contract.ft_force_transfer(
+            owner(),
+            user1(),
+            U128(1_000),
+            "COURT-ORDER".to_string(),
+            "case 19".to_string(),
+        );
+    }
+
+    #[should_panic(expected = "Requires the Compliance role")]
+    #[test]
+    fn test_force_transfer_panics_without_role() {
+        let (mut contract, mut context) = setup(true);
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_force_transfer(
+            owner(),
+            user1(),
+            U128(1_000),
+            "COURT-ORDER".to_string(),
+            "case 19".to_string(),
+        );
+    }
+
+    #[should_panic(expected = "The reason code should not be empty")]
+    #[test]
+    fn test_force_transfer_panics_without_reason_code() {
+        let (mut contract, _) = setup(true);
+
+        contract.ft_force_transfer(
+            owner(),
+            user1(),
+            U128(1_000),
+            "".to_string(),
+            "case 19".to_string(),
+        );
+    }
+}
@@ -0,0 +1,413 @@
+/*!
+M-of-N council approving the admin actions.
+
+A member proposes an `AdminAction` with `council_propose`, which counts as its own confirmation.
+The action runs as soon as `threshold` members confirmed it, and the proposal is removed. Only the
+confirmations of the current members count, so changing the council can make pending proposals
+need more confirmations.
+
+Proposals expire after a week, after which they can't be confirmed anymore. The proposer can
+cancel its proposal at any time, and anybody can remove an expired one. The proposer pays for the
+storage of the proposal with the deposit attached to `council_propose`, which also pays for the
+storage used by the action. The proposer gets the rest back when the proposal is executed, or all
+of it when the proposal is removed.
+
+The council is set at initialization with `new_with_config`, and changed by the council itself.
+Without a council, admin actions are disabled.
+*/
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::U64;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, IntoStorageKey, Timestamp};
+
+use crate::admin::AdminAction;
+use crate::{Contract, ContractExt};
+
+const PROPOSAL_PERIOD: Timestamp = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+#[near(serializers = [json])]
+pub struct CouncilConfig {
+    pub members: Vec<AccountId>,
+    /// Number of confirmations required to execute a proposal.
+    pub threshold: u32,
+}
+
+#[derive(Clone)]
+#[near(serializers = [borsh, json])]
+pub struct Proposal {
+    pub id: U64,
+    pub proposer_id: AccountId,
+    pub action: AdminAction,
+    /// Members which confirmed the proposal, including the proposer.
+    pub confirmations: Vec<AccountId>,
+    /// Block timestamp in nanoseconds after which the proposal can't be confirmed.
+    pub expires_at: U64,
+}
+
+#[near(event_json(standard = "ft-council"))]
+pub enum CouncilEvent {
+    #[event_version("1.0.0")]
+    ProposalCreate {
+        id: U64,
+        proposer_id: AccountId,
+        expires_at: U64,
+    },
+    #[event_version("1.0.0")]
+    ProposalConfirm { id: U64, account_id: AccountId },
+    #[event_version("1.0.0")]
+    ProposalExecute { id: U64 },
+    #[event_version("1.0.0")]
+    ProposalCancel { id: U64 },
+}
+
+#[near]
+pub struct Council {
+    pub members: Vec<AccountId>,
+    pub threshold: u32,
+
+    /// Id assigned to the next proposal.
+    pub next_id: u64,
+
+    /// Proposal id -> pending proposal.
+    pub proposals: UnorderedMap<u64, Proposal>,
+}
+
+impl Council {
+    pub fn new<S>(prefix: S, config: Option<CouncilConfig>) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        let mut this = Self {
+            members: vec![],
+            threshold: 0,
+            next_id: 0,
+            proposals: UnorderedMap::new(prefix),
+        };
+        if let Some(config) = config {
+            this.internal_set_members(config.members, config.threshold);
+        }
+        this
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.members.is_empty()
+    }
+
+    pub(crate) fn internal_set_members(&mut self, members: Vec<AccountId>, threshold: u32) {
+        let mut unique = std::collections::HashSet::new();
+        require!(
+            members.iter().all(|member| unique.insert(member)),
+            "Duplicate council member"
+        );
+        require!(
+            threshold > 0 && threshold as usize <= members.len(),
+            "The threshold should be between 1 and the number of members"
+        );
+        self.members = members;
+        self.threshold = threshold;
+    }
+
+    fn assert_member(&self) -> AccountId {
+        let account_id = env::predecessor_account_id();
+        require!(
+            self.members.contains(&account_id),
+            "Only council members can call this method"
+        );
+        account_id
+    }
+
+    fn internal_unwrap_proposal(&self, id: u64) -> Proposal {
+        self.proposals
+            .get(&id)
+            .unwrap_or_else(|| env::panic_str(format!("Proposal {} doesn't exist", id).as_str()))
+    }
+
+    fn is_approved(&self, proposal: &Proposal) -> bool {
+        let confirmations = proposal
+            .confirmations
+            .iter()
+            .filter(|account_id| self.members.contains(account_id))
+            .count();
+        confirmations >= self.threshold as usize
+    }
+}
+
+impl Contract {
+    /// Executes `proposal` if it has enough confirmations. Returns whether it was executed.
+    fn internal_try_execute_proposal(&mut self, proposal: Proposal) -> bool {
+        if !self.council.is_approved(&proposal) {
+            self.council.proposals.insert(&proposal.id.0, &proposal);
+            return false;
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.council.proposals.remove(&proposal.id.0);
+        CouncilEvent::ProposalExecute { id: proposal.id }.emit();
+        self.internal_execute_paid_admin_action(
+            proposal.action,
+            initial_storage_usage,
+            proposal.proposer_id,
+        );
+        true
+    }
+}
+
+#[near]
+impl Contract {
+    /// Proposes `action` to the council, confirming it for the predecessor, which should be a
+    /// member. The attached deposit must cover the storage of the proposal, the excess is
+    /// refunded. Returns the id of the proposal, which is executed right away if the predecessor's
+    /// confirmation is enough.
+    #[payable]
+    pub fn council_propose(&mut self, action: AdminAction) -> U64 {
+        let initial_storage_usage = env::storage_usage();
+        let proposer_id = self.council.assert_member();
+        self.internal_assert_admin_action(&action);
+        let proposal = Proposal {
+            id: U64(self.council.next_id),
+            proposer_id: proposer_id.clone(),
+            action,
+            confirmations: vec![proposer_id.clone()],
+            expires_at: U64(env::block_timestamp() + PROPOSAL_PERIOD),
+        };
+        self.council.next_id += 1;
+        self.council.proposals.insert(&proposal.id.0, &proposal);
+        self.internal_charge_storage(initial_storage_usage);
+
+        CouncilEvent::ProposalCreate {
+            id: proposal.id,
+            proposer_id,
+            expires_at: proposal.expires_at,
+        }
+        .emit();
+
+        let id = proposal.id;
+        self.internal_try_execute_proposal(proposal);
+        id
+    }
+
+    /// Confirms the proposal `id` for the predecessor, which should be a member. Returns whether
+    /// the proposal was executed.
+    #[payable]
+    pub fn council_confirm(&mut self, id: U64) -> bool {
+        assert_one_yocto();
+        let account_id = self.council.assert_member();
+        let mut proposal = self.council.internal_unwrap_proposal(id.0);
+        require!(
+            env::block_timestamp() <= proposal.expires_at.0,
+            "The proposal has expired"
+        );
+        require!(
+            !proposal.confirmations.contains(&account_id),
+            "The proposal is already confirmed by the account"
+        );
+        proposal.confirmations.push(account_id.clone());
+
+        CouncilEvent::ProposalConfirm { id, account_id }.emit();
+        self.internal_try_execute_proposal(proposal)
+    }
+
+    /// Removes the proposal `id`. Can be called by the proposer, or by anybody once the proposal
+    /// has expired.
+    #[payable]
+    pub fn council_cancel(&mut self, id: U64) {
+        assert_one_yocto();
+        let proposal = self.council.internal_unwrap_proposal(id.0);
+        require!(
+            env::predecessor_account_id() == proposal.proposer_id
+                || env::block_timestamp() > proposal.expires_at.0,
+            "Only the proposer can cancel a proposal before it expires"
+        );
+        let initial_storage_usage = env::storage_usage();
+        self.council.proposals.remove(&id.0);
+        self.internal_refund_storage(initial_storage_usage, proposal.proposer_id);
+
+        CouncilEvent::ProposalCancel { id }.emit();
+    }
+
+    pub fn council_members(&self) -> Vec<AccountId> {
+        self.council.members.clone()
+    }
+
+    pub fn council_threshold(&self) -> u32 {
+        self.council.threshold
+    }
+
+    pub fn council_proposal(&self, id: U64) -> Option<Proposal> {
+        self.council.proposals.get(&id.0)
+    }
+
+    /// Returns the pending proposals, expired ones included.
+    pub fn council_proposals(&self, from_index: Option<U64>, limit: Option<u32>) -> Vec<Proposal> {
+        self.council
+            .proposals
+            .values()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.unwrap_or(10) as usize)
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::roles::Role;
+    use crate::tests::{owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    /// Sets up a council of the owner, user1 and user2 with a threshold of 2, and proposes to mint
+    /// 1_000 tokens to user1 as the owner.
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            council: Some(CouncilConfig {
+                members: vec![owner(), user1(), user2()],
+                threshold: 2,
+            }),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        let id = contract.council_propose(AdminAction::Mint {
+            account_id: user1(),
+            amount: U128(1_000),
+        });
+        assert_eq!(id.0, 0);
+        (contract, context)
+    }
+
+    #[test]
+    fn test_proposal_executes_at_threshold() {
+        let (mut contract, mut context) = setup();
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+        let proposal = contract.council_proposal(U64(0)).unwrap();
+        assert_eq!(proposal.confirmations, vec![owner()]);
+
+        testing_env!(context
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        assert!(contract.council_confirm(U64(0)));
+        assert_eq!(contract.ft_balance_of(user1()).0, 1_000);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + 1_000);
+        assert!(contract.council_proposals(None, None).is_empty());
+    }
+
+    #[should_panic(expected = "The token is paused")]
+    #[test]
+    fn test_paused_token_rejects_transfers() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.council_propose(AdminAction::SetPaused { paused: true });
+        testing_env!(context
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.council_confirm(U64(1));
+        assert!(contract.is_paused());
+
+        testing_env!(context.predecessor_account_id(owner()).build());
+        contract.ft_transfer(user1(), U128(1), None);
+    }
+
+    #[test]
+    fn test_grant_role_is_paid_by_the_proposal() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        let usage_before_proposal = env::storage_usage();
+        contract.council_propose(AdminAction::GrantRole {
+            role: Role::Oracle,
+            account_id: user1(),
+        });
+        let usage_with_proposal = env::storage_usage();
+
+        testing_env!(context
+            .storage_usage(usage_with_proposal)
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        assert!(contract.council_confirm(U64(1)));
+        assert!(contract.has_role(Role::Oracle, user1()));
+        assert!(env::storage_usage() > usage_before_proposal);
+        assert!(env::storage_usage() < usage_with_proposal);
+    }
+
+    #[should_panic(expected = "This setting is changed by the council")]
+    #[test]
+    fn test_owner_settings_panic_with_council() {
+        let (mut contract, _) = setup();
+
+        contract.grant_role(Role::Oracle, owner());
+    }
+
+    #[should_panic(expected = "The proposal has expired")]
+    #[test]
+    fn test_confirm_panics_on_expired_proposal() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .block_timestamp(PROPOSAL_PERIOD + 1)
+            .build());
+        contract.council_confirm(U64(0));
+    }
+
+    #[test]
+    fn test_cancel_and_set_council() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.council_cancel(U64(0));
+        assert!(contract.council_proposal(U64(0)).is_none());
+
+        // the council replaces itself
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        contract.council_propose(AdminAction::SetCouncil {
+            members: vec![user1(), user2()],
+            threshold: 1,
+        });
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        assert!(contract.council_confirm(U64(1)));
+        assert_eq!(contract.council_members(), vec![user1(), user2()]);
+        assert_eq!(contract.council_threshold(), 1);
+    }
+
+    #[should_panic(expected = "The council and the timelock can't be combined")]
+    #[test]
+    fn test_propose_panics_on_timelock_delay() {
+        let (mut contract, _) = setup();
+
+        contract.council_propose(AdminAction::SetTimelockDelay { delay: U64(1) });
+    }
+
+    #[should_panic(expected = "Only council members can call this method")]
+    #[test]
+    fn test_propose_panics_on_non_member() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id("stranger.near".parse().unwrap())
+            .build());
+        contract.council_propose(AdminAction::SetPaused { paused: true });
+    }
+}
@@ -0,0 +1,220 @@
+/*!
+Conversion between human-readable amounts and base units.
+
+An amount of `"1.5"` of a token with 8 `decimals` is `150000000` base units. `parse_amount` and
+`format_amount` convert exactly, without floating point, for any number of decimals, and are
+usable by clients through the `rlib` of the crate. The contract exposes them with the decimals and
+the symbol of the token as `ft_parse_amount` and `ft_format_amount`.
+*/
+use std::fmt;
+
+use near_sdk::json_types::U128;
+use near_sdk::{env, near, FunctionError};
+
+use crate::{Contract, ContractExt};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseAmountError {
+    /// The amount has no digits.
+    Empty,
+    /// The amount has other characters than digits and a single decimal point.
+    InvalidCharacter,
+    /// The amount has more significant fractional digits than the decimals of the token.
+    TooManyDecimals { decimals: u8 },
+    /// The amount in base units doesn't fit in a `u128`.
+    Overflow,
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "The amount is empty"),
+            Self::InvalidCharacter => {
+                write!(f, "The amount should only have digits and a decimal point")
+            }
+            Self::TooManyDecimals { decimals } => {
+                write!(f, "The amount has more than {} decimals", decimals)
+            }
+            Self::Overflow => write!(f, "The amount exceeds the maximum balance"),
+        }
+    }
+}
+
+impl FunctionError for ParseAmountError {
+    fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+/// Parses a decimal `amount`, like `"1.5"`, into base units of a token with `decimals`.
+/// Surrounding whitespace is ignored, and trailing zeros after the decimal point don't count as
+/// decimals.
+pub fn parse_amount(amount: &str, decimals: u8) -> Result<u128, ParseAmountError> {
+    let amount = amount.trim();
+    let (integer, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(ParseAmountError::Empty);
+    }
+    if !integer
+        .bytes()
+        .chain(fraction.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return Err(ParseAmountError::InvalidCharacter);
+    }
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > usize::from(decimals) {
+        return Err(ParseAmountError::TooManyDecimals { decimals });
+    }
+
+    let digits = format!(
+        "{}{}{}",
+        integer,
+        fraction,
+        "0".repeat(usize::from(decimals) - fraction.len())
+    );
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    digits.parse().map_err(|_| ParseAmountError::Overflow)
+}
+
+/// Formats `amount` base units of a token with `decimals` as a decimal string, like `"1.5"`,
+/// without trailing zeros after the decimal point.
+pub fn format_amount(amount: u128, decimals: u8) -> String {
+    let decimals = usize::from(decimals);
+    let digits = format!("{:0>width$}", amount, width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+#[near]
+impl Contract {
+    /// Formats `amount` base units as a decimal amount followed by the symbol, like
+    /// `"1.5 EXAMPLE"`.
+    pub fn ft_format_amount(&self, amount: U128) -> String {
+        let metadata = self.metadata.get().unwrap();
+        format!(
+            "{} {}",
+            format_amount(amount.0, metadata.decimals),
+            metadata.symbol
+        )
+    }
+
+    /// Parses a decimal amount, optionally followed by the symbol, like `"1.5 EXAMPLE"`, into base
+    /// units.
+    #[handle_result]
+    pub fn ft_parse_amount(&self, amount: String) -> Result<U128, ParseAmountError> {
+        let metadata = self.metadata.get().unwrap();
+        let amount = amount.trim();
+        let amount = amount
+            .strip_suffix(metadata.symbol.as_str())
+            .unwrap_or(amount);
+        parse_amount(amount, metadata.decimals).map(U128)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::tests::{setup_with_config, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    #[test]
+    fn test_round_trip_for_all_decimals() {
+        for decimals in 0..=38u8 {
+            let one = 10u128.pow(u32::from(decimals));
+            assert_eq!(parse_amount("1", decimals), Ok(one));
+            assert_eq!(format_amount(one, decimals), "1");
+            assert_eq!(parse_amount("0", decimals), Ok(0));
+            assert_eq!(format_amount(0, decimals), "0");
+
+            let smallest = if decimals == 0 {
+                "1".to_string()
+            } else {
+                format!("0.{}1", "0".repeat(usize::from(decimals) - 1))
+            };
+            assert_eq!(format_amount(1, decimals), smallest);
+            assert_eq!(parse_amount(&smallest, decimals), Ok(1));
+
+            let max = format_amount(u128::MAX, decimals);
+            assert_eq!(parse_amount(&max, decimals), Ok(u128::MAX));
+        }
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("1.5", 8), Ok(150_000_000));
+        assert_eq!(parse_amount(" 1.50000000000 ", 8), Ok(150_000_000));
+        assert_eq!(parse_amount(".5", 1), Ok(5));
+        assert_eq!(parse_amount("007.", 0), Ok(7));
+        assert_eq!(parse_amount("", 8), Err(ParseAmountError::Empty));
+        assert_eq!(parse_amount(".", 8), Err(ParseAmountError::Empty));
+        assert_eq!(
+            parse_amount("1,5", 8),
+            Err(ParseAmountError::InvalidCharacter)
+        );
+        assert_eq!(
+            parse_amount("-1", 8),
+            Err(ParseAmountError::InvalidCharacter)
+        );
+        assert_eq!(
+            parse_amount("1.2.3", 8),
+            Err(ParseAmountError::InvalidCharacter)
+        );
+        assert_eq!(
+            parse_amount("0.001", 2),
+            Err(ParseAmountError::TooManyDecimals { decimals: 2 })
+        );
+    }
+
+    #[test]
+    fn test_parse_amount_overflow() {
+        let max = u128::MAX.to_string();
+        let above_max = "340282366920938463463374607431768211456";
+        assert_eq!(parse_amount(&max, 0), Ok(u128::MAX));
+        assert_eq!(parse_amount(above_max, 0), Err(ParseAmountError::Overflow));
+        assert_eq!(parse_amount("3.5", 38), Err(ParseAmountError::Overflow));
+        assert_eq!(parse_amount("1", 39), Err(ParseAmountError::Overflow));
+        assert_eq!(parse_amount("0.1", 39), Ok(10u128.pow(38)));
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(150_000_000, 8), "1.5");
+        assert_eq!(format_amount(1_234_567, 3), "1234.567");
+        assert_eq!(format_amount(1_000, 0), "1000");
+        assert_eq!(
+            format_amount(u128::MAX, 38),
+            "3.40282366920938463463374607431768211455"
+        );
+        assert_eq!(
+            format_amount(1, 40),
+            "0.0000000000000000000000000000000000000001"
+        );
+    }
+
+    #[test]
+    fn test_ft_format_and_parse_amount() {
+        let (contract, _) = setup_with_config(ContractConfig::default());
+
+        // the test token has 24 decimals
+        let formatted = contract.ft_format_amount(TOTAL_SUPPLY.into());
+        assert_eq!(formatted, "0.000000001 EXAMPLE");
+        assert_eq!(contract.ft_parse_amount(formatted), Ok(U128(TOTAL_SUPPLY)));
+        assert_eq!(
+            contract.ft_parse_amount("1.5".to_string()),
+            Ok(U128(15 * 10u128.pow(23)))
+        );
+        assert_eq!(
+            contract.ft_parse_amount("1.5 OTHER".to_string()),
+            Err(ParseAmountError::InvalidCharacter)
+        );
+    }
+}
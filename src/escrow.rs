@@ -0,0 +1,656 @@
+/*!
+Escrow of tokens between two accounts with an arbiter.
+
+The sender locks tokens in an escrow for the receiver. The sender can release them to the
+receiver at any time, or take them back once the escrow has expired. The receiver can hand them
+back to the sender at any time. Either party can dispute the escrow, after which only the
+arbiter can settle it by splitting the amount between the parties. If the arbiter doesn't settle
+it within 30 days of the later of the dispute and the expiry, either party can refund the escrow
+to the sender.
+
+A party which unregistered since the escrow was created can't be credited, so its part goes to
+the other party, or is burned with an `ft_burn` event for the sender if both unregistered.
+
+Escrowed tokens are part of the total supply, but are not part of any account balance until the
+escrow is settled. The sender pays for the storage of the escrow with the deposit attached to
+`escrow_create`, and gets it back when the escrow is settled.
+*/
+use near_contract_standards::fungible_token::events::FtBurn;
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{assert_one_yocto, env, log, near, require, AccountId, Duration, IntoStorageKey};
+
+use crate::{Contract, ContractExt, StorageKey};
+
+/// Time the arbiter has to resolve a dispute, from the later of the dispute and the expiry.
+const DISPUTE_PERIOD: Duration = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub enum EscrowStatus {
+    /// Waiting to be released or refunded.
+    Pending,
+    /// Disputed by one of the parties, waiting for the arbiter.
+    Disputed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct Escrow {
+    pub id: U64,
+    pub sender_id: AccountId,
+    pub receiver_id: AccountId,
+    pub arbiter_id: AccountId,
+    pub amount: U128,
+    /// Block timestamp in nanoseconds after which the sender can take the tokens back.
+    pub expiry: U64,
+    pub memo: Option<String>,
+    pub status: EscrowStatus,
+    /// Block timestamp in nanoseconds after which a disputed escrow can be refunded to the
+    /// sender, if the arbiter didn't resolve it.
+    pub dispute_deadline: Option<U64>,
+}
+
+#[near(event_json(standard = "ft-escrow"))]
+pub enum EscrowEvent {
+    #[event_version("1.0.0")]
+    EscrowCreate {
+        id: U64,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        arbiter_id: AccountId,
+        amount: U128,
+        expiry: U64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        memo: Option<String>,
+    },
+    #[event_version("1.0.0")]
+    EscrowRelease {
+        id: U64,
+        receiver_id: AccountId,
+        amount: U128,
+    },
+    #[event_version("1.0.0")]
+    EscrowRefund {
+        id: U64,
+        sender_id: AccountId,
+        amount: U128,
+    },
+    #[event_version("1.0.0")]
+    EscrowDispute {
+        id: U64,
+        disputed_by: AccountId,
+        deadline: U64,
+    },
+    #[event_version("1.0.0")]
+    EscrowResolve {
+        id: U64,
+        arbiter_id: AccountId,
+        receiver_amount: U128,
+        sender_amount: U128,
+    },
+}
+
+#[near]
+pub struct Escrows {
+    /// Id assigned to the next escrow.
+    pub next_id: u64,
+
+    /// Escrow id -> escrow.
+    pub escrows: UnorderedMap<u64, Escrow>,
+
+    /// AccountID -> ids of the escrows where the account is the sender, the receiver or the
+    /// arbiter.
+    pub by_party: LookupMap<AccountId, UnorderedSet<u64>>,
+}
+
+impl Escrows {
+    pub fn new<E, P>(escrows_prefix: E, by_party_prefix: P) -> Self
+    where
+        E: IntoStorageKey,
+        P: IntoStorageKey,
+    {
+        Self {
+            next_id: 0,
+            escrows: UnorderedMap::new(escrows_prefix),
+            by_party: LookupMap::new(by_party_prefix),
+        }
+    }
+
+    fn internal_unwrap_escrow(&self, id: u64) -> Escrow {
+        self.escrows
+            .get(&id)
+            .unwrap_or_else(|| env::panic_str(format!("Escrow {} doesn't exist", id).as_str()))
+    }
+
+    fn internal_add(&mut self, escrow: &Escrow) {
+        self.escrows.insert(&escrow.id.0, escrow);
+        for account_id in [&escrow.sender_id, &escrow.receiver_id, &escrow.arbiter_id] {
+            let mut ids = self.by_party.get(account_id).unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::EscrowsByPartyInner {
+                    account_hash: env::sha256_array(account_id.as_bytes()),
+                })
+            });
+            ids.insert(&escrow.id.0);
+            self.by_party.insert(account_id, &ids);
+        }
+    }
+
+    fn internal_remove(&mut self, escrow: &Escrow) {
+        self.escrows.remove(&escrow.id.0);
+        for account_id in [&escrow.sender_id, &escrow.receiver_id, &escrow.arbiter_id] {
+            if let Some(mut ids) = self.by_party.get(account_id) {
+                ids.remove(&escrow.id.0);
+                if ids.is_empty() {
+                    self.by_party.remove(account_id);
+                } else {
+                    self.by_party.insert(account_id, &ids);
+                }
+            }
+        }
+    }
+}
+
+impl Contract {
+    /// Removes the escrow and credits the given amounts to its parties. The part of a party which
+    /// unregistered goes to the other party, or is burned if both unregistered.
+    fn internal_settle_escrow(
+        &mut self,
+        escrow: &Escrow,
+        mut receiver_amount: u128,
+        mut sender_amount: u128,
+    ) {
+        let initial_storage_usage = env::storage_usage();
+        self.escrows.internal_remove(escrow);
+        let receiver_registered = self.token.accounts.contains_key(&escrow.receiver_id);
+        let sender_registered = self.token.accounts.contains_key(&escrow.sender_id);
+        match (receiver_registered, sender_registered) {
+            (true, true) => {}
+            (true, false) => {
+                log!(
+                    "@{} is not registered, the escrowed tokens go to @{}",
+                    escrow.sender_id,
+                    escrow.receiver_id
+                );
+                receiver_amount += std::mem::take(&mut sender_amount);
+            }
+            (false, true) => {
+                log!(
+                    "@{} is not registered, the escrowed tokens go to @{}",
+                    escrow.receiver_id,
+                    escrow.sender_id
+                );
+                sender_amount += std::mem::take(&mut receiver_amount);
+            }
+            (false, false) => {
+                self.internal_burn_locked(escrow.amount.0);
+                FtBurn {
+                    owner_id: &escrow.sender_id,
+                    amount: escrow.amount,
+                    memo: Some("escrow"),
+                }
+                .emit();
+                self.internal_refund_storage(initial_storage_usage, escrow.sender_id.clone());
+                return;
+            }
+        }
+        if receiver_amount > 0 {
+            self.internal_before_unlock(&escrow.sender_id, &escrow.receiver_id, receiver_amount);
+            self.internal_unlock_verified(&escrow.sender_id, &escrow.receiver_id, receiver_amount);
+        }
+        if sender_amount > 0 {
+            self.internal_before_unlock(&escrow.sender_id, &escrow.sender_id, sender_amount);
+            self.internal_unlock(&escrow.sender_id, sender_amount);
+        }
+        self.internal_refund_storage(initial_storage_usage, escrow.sender_id.clone());
+    }
+}
+
+#[near]
+impl Contract {
+    /// Locks `amount` of the predecessor's tokens in an escrow for `receiver_id`, which
+    /// `arbiter_id` settles in case of a dispute. The attached deposit must cover the storage of
+    /// the escrow, the excess is refunded. Returns the id of the escrow.
+    #[payable]
+    pub fn escrow_create(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        arbiter_id: AccountId,
+        expiry: U64,
+        memo: Option<String>,
+    ) -> U64 {
+        let initial_storage_usage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        require!(
+            sender_id != receiver_id,
+            "Sender and receiver should be different"
+        );
+        require!(
+            arbiter_id != sender_id && arbiter_id != receiver_id,
+            "Arbiter should be different from the sender and the receiver"
+        );
+        require!(
+            expiry.0 > env::block_timestamp(),
+            "Expiry should be in the future"
+        );
+        self.token.internal_unwrap_balance_of(&receiver_id);
+        self.internal_before_lock(&sender_id, &receiver_id, amount.0);
+        self.internal_lock(&sender_id, amount.0);
+
+        let escrow = Escrow {
+            id: U64(self.escrows.next_id),
+            sender_id,
+            receiver_id,
+            arbiter_id,
+            amount,
+            expiry,
+            memo,
+            status: EscrowStatus::Pending,
+            dispute_deadline: None,
+        };
+        self.escrows.next_id += 1;
+        self.escrows.internal_add(&escrow);
+        self.internal_charge_storage(initial_storage_usage);
+
+        EscrowEvent::EscrowCreate {
+            id: escrow.id,
+            sender_id: escrow.sender_id,
+            receiver_id: escrow.receiver_id,
+            arbiter_id: escrow.arbiter_id,
+            amount: escrow.amount,
+            expiry: escrow.expiry,
+            memo: escrow.memo,
+        }
+        .emit();
+
+        escrow.id
+    }
+
+    /// Releases the escrowed tokens to the receiver. Can only be called by the sender.
+    #[payable]
+    pub fn escrow_release(&mut self, id: U64) {
+        assert_one_yocto();
+        let escrow = self.escrows.internal_unwrap_escrow(id.0);
+        require!(
+            env::predecessor_account_id() == escrow.sender_id,
+            "Only the sender can release the escrow"
+        );
+        require!(
+            escrow.status == EscrowStatus::Pending,
+            "The escrow is disputed"
+        );
+
+        self.internal_settle_escrow(&escrow, escrow.amount.0, 0);
+
+        EscrowEvent::EscrowRelease {
+            id,
+            receiver_id: escrow.receiver_id,
+            amount: escrow.amount,
+        }
+        .emit();
+    }
+
+    /// Returns the escrowed tokens to the sender. Can be called by the sender once the escrow has
+    /// expired, or by the receiver at any time. A disputed escrow can only be refunded by either
+    /// of them once its dispute deadline has passed.
+    #[payable]
+    pub fn escrow_refund(&mut self, id: U64) {
+        assert_one_yocto();
+        let escrow = self.escrows.internal_unwrap_escrow(id.0);
+        let predecessor_id = env::predecessor_account_id();
+        require!(
+            predecessor_id == escrow.sender_id || predecessor_id == escrow.receiver_id,
+            "Only the sender or the receiver can refund the escrow"
+        );
+        match escrow.dispute_deadline {
+            Some(deadline) => require!(
+                env::block_timestamp() >= deadline.0,
+                "The escrow is disputed"
+            ),
+            None => require!(
+                predecessor_id == escrow.receiver_id || env::block_timestamp() >= escrow.expiry.0,
+                "The escrow hasn't expired yet"
+            ),
+        }
+
+        self.internal_settle_escrow(&escrow, 0, escrow.amount.0);
+
+        EscrowEvent::EscrowRefund {
+            id,
+            sender_id: escrow.sender_id,
+            amount: escrow.amount,
+        }
+        .emit();
+    }
+
+    /// Marks the escrow as disputed, so only the arbiter can settle it. Can be called by the
+    /// sender or the receiver.
+    #[payable]
+    pub fn escrow_dispute(&mut self, id: U64) {
+        assert_one_yocto();
+        let mut escrow = self.escrows.internal_unwrap_escrow(id.0);
+        let predecessor_id = env::predecessor_account_id();
+        require!(
+            predecessor_id == escrow.sender_id || predecessor_id == escrow.receiver_id,
+            "Only the sender or the receiver can dispute the escrow"
+        );
+        require!(
+            escrow.status == EscrowStatus::Pending,
+            "The escrow is already disputed"
+        );
+
+        let deadline =
+            U64(std::cmp::max(env::block_timestamp(), escrow.expiry.0)
+                .saturating_add(DISPUTE_PERIOD));
+        escrow.status = EscrowStatus::Disputed;
+        escrow.dispute_deadline = Some(deadline);
+        self.escrows.escrows.insert(&id.0, &escrow);
+
+        EscrowEvent::EscrowDispute {
+            id,
+            disputed_by: predecessor_id,
+            deadline,
+        }
+        .emit();
+    }
+
+    /// Settles a disputed escrow by giving `receiver_amount` to the receiver and the rest to the
+    /// sender. Can only be called by the arbiter.
+    #[payable]
+    pub fn escrow_resolve(&mut self, id: U64, receiver_amount: U128) {
+        assert_one_yocto();
+        let escrow = self.escrows.internal_unwrap_escrow(id.0);
+        require!(
+            env::predecessor_account_id() == escrow.arbiter_id,
+            "Only the arbiter can resolve the escrow"
+        );
+        require!(
+            escrow.status == EscrowStatus::Disputed,
+            "The escrow is not disputed"
+        );
+        let sender_amount = escrow
+            .amount
+            .0
+            .checked_sub(receiver_amount.0)
+            .unwrap_or_else(|| env::panic_str("The receiver amount exceeds the escrowed amount"));
+
+        self.internal_settle_escrow(&escrow, receiver_amount.0, sender_amount);
+
+        EscrowEvent::EscrowResolve {
+            id,
+            arbiter_id: escrow.arbiter_id,
+            receiver_amount,
+            sender_amount: U128(sender_amount),
+        }
+        .emit();
+    }
+
+    pub fn escrow(&self, id: U64) -> Option<Escrow> {
+        self.escrows.escrows.get(&id.0)
+    }
+
+    /// Returns the open escrows where `account_id` is the sender, the receiver or the arbiter.
+    pub fn escrows_of(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<Escrow> {
+        let Some(ids) = self.escrows.by_party.get(&account_id) else {
+            return vec![];
+        };
+        ids.iter()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.unwrap_or(10) as usize)
+            .map(|id| self.escrows.internal_unwrap_escrow(id))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const AMOUNT: u128 = 1_000;
+    const EXPIRY: u64 = 1_000_000;
+
+    fn arbiter() -> AccountId {
+        accounts(4)
+    }
+
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.escrow_create(user1(), AMOUNT.into(), arbiter(), U64(EXPIRY), None);
+
+        (contract, context)
+    }
+
+    fn call_as(context: &mut VMContextBuilder, account_id: AccountId) {
+        testing_env!(context
+            .predecessor_account_id(account_id)
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+    }
+
+    #[test]
+    fn test_create() {
+        let (contract, _) = setup();
+
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - AMOUNT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+
+        let escrow = contract.escrow(U64(0)).unwrap();
+        assert_eq!(escrow.sender_id, owner());
+        assert_eq!(escrow.receiver_id, user1());
+        assert_eq!(escrow.status, EscrowStatus::Pending);
+        for account_id in [owner(), user1(), arbiter()] {
+            assert_eq!(
+                contract.escrows_of(account_id, None, None),
+                vec![escrow.clone()]
+            );
+        }
+    }
+
+    #[test]
+    fn test_release() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, owner());
+        contract.escrow_release(U64(0));
+
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+        assert!(contract.escrow(U64(0)).is_none());
+        assert!(contract.escrows_of(owner(), None, None).is_empty());
+    }
+
+    #[should_panic(expected = "Only the sender can release the escrow")]
+    #[test]
+    fn test_release_panics_on_receiver() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, user1());
+        contract.escrow_release(U64(0));
+    }
+
+    #[should_panic(expected = "The token is paused")]
+    #[test]
+    fn test_release_panics_when_paused() {
+        let (mut contract, mut context) = setup();
+
+        contract.paused = true;
+        call_as(&mut context, owner());
+        contract.escrow_release(U64(0));
+    }
+
+    #[should_panic(expected = "@charlie is denied")]
+    #[test]
+    fn test_create_panics_on_denied_receiver() {
+        let (mut contract, mut context) = setup();
+
+        contract.denylist.insert(&user1());
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.escrow_create(user1(), AMOUNT.into(), arbiter(), U64(EXPIRY), None);
+    }
+
+    #[test]
+    fn test_refund_after_expiry() {
+        let (mut contract, mut context) = setup();
+
+        context.block_timestamp(EXPIRY);
+        call_as(&mut context, owner());
+        contract.escrow_refund(U64(0));
+
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+        assert!(contract.escrow(U64(0)).is_none());
+    }
+
+    #[should_panic(expected = "The escrow hasn't expired yet")]
+    #[test]
+    fn test_refund_panics_before_expiry() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, owner());
+        contract.escrow_refund(U64(0));
+    }
+
+    #[test]
+    fn test_dispute_resolved_by_arbiter() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, user1());
+        contract.escrow_dispute(U64(0));
+        assert_eq!(
+            contract.escrow(U64(0)).unwrap().status,
+            EscrowStatus::Disputed
+        );
+
+        call_as(&mut context, arbiter());
+        contract.escrow_resolve(U64(0), U128(AMOUNT / 4));
+
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT / 4);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - AMOUNT / 4);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    fn test_refund_after_dispute_deadline() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, user1());
+        contract.escrow_dispute(U64(0));
+        let deadline = EXPIRY + DISPUTE_PERIOD;
+        assert_eq!(
+            contract.escrow(U64(0)).unwrap().dispute_deadline,
+            Some(U64(deadline))
+        );
+
+        context.block_timestamp(deadline);
+        call_as(&mut context, owner());
+        contract.escrow_refund(U64(0));
+
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+        assert!(contract.escrow(U64(0)).is_none());
+    }
+
+    #[should_panic(expected = "The escrow is disputed")]
+    #[test]
+    fn test_refund_panics_before_dispute_deadline() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, user1());
+        contract.escrow_dispute(U64(0));
+
+        context.block_timestamp(EXPIRY + DISPUTE_PERIOD - 1);
+        call_as(&mut context, owner());
+        contract.escrow_refund(U64(0));
+    }
+
+    #[test]
+    fn test_release_to_unregistered_receiver_refunds_sender() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, user1());
+        assert!(contract.storage_unregister(None));
+
+        call_as(&mut context, owner());
+        contract.escrow_release(U64(0));
+
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+        assert!(contract.escrow(U64(0)).is_none());
+    }
+
+    #[test]
+    fn test_refund_burned_when_both_parties_unregistered() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, owner());
+        assert!(contract.storage_unregister(Some(true)));
+        call_as(&mut context, user1());
+        assert!(contract.storage_unregister(None));
+        assert_eq!(contract.ft_total_supply().0, AMOUNT);
+
+        call_as(&mut context, user1());
+        contract.escrow_refund(U64(0));
+
+        assert_eq!(contract.ft_total_supply().0, 0);
+        assert!(contract.escrow(U64(0)).is_none());
+    }
+
+    #[should_panic(expected = "The escrow is disputed")]
+    #[test]
+    fn test_release_panics_when_disputed() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, user1());
+        contract.escrow_dispute(U64(0));
+
+        call_as(&mut context, owner());
+        contract.escrow_release(U64(0));
+    }
+
+    #[should_panic(expected = "Only the arbiter can resolve the escrow")]
+    #[test]
+    fn test_resolve_panics_on_party() {
+        let (mut contract, mut context) = setup();
+
+        call_as(&mut context, user1());
+        contract.escrow_dispute(U64(0));
+
+        call_as(&mut context, user1());
+        contract.escrow_resolve(U64(0), U128(AMOUNT));
+    }
+
+    #[should_panic(expected = "The account doesn't have enough balance")]
+    #[test]
+    fn test_create_panics_on_amount_greater_than_balance() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.escrow_create(owner(), AMOUNT.into(), arbiter(), U64(EXPIRY), None);
+    }
+}
@@ -0,0 +1,358 @@
+/*!
+Flash loans, the NEAR equivalent of ERC-3156 flash mints.
+
+`flash_loan` mints the amount to the receiver and calls `on_flash_loan` on it. Then
+`flash_resolve_loan` burns the amount plus the fee from the balance of the receiver, which should
+have done whatever it borrowed the tokens for by then. If `on_flash_loan` fails, or the receiver
+can't repay, the loan is rolled back instead: as much as possible of the amount is burned without
+a fee, and what couldn't be burned is recorded as the debt of the receiver, which can't borrow
+again.
+
+Since anybody can start a flash loan to any account, borrowers should check the `initiator_id`
+passed to `on_flash_loan`. Loans are disabled until the owner sets a maximum amount, and aren't
+subject to the transfer limits.
+*/
+use near_contract_standards::fungible_token::events::{FtBurn, FtMint};
+use near_contract_standards::fungible_token::{Balance, FungibleTokenCore};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::U128;
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, near, require, AccountId, Gas, IntoStorageKey, Promise,
+    PromiseResult,
+};
+
+use crate::math::mul_div;
+use crate::{Contract, ContractExt};
+
+const GAS_FOR_RESOLVE_FLASH_LOAN: Gas = Gas::from_tgas(10);
+const GAS_FOR_FLASH_LOAN: Gas = Gas::from_tgas(30);
+const BASIS_POINTS: u128 = 10_000;
+
+#[ext_contract(ext_flash_borrower)]
+pub trait FlashBorrower {
+    /// Called with `amount` already in the balance of the borrower. The borrower should hold
+    /// `amount + fee` when the call and the promises it returns are done.
+    fn on_flash_loan(&mut self, initiator_id: AccountId, amount: U128, fee: U128, msg: String);
+}
+
+#[near(event_json(standard = "ft-flash"))]
+pub enum FlashEvent {
+    #[event_version("1.0.0")]
+    FlashLoan {
+        initiator_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        fee: U128,
+        repaid: bool,
+    },
+}
+
+#[near]
+pub struct FlashLoans {
+    /// Maximum amount of a loan. Loans are disabled when 0.
+    pub max_loan: Balance,
+
+    /// Fee in basis points of the amount.
+    pub fee: u32,
+
+    /// Receiver -> amount of the loans that couldn't be burned back.
+    pub debts: LookupMap<AccountId, Balance>,
+}
+
+impl FlashLoans {
+    pub fn new<S>(prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            max_loan: 0,
+            fee: 0,
+            debts: LookupMap::new(prefix),
+        }
+    }
+
+    pub(crate) fn internal_set_fee(&mut self, fee: u32) {
+        require!(
+            u128::from(fee) <= BASIS_POINTS,
+            "The fee can't exceed 10000 basis points"
+        );
+        self.fee = fee;
+    }
+
+    /// Fee of a loan of `amount`, rounded up.
+    fn fee_of(&self, amount: Balance) -> Balance {
+        mul_div(amount, u128::from(self.fee), BASIS_POINTS, true)
+    }
+}
+
+#[near]
+impl Contract {
+    /// Lends `amount` to `receiver_id`, which should be registered, and calls its
+    /// `on_flash_loan` with all the prepaid gas above 30 Tgas.
+    #[payable]
+    pub fn flash_loan(&mut self, receiver_id: AccountId, amount: U128, msg: String) -> Promise {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        require!(
+            amount.0 <= self.flash_loans.max_loan,
+            format!(
+                "The amount exceeds the maximum flash loan of {}",
+                self.flash_loans.max_loan
+            )
+        );
+        require!(
+            !self.flash_loans.debts.contains_key(&receiver_id),
+            "The receiver has an outstanding flash loan debt"
+        );
+        require!(
+            env::prepaid_gas() > GAS_FOR_FLASH_LOAN,
+            "More gas is required"
+        );
+        let initiator_id = env::predecessor_account_id();
+        self.internal_assert_can_move(&initiator_id, &receiver_id);
+        self.internal_assert_verified(&receiver_id);
+        let fee = U128(self.flash_loans.fee_of(amount.0));
+
+        self.internal_deposit(&receiver_id, amount.0);
+        FtMint {
+            owner_id: &receiver_id,
+            amount,
+            memo: Some("flash loan"),
+        }
+        .emit();
+
+        ext_flash_borrower::ext(receiver_id.clone())
+            .with_static_gas(env::prepaid_gas().saturating_sub(GAS_FOR_FLASH_LOAN))
+            .on_flash_loan(initiator_id.clone(), amount, fee, msg)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_FLASH_LOAN)
+                    .flash_resolve_loan(initiator_id, receiver_id, amount, fee),
+            )
+    }
+
+    /// Burns the amount plus the fee from the receiver, or rolls the loan back. Returns whether
+    /// the loan was repaid.
+    #[private]
+    pub fn flash_resolve_loan(
+        &mut self,
+        initiator_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        fee: U128,
+    ) -> bool {
+        // the receiver could have unregistered in the meantime, leaving a balance of 0
+        let balance = self.ft_balance_of(receiver_id.clone()).0;
+        let due = amount.0.saturating_add(fee.0);
+        let repaid =
+            matches!(env::promise_result(0), PromiseResult::Successful(_)) && balance >= due;
+        let burned = if repaid {
+            due
+        } else {
+            let burned = balance.min(amount.0);
+            let debt = amount.0 - burned;
+            if debt > 0 {
+                let total_debt = self.flash_loans.debts.get(&receiver_id).unwrap_or(0);
+                self.flash_loans
+                    .debts
+                    .insert(&receiver_id, &total_debt.saturating_add(debt));
+            }
+            burned
+        };
+        if burned > 0 {
+            self.internal_withdraw(&receiver_id, burned);
+            FtBurn {
+                owner_id: &receiver_id,
+                amount: burned.into(),
+                memo: Some(if repaid {
+                    "flash loan repayment"
+                } else {
+                    "flash loan rollback"
+                }),
+            }
+            .emit();
+        }
+
+        FlashEvent::FlashLoan {
+            initiator_id,
+            receiver_id,
+            amount,
+            fee,
+            repaid,
+        }
+        .emit();
+        repaid
+    }
+
+    /// Sets the maximum amount of a flash loan, 0 disabling them. Can only be called by the owner,
+    /// without a council and while the timelock is disabled. Otherwise it is an admin action.
+    #[payable]
+    pub fn flash_set_max_loan(&mut self, max_loan: U128) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        self.flash_loans.max_loan = max_loan.0;
+    }
+
+    /// Sets the fee of flash loans in basis points. Can only be called by the owner, without a
+    /// council and while the timelock is disabled. Otherwise fee changes are an admin action.
+    #[payable]
+    pub fn flash_set_fee(&mut self, fee: u32) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        self.flash_loans.internal_set_fee(fee);
+    }
+
+    pub fn flash_max_loan(&self) -> U128 {
+        self.flash_loans.max_loan.into()
+    }
+
+    /// Fee of a flash loan of `amount`.
+    pub fn flash_fee(&self, amount: U128) -> U128 {
+        self.flash_loans.fee_of(amount.0).into()
+    }
+
+    /// Amount of the flash loans of `account_id` that couldn't be burned back.
+    pub fn flash_debt_of(&self, account_id: AccountId) -> U128 {
+        self.flash_loans.debts.get(&account_id).unwrap_or(0).into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken, RuntimeFeesConfig};
+
+    use super::*;
+    use crate::council::CouncilConfig;
+    use crate::tests::{current, owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const AMOUNT: u128 = 10_000;
+
+    /// Registers user1 with 100 tokens, sets a 1% fee, and lends `AMOUNT` to user1.
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), 100.into(), None);
+        contract.flash_set_max_loan(AMOUNT.into());
+        contract.flash_set_fee(100);
+
+        testing_env!(context.predecessor_account_id(user2()).build());
+        contract.flash_loan(user1(), AMOUNT.into(), "".to_string());
+        assert_eq!(contract.ft_balance_of(user1()).0, 100 + AMOUNT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + AMOUNT);
+        (contract, context)
+    }
+
+    fn resolve(
+        contract: &mut Contract,
+        context: &mut VMContextBuilder,
+        result: PromiseResult,
+    ) -> bool {
+        testing_env!(
+            context.predecessor_account_id(current()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+        contract.flash_resolve_loan(
+            user2(),
+            user1(),
+            AMOUNT.into(),
+            contract.flash_fee(AMOUNT.into()),
+        )
+    }
+
+    #[test]
+    fn test_flash_loan_repaid() {
+        let (mut contract, mut context) = setup();
+
+        assert!(resolve(
+            &mut contract,
+            &mut context,
+            PromiseResult::Successful(vec![])
+        ));
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY - 100);
+    }
+
+    #[test]
+    fn test_flash_loan_rolled_back_on_failure() {
+        let (mut contract, mut context) = setup();
+
+        assert!(!resolve(&mut contract, &mut context, PromiseResult::Failed));
+        assert_eq!(contract.ft_balance_of(user1()).0, 100);
+        assert_eq!(contract.flash_debt_of(user1()).0, 0);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    fn test_flash_loan_default_is_recorded_as_debt() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_transfer(owner(), (AMOUNT - 50).into(), None);
+
+        assert!(!resolve(
+            &mut contract,
+            &mut context,
+            PromiseResult::Successful(vec![])
+        ));
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+        assert_eq!(contract.flash_debt_of(user1()).0, AMOUNT - 150);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + AMOUNT - 150);
+    }
+
+    #[should_panic(expected = "The receiver has an outstanding flash loan debt")]
+    #[test]
+    fn test_flash_loan_panics_on_debt() {
+        let (mut contract, mut context) = setup();
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_transfer(owner(), AMOUNT.into(), None);
+        resolve(&mut contract, &mut context, PromiseResult::Failed);
+
+        testing_env!(context
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.flash_loan(user1(), AMOUNT.into(), "".to_string());
+    }
+
+    #[should_panic(expected = "The amount exceeds the maximum flash loan of 10000")]
+    #[test]
+    fn test_flash_loan_panics_above_max() {
+        let (mut contract, _) = setup();
+
+        contract.flash_loan(user1(), (AMOUNT + 1).into(), "".to_string());
+    }
+
+    #[should_panic(expected = "This setting is changed by the council")]
+    #[test]
+    fn test_flash_set_max_loan_panics_with_council() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            council: Some(CouncilConfig {
+                members: vec![owner(), user1()],
+                threshold: 2,
+            }),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.flash_set_max_loan(AMOUNT.into());
+    }
+}
@@ -0,0 +1,549 @@
+/*!
+Social recovery of the balance of an account which lost its keys.
+
+An account opts in by nominating guardians and a threshold with `guardians_set`. When the account
+loses its keys, its guardians approve moving its balance to a new account with `recovery_approve`.
+Each guardian approves one new account at a time, and can move its approval to another one by
+approving it. Once `threshold` guardians approved the same new account, a challenge period of a
+week starts, after which any guardian can move the whole balance with `recovery_execute`. Until
+then, any transfer made by the account, or any change to its guardians, vetoes the recovery.
+
+The recovery moves the balance as a whole, so it isn't subject to the transfer limits. It is
+blocked while the token is paused or either account is denied, and the new account should be KYC
+verified when the token requires it.
+
+The feature is switched on at initialization with `new_with_config` and `social_recovery`, in
+which case the registration deposit of every account covers the storage of its guardians and of a
+pending recovery.
+*/
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{
+    assert_one_yocto, env, near, require, AccountId, Duration, IntoStorageKey, StorageUsage,
+};
+
+use crate::{Contract, ContractExt};
+
+const MAX_GUARDIANS: usize = 5;
+const CHALLENGE_PERIOD: Duration = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct RecoveryApproval {
+    pub guardian_id: AccountId,
+    pub new_account_id: AccountId,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct PendingRecovery {
+    /// The current approval of each guardian which approved a recovery.
+    pub approvals: Vec<RecoveryApproval>,
+    /// New account approved by `threshold` guardians. Not set until the threshold is reached.
+    pub new_account_id: Option<AccountId>,
+    /// Block timestamp in nanoseconds from which the recovery can be executed. Not set until the
+    /// threshold is reached.
+    pub challenge_ends_at: Option<U64>,
+}
+
+impl PendingRecovery {
+    fn approvals_of(&self, new_account_id: &AccountId) -> usize {
+        self.approvals
+            .iter()
+            .filter(|approval| &approval.new_account_id == new_account_id)
+            .count()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct GuardianSetting {
+    pub guardians: Vec<AccountId>,
+    pub threshold: u32,
+    pub recovery: Option<PendingRecovery>,
+}
+
+#[near(event_json(standard = "ft-social-recovery"))]
+pub enum SocialRecoveryEvent {
+    #[event_version("1.0.0")]
+    GuardiansSet {
+        account_id: AccountId,
+        guardians: Vec<AccountId>,
+        threshold: u32,
+    },
+    #[event_version("1.0.0")]
+    RecoveryApprove {
+        account_id: AccountId,
+        new_account_id: AccountId,
+        guardian_id: AccountId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        challenge_ends_at: Option<U64>,
+    },
+    #[event_version("1.0.0")]
+    RecoveryVeto { account_id: AccountId },
+    #[event_version("1.0.0")]
+    RecoveryExecute {
+        account_id: AccountId,
+        new_account_id: AccountId,
+        amount: U128,
+    },
+}
+
+#[near]
+pub struct Guardians {
+    pub enabled: bool,
+
+    /// AccountID -> guardians and pending recovery.
+    pub settings: LookupMap<AccountId, GuardianSetting>,
+
+    /// The storage size in bytes for the largest setting of one account.
+    pub setting_storage_usage: StorageUsage,
+}
+
+impl Guardians {
+    pub fn new<S>(prefix: S, enabled: bool) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        let mut this = Self {
+            enabled,
+            settings: LookupMap::new(prefix),
+            setting_storage_usage: 0,
+        };
+        if enabled {
+            let tmp_account_id: AccountId = "a".repeat(64).parse().unwrap();
+            let tmp_account_ids = vec![tmp_account_id.clone(); MAX_GUARDIANS];
+            let initial_storage_usage = env::storage_usage();
+            this.settings.insert(
+                &tmp_account_id,
+                &GuardianSetting {
+                    guardians: tmp_account_ids.clone(),
+                    threshold: u32::MAX,
+                    recovery: Some(PendingRecovery {
+                        approvals: tmp_account_ids
+                            .into_iter()
+                            .map(|guardian_id| RecoveryApproval {
+                                guardian_id,
+                                new_account_id: tmp_account_id.clone(),
+                            })
+                            .collect(),
+                        new_account_id: Some(tmp_account_id.clone()),
+                        challenge_ends_at: Some(U64(u64::MAX)),
+                    }),
+                },
+            );
+            this.setting_storage_usage = env::storage_usage() - initial_storage_usage;
+            this.settings.remove(&tmp_account_id);
+        }
+        this
+    }
+
+    fn assert_enabled(&self) {
+        require!(self.enabled, "Social recovery is disabled");
+    }
+
+    fn internal_unwrap_setting(&self, account_id: &AccountId) -> GuardianSetting {
+        self.settings
+            .get(account_id)
+            .unwrap_or_else(|| env::panic_str(format!("@{} has no guardians", account_id).as_str()))
+    }
+
+    /// Cancels the pending recovery of `account_id`, which just acted with its keys.
+    pub(crate) fn internal_veto(&mut self, account_id: &AccountId) {
+        if !self.enabled {
+            return;
+        }
+        let Some(mut setting) = self.settings.get(account_id) else {
+            return;
+        };
+        if setting.recovery.take().is_some() {
+            self.settings.insert(account_id, &setting);
+            SocialRecoveryEvent::RecoveryVeto {
+                account_id: account_id.clone(),
+            }
+            .emit();
+        }
+    }
+
+    pub(crate) fn internal_remove(&mut self, account_id: &AccountId) {
+        if self.enabled {
+            self.settings.remove(account_id);
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Nominates up to 5 `guardians` of the predecessor, `threshold` of which can recover its
+    /// balance. Replaces the current guardians and vetoes any pending recovery.
+    #[payable]
+    pub fn guardians_set(&mut self, guardians: Vec<AccountId>, threshold: u32) {
+        assert_one_yocto();
+        self.guardians.assert_enabled();
+        let account_id = env::predecessor_account_id();
+        self.token.internal_unwrap_balance_of(&account_id);
+        require!(
+            guardians.len() <= MAX_GUARDIANS,
+            format!("At most {} guardians can be nominated", MAX_GUARDIANS)
+        );
+        let mut unique = std::collections::HashSet::new();
+        require!(
+            guardians
+                .iter()
+                .all(|guardian| guardian != &account_id && unique.insert(guardian)),
+            "Guardians should be unique and different from the account"
+        );
+        require!(
+            threshold > 0 && threshold as usize <= guardians.len(),
+            "The threshold should be between 1 and the number of guardians"
+        );
+        self.guardians.internal_veto(&account_id);
+        self.guardians.settings.insert(
+            &account_id,
+            &GuardianSetting {
+                guardians: guardians.clone(),
+                threshold,
+                recovery: None,
+            },
+        );
+
+        SocialRecoveryEvent::GuardiansSet {
+            account_id,
+            guardians,
+            threshold,
+        }
+        .emit();
+    }
+
+    /// Opts the predecessor out of social recovery, vetoing any pending recovery.
+    #[payable]
+    pub fn guardians_remove(&mut self) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.guardians.internal_veto(&account_id);
+        require!(
+            self.guardians.settings.remove(&account_id).is_some(),
+            format!("@{} has no guardians", account_id)
+        );
+    }
+
+    /// Approves moving the balance of `account_id` to `new_account_id`, which should be
+    /// registered, for the predecessor, which should be a guardian of the account. Replaces the
+    /// previous approval of the guardian. Starts the challenge period once the threshold is
+    /// reached for `new_account_id`, and stops it if the approved account falls below it.
+    #[payable]
+    pub fn recovery_approve(&mut self, account_id: AccountId, new_account_id: AccountId) {
+        assert_one_yocto();
+        self.guardians.assert_enabled();
+        let guardian_id = env::predecessor_account_id();
+        let mut setting = self.guardians.internal_unwrap_setting(&account_id);
+        require!(
+            setting.guardians.contains(&guardian_id),
+            "Only the guardians of the account can call this method"
+        );
+        require!(
+            new_account_id != account_id,
+            "The new account should be different from the account"
+        );
+        self.token.internal_unwrap_balance_of(&new_account_id);
+        let mut recovery = setting.recovery.take().unwrap_or(PendingRecovery {
+            approvals: vec![],
+            new_account_id: None,
+            challenge_ends_at: None,
+        });
+        let approval = RecoveryApproval {
+            guardian_id: guardian_id.clone(),
+            new_account_id: new_account_id.clone(),
+        };
+        require!(
+            !recovery.approvals.contains(&approval),
+            "The recovery is already approved by the guardian"
+        );
+        recovery
+            .approvals
+            .retain(|approval| approval.guardian_id != guardian_id);
+        recovery.approvals.push(approval);
+
+        let threshold = setting.threshold as usize;
+        if let Some(approved_id) = &recovery.new_account_id {
+            if recovery.approvals_of(approved_id) < threshold {
+                recovery.new_account_id = None;
+                recovery.challenge_ends_at = None;
+            }
+        }
+        match &recovery.new_account_id {
+            Some(approved_id) => require!(
+                approved_id == &new_account_id,
+                format!("A recovery to @{} is already approved", approved_id)
+            ),
+            None => {
+                if recovery.approvals_of(&new_account_id) >= threshold {
+                    recovery.new_account_id = Some(new_account_id.clone());
+                    recovery.challenge_ends_at =
+                        Some(U64(env::block_timestamp().saturating_add(CHALLENGE_PERIOD)));
+                }
+            }
+        }
+        let challenge_ends_at = recovery.challenge_ends_at;
+        setting.recovery = Some(recovery);
+        self.guardians.settings.insert(&account_id, &setting);
+
+        SocialRecoveryEvent::RecoveryApprove {
+            account_id,
+            new_account_id,
+            guardian_id,
+            challenge_ends_at,
+        }
+        .emit();
+    }
+
+    /// Moves the whole balance of `account_id` to the new account of its approved recovery once
+    /// the challenge period is over. Can only be called by a guardian of the account. Returns the
+    /// moved amount.
+    #[payable]
+    pub fn recovery_execute(&mut self, account_id: AccountId) -> U128 {
+        assert_one_yocto();
+        let mut setting = self.guardians.internal_unwrap_setting(&account_id);
+        require!(
+            setting.guardians.contains(&env::predecessor_account_id()),
+            "Only the guardians of the account can call this method"
+        );
+        let recovery = setting
+            .recovery
+            .take()
+            .unwrap_or_else(|| env::panic_str("There is no pending recovery"));
+        let (Some(new_account_id), Some(challenge_ends_at)) =
+            (recovery.new_account_id, recovery.challenge_ends_at)
+        else {
+            env::panic_str("The recovery is not approved by enough guardians")
+        };
+        require!(
+            env::block_timestamp() >= challenge_ends_at.0,
+            format!("The recovery can be executed from {}", challenge_ends_at.0)
+        );
+        self.guardians.settings.insert(&account_id, &setting);
+        let amount = U128(self.internal_balance_of(&account_id));
+        require!(amount.0 > 0, "The account has no tokens to recover");
+        // the transfer limits could keep a large balance from ever being recovered
+        self.internal_assert_can_move(&account_id, &new_account_id);
+        self.internal_assert_verified(&new_account_id);
+        self.internal_move(
+            &account_id,
+            &new_account_id,
+            amount.0,
+            Some("recovery".to_string()),
+        );
+
+        SocialRecoveryEvent::RecoveryExecute {
+            account_id,
+            new_account_id,
+            amount,
+        }
+        .emit();
+        amount
+    }
+
+    pub fn guardians_of(&self, account_id: AccountId) -> Option<GuardianSetting> {
+        self.guardians.settings.get(&account_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::limits::TransferLimits;
+    use crate::tests::{current, owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    fn new_account() -> AccountId {
+        "new-account.near".parse().unwrap()
+    }
+
+    /// Registers the new account, and has the owner nominate user1, user2 and the contract
+    /// account as guardians with a threshold of 2. user1 and user2 approve a recovery to the new
+    /// account.
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            social_recovery: true,
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(new_account())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.guardians_set(vec![user1(), user2(), current()], 2);
+
+        for guardian_id in [user1(), user2()].iter() {
+            testing_env!(context.predecessor_account_id(guardian_id.clone()).build());
+            contract.recovery_approve(owner(), new_account());
+        }
+        (contract, context)
+    }
+
+    #[test]
+    fn test_recovery_after_challenge_period() {
+        let (mut contract, mut context) = setup();
+        let recovery = contract.guardians_of(owner()).unwrap().recovery.unwrap();
+        assert_eq!(recovery.challenge_ends_at, Some(U64(CHALLENGE_PERIOD)));
+
+        testing_env!(context.block_timestamp(CHALLENGE_PERIOD).build());
+        assert_eq!(contract.recovery_execute(owner()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(owner()).0, 0);
+        assert_eq!(contract.ft_balance_of(new_account()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.guardians_of(owner()).unwrap().recovery, None);
+    }
+
+    #[should_panic(expected = "There is no pending recovery")]
+    #[test]
+    fn test_transfer_vetoes_recovery() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(owner()).build());
+        contract.ft_transfer(new_account(), U128(1), None);
+        assert_eq!(contract.guardians_of(owner()).unwrap().recovery, None);
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .block_timestamp(CHALLENGE_PERIOD)
+            .build());
+        contract.recovery_execute(owner());
+    }
+
+    #[should_panic(expected = "The recovery can be executed from 604800000000000")]
+    #[test]
+    fn test_execute_panics_during_challenge_period() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.block_timestamp(CHALLENGE_PERIOD - 1).build());
+        contract.recovery_execute(owner());
+    }
+
+    #[should_panic(expected = "Only the guardians of the account can call this method")]
+    #[test]
+    fn test_approve_panics_on_non_guardian() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(new_account()).build());
+        contract.recovery_approve(owner(), new_account());
+    }
+
+    fn other_account() -> AccountId {
+        "other-account.near".parse().unwrap()
+    }
+
+    #[test]
+    fn test_guardian_moves_approval() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(other_account())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        // user1 moving its approval leaves the new account below the threshold
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.recovery_approve(owner(), other_account());
+        let recovery = contract.guardians_of(owner()).unwrap().recovery.unwrap();
+        assert_eq!(recovery.new_account_id, None);
+        assert_eq!(recovery.challenge_ends_at, None);
+
+        testing_env!(context.predecessor_account_id(current()).build());
+        contract.recovery_approve(owner(), other_account());
+        let recovery = contract.guardians_of(owner()).unwrap().recovery.unwrap();
+        assert_eq!(recovery.new_account_id, Some(other_account()));
+
+        testing_env!(context.block_timestamp(CHALLENGE_PERIOD).build());
+        contract.recovery_execute(owner());
+        assert_eq!(contract.ft_balance_of(other_account()).0, TOTAL_SUPPLY);
+    }
+
+    #[should_panic(expected = "A recovery to @new-account.near is already approved")]
+    #[test]
+    fn test_approve_panics_on_other_account_once_approved() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(other_account())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(current())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.recovery_approve(owner(), other_account());
+    }
+
+    #[should_panic(expected = "The token is paused")]
+    #[test]
+    fn test_execute_panics_when_paused() {
+        let (mut contract, mut context) = setup();
+
+        contract.paused = true;
+        testing_env!(context.block_timestamp(CHALLENGE_PERIOD).build());
+        contract.recovery_execute(owner());
+    }
+
+    #[test]
+    fn test_recovery_ignores_transfer_limits() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            social_recovery: true,
+            transfer_limits: Some(TransferLimits {
+                max_transfer: Some(U128(10)),
+                max_daily_outflow: Some(U128(10)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        for account_id in [user1(), new_account()].iter() {
+            testing_env!(context
+                .predecessor_account_id(account_id.clone())
+                .attached_deposit(contract.storage_balance_bounds().min)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), U128(100), None);
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.guardians_set(vec![user2()], 1);
+        testing_env!(context.predecessor_account_id(user2()).build());
+        contract.recovery_approve(user1(), new_account());
+
+        testing_env!(context.block_timestamp(CHALLENGE_PERIOD).build());
+        assert_eq!(contract.recovery_execute(user1()).0, 100);
+        assert_eq!(contract.ft_balance_of(new_account()).0, 100);
+    }
+
+    #[should_panic(expected = "Social recovery is disabled")]
+    #[test]
+    fn test_guardians_set_panics_when_disabled() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.guardians_set(vec![user1()], 1);
+    }
+}
@@ -0,0 +1,614 @@
+/*!
+Optional on-chain transfer history.
+
+Every account that opts in keeps its last `length` transfers in a ring buffer, so small dapps can
+show recent activity without running an indexer. When the history is switched on at
+initialization, `storage_deposit` takes deposits above the registration minimum, up to the cost of
+the longest history, as the storage balance of the account. `ft_history_enable` charges the ring
+buffer against it, `ft_history_disable` releases it, and `storage_withdraw` withdraws what is not
+used.
+
+Whether an account keeps a history is flagged by the top bit of its balance record, which every
+transfer reads anyway, so transfers between accounts which don't opt in cost the same gas as
+without the history. Balances are limited to 2**127 - 1 in return.
+*/
+use near_contract_standards::fungible_token::Balance;
+use near_contract_standards::storage_management::{StorageBalance, StorageManagement};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{Base58CryptoHash, U128, U64};
+use near_sdk::{
+    assert_one_yocto, env, near, require, AccountId, IntoStorageKey, NearToken, Promise,
+    StorageUsage,
+};
+
+use crate::{Contract, ContractExt};
+
+/// Top bit of the balance record of an account, set while the account keeps a history.
+pub(crate) const HISTORY_FLAG: Balance = 1 << 127;
+
+/// Whether the transfer moved tokens into or out of the account.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub enum TransferDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// A single transfer recorded in the history of an account.
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct HistoryEntry {
+    pub counterparty: AccountId,
+    pub amount: U128,
+    pub direction: TransferDirection,
+    /// SHA-256 of the transfer memo, if there was one.
+    pub memo_hash: Option<Base58CryptoHash>,
+    pub block_height: U64,
+}
+
+/// Storage balance of an account above the registration minimum, and its ring buffer settings.
+#[near]
+pub struct HistoryAccount {
+    /// Number of entries the ring buffer holds. Zero while the history is disabled.
+    pub length: u32,
+    /// Total number of transfers recorded since the history was enabled.
+    pub recorded: u64,
+    /// Storage balance above the registration minimum, which pays for this record and the ring
+    /// buffer.
+    pub deposit: NearToken,
+}
+
+#[near]
+pub struct TransferHistory {
+    /// Maximum ring buffer length an account can ask for. Zero disables the history.
+    pub max_length: u32,
+
+    /// AccountID -> storage balance and ring buffer settings.
+    pub accounts: LookupMap<AccountId, HistoryAccount>,
+
+    /// (AccountID, slot) -> recorded transfer.
+    pub entries: LookupMap<(AccountId, u32), HistoryEntry>,
+
+    /// The storage size in bytes for the ring buffer settings of one account.
+    pub account_storage_usage: StorageUsage,
+
+    /// The storage size in bytes for one recorded transfer.
+    pub entry_storage_usage: StorageUsage,
+}
+
+impl TransferHistory {
+    pub fn new<A, E>(accounts_prefix: A, entries_prefix: E, max_length: u32) -> Self
+    where
+        A: IntoStorageKey,
+        E: IntoStorageKey,
+    {
+        let mut this = Self {
+            max_length,
+            accounts: LookupMap::new(accounts_prefix),
+            entries: LookupMap::new(entries_prefix),
+            account_storage_usage: 0,
+            entry_storage_usage: 0,
+        };
+        if this.is_enabled() {
+            this.measure_storage_usage();
+        }
+        this
+    }
+
+    fn measure_storage_usage(&mut self) {
+        let tmp_account_id: AccountId = "a".repeat(64).parse().unwrap();
+
+        let initial_storage_usage = env::storage_usage();
+        self.accounts.insert(
+            &tmp_account_id,
+            &HistoryAccount {
+                length: u32::MAX,
+                recorded: u64::MAX,
+                deposit: NearToken::from_yoctonear(u128::MAX),
+            },
+        );
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.accounts.remove(&tmp_account_id);
+
+        let initial_storage_usage = env::storage_usage();
+        self.entries.insert(
+            &(tmp_account_id.clone(), u32::MAX),
+            &HistoryEntry {
+                counterparty: tmp_account_id.clone(),
+                amount: U128(u128::MAX),
+                direction: TransferDirection::Outgoing,
+                memo_hash: Some([0u8; 32].into()),
+                block_height: U64(u64::MAX),
+            },
+        );
+        self.entry_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.entries.remove(&(tmp_account_id, u32::MAX));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_length > 0
+    }
+
+    /// Storage cost of the settings of an account and of a ring buffer holding `length` entries.
+    pub fn storage_cost(&self, length: u32) -> NearToken {
+        let storage_usage =
+            self.account_storage_usage + self.entry_storage_usage * StorageUsage::from(length);
+        env::storage_byte_cost().saturating_mul(storage_usage.into())
+    }
+
+    /// Storage balance of `account` which its history doesn't use.
+    fn available(&self, account: &HistoryAccount) -> NearToken {
+        account
+            .deposit
+            .saturating_sub(self.storage_cost(account.length))
+    }
+
+    /// Adds `amount` to the storage balance of `account_id`, up to the cost of the longest
+    /// history. Returns the part of `amount` which doesn't fit, all of it when the storage balance
+    /// wouldn't cover the settings of the account.
+    fn internal_deposit(&mut self, account_id: &AccountId, amount: NearToken) -> NearToken {
+        let mut account = self.accounts.get(account_id).unwrap_or(HistoryAccount {
+            length: 0,
+            recorded: 0,
+            deposit: NearToken::from_yoctonear(0),
+        });
+        let total = account.deposit.saturating_add(amount);
+        let deposit = std::cmp::min(total, self.storage_cost(self.max_length));
+        if deposit < self.storage_cost(0) {
+            return amount;
+        }
+        account.deposit = deposit;
+        self.accounts.insert(account_id, &account);
+        total.saturating_sub(deposit)
+    }
+
+    /// Records the transfer in the history of `account_id`, which keeps one.
+    pub fn internal_record(
+        &mut self,
+        account_id: &AccountId,
+        counterparty: &AccountId,
+        amount: u128,
+        direction: TransferDirection,
+        memo: Option<&str>,
+    ) {
+        let mut account = self
+            .accounts
+            .get(account_id)
+            .unwrap_or_else(|| env::panic_str("History settings are missing"));
+        let slot = (account.recorded % u64::from(account.length)) as u32;
+        self.entries.insert(
+            &(account_id.clone(), slot),
+            &HistoryEntry {
+                counterparty: counterparty.clone(),
+                amount: U128(amount),
+                direction,
+                memo_hash: memo.map(|memo| env::sha256_array(memo.as_bytes()).into()),
+                block_height: U64(env::block_height()),
+            },
+        );
+        account.recorded += 1;
+        self.accounts.insert(account_id, &account);
+    }
+
+    /// Removes the recorded transfers of `account_id`, releasing the storage they used.
+    fn internal_clear(&mut self, account_id: &AccountId, account: &mut HistoryAccount) {
+        let used_slots = std::cmp::min(account.recorded, u64::from(account.length)) as u32;
+        for slot in 0..used_slots {
+            self.entries.remove(&(account_id.clone(), slot));
+        }
+        account.length = 0;
+        account.recorded = 0;
+    }
+
+    /// Removes the history of `account_id` and returns its storage balance.
+    pub fn internal_remove(&mut self, account_id: &AccountId) -> Option<NearToken> {
+        let mut account = self.accounts.remove(account_id)?;
+        self.internal_clear(account_id, &mut account);
+        Some(account.deposit)
+    }
+
+    /// Returns up to `limit` entries of `account_id`, newest first, skipping the `from_index`
+    /// most recent ones.
+    pub fn history_of(
+        &self,
+        account_id: &AccountId,
+        from_index: u64,
+        limit: u32,
+    ) -> Vec<HistoryEntry> {
+        let Some(account) = self.accounts.get(account_id) else {
+            return vec![];
+        };
+        if account.length == 0 {
+            return vec![];
+        }
+        let retained = std::cmp::min(account.recorded, u64::from(account.length));
+        (from_index..retained)
+            .take(limit as usize)
+            .map(|offset| {
+                let sequence = account.recorded - 1 - offset;
+                let slot = (sequence % u64::from(account.length)) as u32;
+                self.entries
+                    .get(&(account_id.clone(), slot))
+                    .unwrap_or_else(|| env::panic_str("History entry is missing"))
+            })
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Same as `FungibleToken::storage_deposit`, keeping the deposit above the registration
+    /// minimum as the storage balance of the history of the account, unless `registration_only`.
+    pub(crate) fn internal_history_storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let mut refund = env::attached_deposit();
+        if !self.token.accounts.contains_key(&account_id) {
+            let min_balance = self.token.storage_balance_bounds().min;
+            require!(
+                refund >= min_balance,
+                "The attached deposit is less than the minimum storage balance"
+            );
+            self.token.internal_register_account(&account_id);
+            refund = refund.saturating_sub(min_balance);
+        }
+        if !registration_only.unwrap_or(false) {
+            refund = self.history.internal_deposit(&account_id, refund);
+        }
+        if !refund.is_zero() {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        self.internal_history_storage_balance_of(&account_id)
+            .unwrap()
+    }
+
+    /// Same as `FungibleToken::storage_withdraw`, withdrawing the storage balance which the
+    /// history of the predecessor doesn't use.
+    pub(crate) fn internal_history_storage_withdraw(
+        &mut self,
+        amount: Option<NearToken>,
+    ) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let storage_balance = self
+            .internal_history_storage_balance_of(&account_id)
+            .unwrap_or_else(|| {
+                env::panic_str(format!("The account {} is not registered", account_id).as_str())
+            });
+        let amount = amount.unwrap_or(storage_balance.available);
+        require!(
+            amount <= storage_balance.available,
+            "The amount is greater than the available storage balance"
+        );
+        if amount.is_zero() {
+            return storage_balance;
+        }
+        let mut account = self.history.accounts.get(&account_id).unwrap();
+        account.deposit = account.deposit.saturating_sub(amount);
+        self.history.accounts.insert(&account_id, &account);
+        Promise::new(account_id.clone()).transfer(amount);
+        self.internal_history_storage_balance_of(&account_id)
+            .unwrap()
+    }
+
+    pub(crate) fn internal_history_storage_balance_of(
+        &self,
+        account_id: &AccountId,
+    ) -> Option<StorageBalance> {
+        let min_balance = self.token.storage_balance_bounds().min;
+        if !self.token.accounts.contains_key(account_id) {
+            return None;
+        }
+        Some(match self.history.accounts.get(account_id) {
+            Some(account) => StorageBalance {
+                total: min_balance.saturating_add(account.deposit),
+                available: self.history.available(&account),
+            },
+            None => StorageBalance {
+                total: min_balance,
+                available: NearToken::from_yoctonear(0),
+            },
+        })
+    }
+}
+
+#[near]
+impl Contract {
+    /// Opts the predecessor into keeping its last `length` transfers on chain. The storage
+    /// balance of the predecessor above the registration minimum must cover
+    /// `ft_history_storage_cost(length)`, see `storage_deposit`.
+    #[payable]
+    pub fn ft_history_enable(&mut self, length: u32) {
+        assert_one_yocto();
+        require!(self.history.is_enabled(), "Transfer history is disabled");
+        require!(
+            length > 0 && length <= self.history.max_length,
+            format!(
+                "History length should be between 1 and {}",
+                self.history.max_length
+            )
+        );
+        let account_id = env::predecessor_account_id();
+        let (balance, keeps_history) = self.internal_unwrap_balance_record(&account_id);
+        require!(!keeps_history, "Transfer history is already enabled");
+
+        let storage_cost = self.history.storage_cost(length);
+        let deposit = self
+            .history
+            .accounts
+            .get(&account_id)
+            .map_or(NearToken::from_yoctonear(0), |account| account.deposit);
+        require!(
+            deposit >= storage_cost,
+            format!(
+                "Requires a storage balance of at least {} above the registration minimum",
+                storage_cost.exact_amount_display()
+            )
+        );
+        self.history.accounts.insert(
+            &account_id,
+            &HistoryAccount {
+                length,
+                recorded: 0,
+                deposit,
+            },
+        );
+        self.internal_set_balance_record(&account_id, balance, true);
+    }
+
+    /// Removes the history of the predecessor, whose storage balance becomes available to
+    /// `storage_withdraw`.
+    #[payable]
+    pub fn ft_history_disable(&mut self) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let (balance, keeps_history) = self.internal_unwrap_balance_record(&account_id);
+        require!(keeps_history, "Transfer history is not enabled");
+        let mut account = self.history.accounts.get(&account_id).unwrap();
+        self.history.internal_clear(&account_id, &mut account);
+        self.history.accounts.insert(&account_id, &account);
+        self.internal_set_balance_record(&account_id, balance, false);
+    }
+
+    /// Returns up to `limit` (default 10) recorded transfers of `account_id`, newest first,
+    /// skipping the `from_index` most recent ones.
+    pub fn ft_history_of(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<HistoryEntry> {
+        self.history.history_of(
+            &account_id,
+            from_index.map_or(0, |from_index| from_index.0),
+            limit.unwrap_or(10),
+        )
+    }
+
+    /// Storage balance above the registration minimum required by `ft_history_enable` for a
+    /// history of `length` transfers.
+    pub fn ft_history_storage_cost(&self, length: u32) -> NearToken {
+        self.history.storage_cost(length)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            max_history_length: Some(3),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        (contract, context)
+    }
+
+    /// Deposits the storage of a history of `length` transfers for `account_id` and enables it.
+    fn enable(
+        contract: &mut Contract,
+        context: &mut VMContextBuilder,
+        account_id: AccountId,
+        length: u32,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(account_id.clone())
+            .attached_deposit(contract.ft_history_storage_cost(length))
+            .build());
+        contract.storage_deposit(None, None);
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_history_enable(length);
+    }
+
+    #[test]
+    fn test_history_records_both_sides() {
+        let (mut contract, mut context) = setup();
+        enable(&mut contract, &mut context, owner(), 3);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .block_height(42)
+            .build());
+        contract.ft_transfer(user1(), 10.into(), Some("invoice".to_string()));
+
+        let history = contract.ft_history_of(owner(), None, None);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].counterparty, user1());
+        assert_eq!(history[0].amount.0, 10);
+        assert_eq!(history[0].direction, TransferDirection::Outgoing);
+        assert_eq!(
+            history[0].memo_hash,
+            Some(env::sha256_array(b"invoice").into())
+        );
+        assert_eq!(history[0].block_height.0, 42);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - 10);
+
+        // user1 didn't opt in, so it has no history settings either
+        assert!(contract.ft_history_of(user1(), None, None).is_empty());
+        assert!(contract.history.accounts.get(&user1()).is_none());
+    }
+
+    #[test]
+    fn test_history_keeps_last_entries() {
+        let (mut contract, mut context) = setup();
+        enable(&mut contract, &mut context, user1(), 2);
+
+        for amount in 1..=5u128 {
+            testing_env!(context
+                .predecessor_account_id(owner())
+                .attached_deposit(NearToken::from_yoctonear(1))
+                .build());
+            contract.ft_transfer(user1(), amount.into(), None);
+        }
+        assert_eq!(contract.ft_balance_of(user1()).0, 15);
+
+        let history = contract.ft_history_of(user1(), None, None);
+        let amounts: Vec<u128> = history.iter().map(|entry| entry.amount.0).collect();
+        assert_eq!(amounts, vec![5, 4]);
+        assert!(history
+            .iter()
+            .all(|entry| entry.direction == TransferDirection::Incoming));
+
+        let history = contract.ft_history_of(user1(), Some(U64(1)), Some(5));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].amount.0, 4);
+    }
+
+    #[test]
+    fn test_history_is_charged_to_storage_balance() {
+        let (mut contract, mut context) = setup();
+        let min_balance = contract.storage_balance_bounds().min;
+        assert_eq!(
+            contract.storage_balance_bounds().max,
+            Some(min_balance.saturating_add(contract.ft_history_storage_cost(3)))
+        );
+        enable(&mut contract, &mut context, user1(), 2);
+
+        let storage_balance = contract.storage_balance_of(user1()).unwrap();
+        assert_eq!(
+            storage_balance.total,
+            min_balance.saturating_add(contract.ft_history_storage_cost(2))
+        );
+        assert!(storage_balance.available.is_zero());
+
+        // the disabled history releases the storage balance of the ring buffer
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_history_disable();
+        let released = contract
+            .ft_history_storage_cost(2)
+            .saturating_sub(contract.ft_history_storage_cost(0));
+        assert_eq!(
+            contract.storage_balance_of(user1()).unwrap().available,
+            released
+        );
+        let storage_balance = contract.storage_withdraw(None);
+        assert_eq!(
+            storage_balance.total,
+            min_balance.saturating_add(contract.ft_history_storage_cost(0))
+        );
+        assert!(storage_balance.available.is_zero());
+    }
+
+    #[test]
+    fn test_history_disable() {
+        let (mut contract, mut context) = setup();
+        enable(&mut contract, &mut context, user1(), 3);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), 10.into(), None);
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_history_disable();
+
+        assert!(contract.ft_history_of(user1(), None, None).is_empty());
+        assert!(contract.history.entries.get(&(user1(), 0)).is_none());
+        assert_eq!(contract.ft_balance_of(user1()).0, 10);
+
+        // the balance can be unregistered once the history flag is cleared
+        contract.ft_transfer(owner(), 10.into(), None);
+        assert!(contract.storage_unregister(None));
+        assert!(contract.history.accounts.get(&user1()).is_none());
+    }
+
+    #[test]
+    fn test_unregister_removes_history() {
+        let (mut contract, mut context) = setup();
+        enable(&mut contract, &mut context, user1(), 3);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), 10.into(), None);
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract.history.accounts.get(&user1()).is_none());
+        assert!(contract.history.entries.get(&(user1(), 0)).is_none());
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY - 10);
+    }
+
+    #[should_panic(expected = "Requires a storage balance of at least")]
+    #[test]
+    fn test_history_enable_panics_on_insufficient_storage_balance() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.ft_history_storage_cost(2))
+            .build());
+        contract.storage_deposit(None, None);
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_history_enable(3);
+    }
+
+    #[should_panic(expected = "History length should be between 1 and 3")]
+    #[test]
+    fn test_history_enable_panics_on_length_above_max() {
+        let (mut contract, mut context) = setup();
+
+        enable(&mut contract, &mut context, user1(), 4);
+    }
+
+    #[should_panic(expected = "Transfer history is disabled")]
+    #[test]
+    fn test_history_enable_panics_when_disabled_at_init() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_history_enable(1);
+    }
+}
@@ -0,0 +1,320 @@
+/*!
+Hashed time-lock contracts (HTLC) for cross-chain atomic swaps.
+
+The sender locks tokens for the receiver under the SHA-256 hash of a secret preimage. Anyone who
+knows the preimage can claim the tokens for the receiver before the timelock expires, revealing
+the preimage in the `htlc_claim` event for the other side of the swap. After the timelock the
+sender can take the tokens back.
+
+Locked tokens are part of the total supply, but can't be spent by either party until the HTLC is
+claimed or refunded. The sender pays for the storage of the HTLC with the deposit attached to
+`htlc_lock`, and gets it back when the HTLC is claimed or refunded.
+*/
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::{Base58CryptoHash, Base64VecU8, U128, U64};
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, CryptoHash, IntoStorageKey};
+
+use crate::{Contract, ContractExt};
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct Htlc {
+    pub id: U64,
+    pub sender_id: AccountId,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    /// SHA-256 hash of the preimage that unlocks the tokens.
+    pub hashlock: Base58CryptoHash,
+    /// Block timestamp in nanoseconds after which the sender can take the tokens back.
+    pub timelock: U64,
+}
+
+#[near(event_json(standard = "ft-htlc"))]
+pub enum HtlcEvent {
+    #[event_version("1.0.0")]
+    HtlcLock {
+        id: U64,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        hashlock: Base58CryptoHash,
+        timelock: U64,
+    },
+    #[event_version("1.0.0")]
+    HtlcClaim {
+        id: U64,
+        receiver_id: AccountId,
+        amount: U128,
+        hashlock: Base58CryptoHash,
+        preimage: Base64VecU8,
+    },
+    #[event_version("1.0.0")]
+    HtlcRefund {
+        id: U64,
+        sender_id: AccountId,
+        amount: U128,
+        hashlock: Base58CryptoHash,
+    },
+}
+
+#[near]
+pub struct Htlcs {
+    /// Id assigned to the next HTLC.
+    pub next_id: u64,
+
+    /// HTLC id -> HTLC.
+    pub htlcs: UnorderedMap<u64, Htlc>,
+}
+
+impl Htlcs {
+    pub fn new<S>(prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            next_id: 0,
+            htlcs: UnorderedMap::new(prefix),
+        }
+    }
+
+    fn internal_unwrap_htlc(&self, id: u64) -> Htlc {
+        self.htlcs
+            .get(&id)
+            .unwrap_or_else(|| env::panic_str(format!("HTLC {} doesn't exist", id).as_str()))
+    }
+}
+
+impl Contract {
+    /// Removes the HTLC and credits its tokens to `account_id`.
+    fn internal_settle_htlc(&mut self, htlc: &Htlc, account_id: &AccountId) {
+        let initial_storage_usage = env::storage_usage();
+        self.htlcs.htlcs.remove(&htlc.id.0);
+        self.internal_before_unlock(&htlc.sender_id, account_id, htlc.amount.0);
+        self.internal_unlock_verified(&htlc.sender_id, account_id, htlc.amount.0);
+        self.internal_refund_storage(initial_storage_usage, htlc.sender_id.clone());
+    }
+}
+
+#[near]
+impl Contract {
+    /// Locks `amount` of the predecessor's tokens for `receiver_id` until the preimage of
+    /// `hashlock` is revealed or `timelock` passes. The attached deposit must cover the storage of
+    /// the HTLC, the excess is refunded. Returns the id of the HTLC.
+    #[payable]
+    pub fn htlc_lock(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        hashlock: Base58CryptoHash,
+        timelock: U64,
+    ) -> U64 {
+        let initial_storage_usage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        require!(
+            sender_id != receiver_id,
+            "Sender and receiver should be different"
+        );
+        require!(
+            timelock.0 > env::block_timestamp(),
+            "Timelock should be in the future"
+        );
+        self.token.internal_unwrap_balance_of(&receiver_id);
+        self.internal_before_lock(&sender_id, &receiver_id, amount.0);
+        self.internal_lock(&sender_id, amount.0);
+
+        let htlc = Htlc {
+            id: U64(self.htlcs.next_id),
+            sender_id,
+            receiver_id,
+            amount,
+            hashlock,
+            timelock,
+        };
+        self.htlcs.next_id += 1;
+        self.htlcs.htlcs.insert(&htlc.id.0, &htlc);
+        self.internal_charge_storage(initial_storage_usage);
+
+        HtlcEvent::HtlcLock {
+            id: htlc.id,
+            sender_id: htlc.sender_id,
+            receiver_id: htlc.receiver_id,
+            amount: htlc.amount,
+            hashlock: htlc.hashlock,
+            timelock: htlc.timelock,
+        }
+        .emit();
+
+        htlc.id
+    }
+
+    /// Releases the tokens to the receiver if the SHA-256 hash of `preimage` matches the hashlock.
+    /// Can be called by anyone before the timelock expires.
+    pub fn htlc_claim(&mut self, id: U64, preimage: Base64VecU8) {
+        let htlc = self.htlcs.internal_unwrap_htlc(id.0);
+        require!(
+            env::block_timestamp() < htlc.timelock.0,
+            "The HTLC has expired"
+        );
+        let hashlock: CryptoHash = htlc.hashlock.into();
+        require!(
+            env::sha256_array(&preimage.0) == hashlock,
+            "The preimage doesn't match the hashlock"
+        );
+
+        self.internal_settle_htlc(&htlc, &htlc.receiver_id);
+
+        HtlcEvent::HtlcClaim {
+            id,
+            receiver_id: htlc.receiver_id,
+            amount: htlc.amount,
+            hashlock: htlc.hashlock,
+            preimage,
+        }
+        .emit();
+    }
+
+    /// Returns the tokens to the sender once the timelock has expired. Can only be called by the
+    /// sender.
+    #[payable]
+    pub fn htlc_refund(&mut self, id: U64) {
+        assert_one_yocto();
+        let htlc = self.htlcs.internal_unwrap_htlc(id.0);
+        require!(
+            env::predecessor_account_id() == htlc.sender_id,
+            "Only the sender can refund the HTLC"
+        );
+        require!(
+            env::block_timestamp() >= htlc.timelock.0,
+            "The HTLC hasn't expired yet"
+        );
+
+        self.internal_settle_htlc(&htlc, &htlc.sender_id);
+
+        HtlcEvent::HtlcRefund {
+            id,
+            sender_id: htlc.sender_id,
+            amount: htlc.amount,
+            hashlock: htlc.hashlock,
+        }
+        .emit();
+    }
+
+    pub fn htlc(&self, id: U64) -> Option<Htlc> {
+        self.htlcs.htlcs.get(&id.0)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const AMOUNT: u128 = 1_000;
+    const TIMELOCK: u64 = 1_000_000;
+    const PREIMAGE: &[u8] = b"secret";
+
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.htlc_lock(
+            user1(),
+            AMOUNT.into(),
+            env::sha256_array(PREIMAGE).into(),
+            U64(TIMELOCK),
+        );
+
+        (contract, context)
+    }
+
+    fn call_at(context: &mut VMContextBuilder, account_id: AccountId, timestamp: u64) {
+        testing_env!(context
+            .predecessor_account_id(account_id)
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .block_timestamp(timestamp)
+            .build());
+    }
+
+    #[test]
+    fn test_lock() {
+        let (contract, _) = setup();
+
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - AMOUNT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.htlc(U64(0)).unwrap().amount.0, AMOUNT);
+    }
+
+    #[test]
+    fn test_claim() {
+        let (mut contract, mut context) = setup();
+
+        // anyone knowing the preimage can claim for the receiver
+        call_at(&mut context, user2(), TIMELOCK - 1);
+        contract.htlc_claim(U64(0), PREIMAGE.to_vec().into());
+
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+        assert!(contract.htlc(U64(0)).is_none());
+    }
+
+    #[should_panic(expected = "The preimage doesn't match the hashlock")]
+    #[test]
+    fn test_claim_panics_on_wrong_preimage() {
+        let (mut contract, mut context) = setup();
+
+        call_at(&mut context, user1(), 0);
+        contract.htlc_claim(U64(0), b"guess".to_vec().into());
+    }
+
+    #[should_panic(expected = "The HTLC has expired")]
+    #[test]
+    fn test_claim_panics_after_timelock() {
+        let (mut contract, mut context) = setup();
+
+        call_at(&mut context, user1(), TIMELOCK);
+        contract.htlc_claim(U64(0), PREIMAGE.to_vec().into());
+    }
+
+    #[test]
+    fn test_refund() {
+        let (mut contract, mut context) = setup();
+
+        call_at(&mut context, owner(), TIMELOCK);
+        contract.htlc_refund(U64(0));
+
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+        assert!(contract.htlc(U64(0)).is_none());
+    }
+
+    #[should_panic(expected = "The HTLC hasn't expired yet")]
+    #[test]
+    fn test_refund_panics_before_timelock() {
+        let (mut contract, mut context) = setup();
+
+        call_at(&mut context, owner(), TIMELOCK - 1);
+        contract.htlc_refund(U64(0));
+    }
+
+    #[should_panic(expected = "The account doesn't have enough balance")]
+    #[test]
+    fn test_locked_amount_is_not_spendable() {
+        let (mut contract, mut context) = setup();
+
+        call_at(&mut context, owner(), 0);
+        contract.ft_transfer(user1(), (TOTAL_SUPPLY - AMOUNT + 1).into(), None);
+    }
+}
@@ -0,0 +1,396 @@
+/*!
+Interest-bearing mode.
+
+When switched on at initialization, every balance accrues simple interest at the APR set by the
+owner, compounded whenever the account is touched: on transfers, locks and unlocks, and on
+`ft_accrue`. `ft_balance_of` includes the interest accrued since the last touch, and
+`ft_total_supply` includes the interest accrued by all the balances. Accrued interest is minted
+with an `ft_mint` event when the account is touched.
+
+Rounding rules:
+  - The interest of an account is rounded down at every touch.
+  - The total supply includes the interest of all balances rounded down as a whole, so it can
+    exceed the sum of the balances by less than one unit per account with accrued interest, and
+    it drops by at most one unit when an account is touched.
+
+Tokens locked by the contract (escrows, streams, HTLCs and the bridge) don't accrue interest.
+The registration deposit of `storage_deposit` covers the storage of the accrual checkpoint of the
+account.
+*/
+use near_contract_standards::fungible_token::events::FtMint;
+use near_contract_standards::fungible_token::{Balance, FungibleTokenCore};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, log, near, AccountId, IntoStorageKey, StorageUsage};
+
+use crate::math::{to_u128, U256};
+use crate::{Contract, ContractExt};
+
+const BASIS_POINTS: u128 = 10_000;
+const NANOS_PER_YEAR: u128 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+#[near]
+pub struct Interest {
+    /// Annual rate in basis points.
+    pub apr: u32,
+
+    /// Integral of the APR over time, in basis points times nanoseconds, at `updated_at`.
+    pub rate_integral: u128,
+
+    /// Block timestamp in nanoseconds of the last APR change.
+    pub updated_at: u64,
+
+    /// AccountId -> rate integral at the last touch of the account.
+    pub checkpoints: LookupMap<AccountId, u128>,
+
+    /// Sum of the balances of all the accounts.
+    pub total_balance: Balance,
+
+    /// Sum of the balances times the rate integral at their checkpoints, as a little-endian
+    /// 256-bit integer.
+    pub total_weighted_balance: [u64; 4],
+
+    /// The storage size in bytes for the checkpoint of one account.
+    pub checkpoint_storage_usage: StorageUsage,
+}
+
+impl Interest {
+    pub fn new<S>(prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        let mut this = Self {
+            apr: 0,
+            rate_integral: 0,
+            updated_at: env::block_timestamp(),
+            checkpoints: LookupMap::new(prefix),
+            total_balance: 0,
+            total_weighted_balance: [0; 4],
+            checkpoint_storage_usage: 0,
+        };
+        let tmp_account_id: AccountId = "a".repeat(64).parse().unwrap();
+        let initial_storage_usage = env::storage_usage();
+        this.checkpoints.insert(&tmp_account_id, &u128::MAX);
+        this.checkpoint_storage_usage = env::storage_usage() - initial_storage_usage;
+        this.checkpoints.remove(&tmp_account_id);
+        this
+    }
+
+    /// Integral of the APR over time at the current block timestamp.
+    fn current_rate_integral(&self) -> u128 {
+        let elapsed = u128::from(env::block_timestamp().saturating_sub(self.updated_at));
+        self.rate_integral
+            .checked_add(u128::from(self.apr) * elapsed)
+            .unwrap_or_else(|| env::panic_str("Rate overflow"))
+    }
+
+    /// Interest accrued by `balance` since its checkpoint, rounded down.
+    fn accrued(&self, balance: Balance, checkpoint: u128) -> Balance {
+        let rate = U256::from(self.current_rate_integral() - checkpoint);
+        to_u128(U256::from(balance) * rate / U256::from(BASIS_POINTS * NANOS_PER_YEAR))
+    }
+
+    /// Interest accrued by all the balances since their checkpoints, rounded down.
+    fn total_accrued(&self) -> Balance {
+        let weighted = U256::from(self.total_balance) * U256::from(self.current_rate_integral())
+            - U256(self.total_weighted_balance);
+        to_u128(weighted / U256::from(BASIS_POINTS * NANOS_PER_YEAR))
+    }
+
+    fn add_to_totals(&mut self, balance: Balance, checkpoint: u128) {
+        self.total_balance += balance;
+        let weighted =
+            U256(self.total_weighted_balance) + U256::from(balance) * U256::from(checkpoint);
+        self.total_weighted_balance = weighted.0;
+    }
+
+    fn remove_from_totals(&mut self, balance: Balance, checkpoint: u128) {
+        self.total_balance -= balance;
+        let weighted =
+            U256(self.total_weighted_balance) - U256::from(balance) * U256::from(checkpoint);
+        self.total_weighted_balance = weighted.0;
+    }
+}
+
+impl Contract {
+    /// Credits the interest accrued by `account_id` and takes its balance out of the totals.
+    /// Must be followed by `internal_checkpoint` once the balance of the account is updated.
+    pub(crate) fn internal_accrue(&mut self, account_id: &AccountId) {
+        if self.interest.is_none() {
+            return;
+        }
+        let Some((balance, _)) = self.internal_balance_record(account_id) else {
+            return;
+        };
+        let interest = self.interest.as_mut().unwrap();
+        let Some(checkpoint) = interest.checkpoints.get(account_id) else {
+            return;
+        };
+        let accrued = interest.accrued(balance, checkpoint);
+        interest.remove_from_totals(balance, checkpoint);
+        interest.checkpoints.remove(account_id);
+        if accrued > 0 {
+            self.internal_deposit_shares(account_id, accrued);
+            FtMint {
+                owner_id: account_id,
+                amount: accrued.into(),
+                memo: Some("interest"),
+            }
+            .emit();
+        }
+    }
+
+    /// Starts accruing interest on the current balance of `account_id`.
+    pub(crate) fn internal_checkpoint(&mut self, account_id: &AccountId) {
+        if self.interest.is_none() {
+            return;
+        }
+        let Some((balance, _)) = self.internal_balance_record(account_id) else {
+            return;
+        };
+        let interest = self.interest.as_mut().unwrap();
+        let checkpoint = interest.current_rate_integral();
+        interest.checkpoints.insert(account_id, &checkpoint);
+        interest.add_to_totals(balance, checkpoint);
+    }
+
+    /// Balance of `account_id` including the interest accrued since the last touch.
+    pub(crate) fn internal_balance_with_interest(&self, account_id: &AccountId) -> Balance {
+        let (balance, _) = self
+            .internal_balance_record(account_id)
+            .unwrap_or((0, false));
+        let accrued = self.interest.as_ref().map_or(0, |interest| {
+            interest
+                .checkpoints
+                .get(account_id)
+                .map_or(0, |checkpoint| interest.accrued(balance, checkpoint))
+        });
+        balance + accrued
+    }
+
+    /// Interest accrued by all the balances since their last touch.
+    pub(crate) fn internal_total_accrued(&self) -> Balance {
+        self.interest
+            .as_ref()
+            .map_or(0, |interest| interest.total_accrued())
+    }
+
+    pub(crate) fn internal_set_apr(&mut self, apr: u32) {
+        let interest = self.internal_unwrap_interest();
+        interest.rate_integral = interest.current_rate_integral();
+        interest.updated_at = env::block_timestamp();
+        log!("APR changed from {} to {} basis points", interest.apr, apr);
+        interest.apr = apr;
+    }
+
+    fn internal_unwrap_interest(&mut self) -> &mut Interest {
+        self.interest
+            .as_mut()
+            .unwrap_or_else(|| env::panic_str("The token is not interest-bearing"))
+    }
+}
+
+#[near]
+impl Contract {
+    /// Credits the interest accrued by `account_id` to its balance. Returns the new balance.
+    pub fn ft_accrue(&mut self, account_id: AccountId) -> U128 {
+        self.internal_unwrap_interest();
+        self.token.internal_unwrap_balance_of(&account_id);
+        self.internal_accrue(&account_id);
+        self.internal_checkpoint(&account_id);
+        self.ft_balance_of(account_id)
+    }
+
+    /// Sets the annual interest rate in basis points. Interest accrued so far keeps the old rate.
+    /// Can only be called by the owner, without a council or timelock.
+    #[payable]
+    pub fn interest_set_apr(&mut self, apr: u32) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        self.internal_set_apr(apr);
+    }
+
+    /// Annual interest rate in basis points.
+    pub fn interest_apr(&self) -> u32 {
+        self.interest.as_ref().map_or(0, |interest| interest.apr)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+    const YEAR: u64 = 365 * NANOS_PER_DAY;
+
+    /// Deterministic pseudo-random numbers for the property tests.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (self.0 >> 33) % bound
+        }
+    }
+
+    fn setup(apr: u32) -> (Contract, VMContextBuilder) {
+        // the property tests set up a new contract on each iteration
+        near_sdk::mock::with_mocked_blockchain(|blockchain| drop(blockchain.take_storage()));
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            interest_bearing: true,
+            ..Default::default()
+        });
+
+        for account_id in [user1(), user2()] {
+            testing_env!(context
+                .predecessor_account_id(account_id)
+                .attached_deposit(contract.storage_balance_bounds().min)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.interest_set_apr(apr);
+
+        (contract, context)
+    }
+
+    fn call_at(context: &mut VMContextBuilder, account_id: AccountId, timestamp: u64) {
+        testing_env!(context
+            .predecessor_account_id(account_id)
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .block_timestamp(timestamp)
+            .build());
+    }
+
+    fn balances(contract: &Contract) -> u128 {
+        [owner(), user1(), user2()]
+            .iter()
+            .map(|account_id| contract.ft_balance_of(account_id.clone()).0)
+            .sum()
+    }
+
+    #[test]
+    fn test_balance_includes_interest() {
+        // 10%
+        let (mut contract, mut context) = setup(1_000);
+
+        call_at(&mut context, user1(), YEAR);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY * 11 / 10);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY * 11 / 10);
+
+        // accruing compounds the interest
+        assert_eq!(contract.ft_accrue(owner()).0, TOTAL_SUPPLY * 11 / 10);
+        call_at(&mut context, user1(), 2 * YEAR);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY * 121 / 100);
+    }
+
+    #[test]
+    fn test_apr_change_keeps_accrued_interest() {
+        let (mut contract, mut context) = setup(1_000);
+
+        call_at(&mut context, owner(), YEAR);
+        contract.interest_set_apr(500);
+        call_at(&mut context, owner(), 2 * YEAR);
+
+        // 10% for the first year and 5% for the second one, without compounding
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY * 115 / 100);
+        assert_eq!(contract.interest_apr(), 500);
+    }
+
+    #[test]
+    fn test_locked_tokens_dont_accrue_interest() {
+        let (mut contract, mut context) = setup(1_000);
+
+        call_at(&mut context, owner(), 0);
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        contract.htlc_lock(
+            user1(),
+            U128(TOTAL_SUPPLY / 2),
+            env::sha256_array(b"secret").into(),
+            (2 * YEAR).into(),
+        );
+
+        call_at(&mut context, user1(), YEAR);
+        assert_eq!(
+            contract.ft_balance_of(owner()).0,
+            TOTAL_SUPPLY / 2 * 11 / 10
+        );
+        assert_eq!(
+            contract.ft_total_supply().0,
+            TOTAL_SUPPLY / 2 * 11 / 10 + TOTAL_SUPPLY / 2
+        );
+    }
+
+    #[test]
+    fn test_total_supply_tracks_balances_over_long_spans() {
+        let mut rng = Lcg(7);
+        for _ in 0..10 {
+            let (mut contract, mut context) = setup(rng.next(5_000) as u32);
+            let accounts = [owner(), user1(), user2()];
+            let mut timestamp = 0;
+            for _ in 0..20 {
+                // up to 5 years between the touches
+                timestamp += rng.next(5 * YEAR);
+                let sender_id = accounts[rng.next(3) as usize].clone();
+                let receiver_id = accounts[rng.next(3) as usize].clone();
+                call_at(&mut context, sender_id.clone(), timestamp);
+
+                let balance = contract.ft_balance_of(sender_id.clone()).0;
+                if sender_id != receiver_id && balance > 0 {
+                    let amount = u128::from(rng.next(u64::MAX)) % balance + 1;
+                    contract.ft_transfer(receiver_id, amount.into(), None);
+                } else {
+                    assert_eq!(contract.ft_accrue(sender_id.clone()).0, balance);
+                }
+
+                let total_supply = contract.ft_total_supply().0;
+                let balances = balances(&contract);
+                assert!(balances <= total_supply);
+                assert!(total_supply < balances + accounts.len() as u128);
+
+                // balances never decrease with time
+                timestamp += YEAR;
+                call_at(&mut context, sender_id, timestamp);
+                assert!(balances <= self::balances(&contract));
+            }
+        }
+    }
+
+    #[test]
+    fn test_accrued_interest_matches_simple_interest() {
+        let mut rng = Lcg(42);
+        for _ in 0..50 {
+            let apr = rng.next(10_000) as u32;
+            let elapsed = rng.next(100 * YEAR);
+            let (contract, mut context) = setup(apr);
+
+            call_at(&mut context, owner(), elapsed);
+            let expected = TOTAL_SUPPLY
+                + TOTAL_SUPPLY * u128::from(apr) * u128::from(elapsed)
+                    / (BASIS_POINTS * NANOS_PER_YEAR);
+            assert_eq!(contract.ft_balance_of(owner()).0, expected);
+            assert_eq!(contract.ft_total_supply().0, expected);
+        }
+    }
+
+    #[should_panic(expected = "The token is not interest-bearing")]
+    #[test]
+    fn test_accrue_panics_when_not_interest_bearing() {
+        let (mut contract, _) = setup_with_config(ContractConfig::default());
+
+        contract.ft_accrue(owner());
+    }
+}
@@ -0,0 +1,648 @@
+/*!
+KYC gating of the receivers of transfers, for permissioned deployments.
+
+When initialized with `new_with_config` and `kyc`, tokens are only credited to verified accounts.
+This covers transfers, mints by admin actions, migrations, staking rewards and transfers of stakes,
+`bridge_in`, flash loans, recovered and forcibly transferred tokens, and the receivers of escrows,
+streams and HTLCs. Tokens given back to the account they came from, like refunds of locked or
+unstaked tokens, and the initial allocations are not gated. The owner is always considered
+verified. The KYC status comes from either:
+  - `Internal`: a registry kept by the contract, written by the accounts with the `Attestor` role.
+    The attestor pays for the storage of a verified account with the deposit attached to
+    `kyc_verify`, and gets it back on `kyc_revoke`. Unverified receivers make the transfer fail,
+    and the receivers of escrows, streams and HTLCs are checked when the tokens are locked.
+  - `External`: the `is_verified` view of another contract, which can't be asked synchronously.
+    `ft_transfer` and `ft_transfer_call` take the amount from the sender and ask the registry.
+    `kyc_resolve_transfer` and `kyc_resolve_transfer_call` then finish the transfer, or give the
+    amount back to the sender when the receiver isn't verified or the registry fails. Tokens
+    released from escrows, streams and HTLCs go through the registry the same way, and go back to
+    the sender if their receiver isn't verified. The bridge, force transfers and social recovery
+    can't be switched on with an external registry, nor can staking rewards be emitted. Mints,
+    migrations, flash loans, recovered tokens and transfers of stakes can only credit the owner.
+*/
+use near_contract_standards::fungible_token::events::{FtBurn, FtTransfer};
+use near_contract_standards::fungible_token::Balance;
+use near_sdk::collections::LookupSet;
+use near_sdk::json_types::U128;
+use near_sdk::serde_json;
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, log, near, require, AccountId, Gas, IntoStorageKey,
+    Promise, PromiseOrValue, PromiseResult,
+};
+
+use crate::roles::Role;
+use crate::{Contract, ContractExt, GAS_FOR_FT_TRANSFER_CALL};
+
+const GAS_FOR_IS_VERIFIED: Gas = Gas::from_tgas(5);
+const GAS_FOR_RESOLVE_KYC_TRANSFER: Gas = Gas::from_tgas(10);
+/// Gas asking the external registry adds to a transfer call, whose callback then calls the
+/// receiver like `ft_transfer_call`.
+pub(crate) const GAS_FOR_KYC_CHECK: Gas =
+    Gas::from_gas(GAS_FOR_IS_VERIFIED.as_gas() + GAS_FOR_FT_TRANSFER_CALL.as_gas());
+
+#[ext_contract(ext_kyc_registry)]
+pub trait KycRegistry {
+    fn is_verified(&self, account_id: AccountId) -> bool;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub enum KycMode {
+    Internal,
+    External { registry_id: AccountId },
+}
+
+#[near]
+pub struct Kyc {
+    /// Transfers are not gated when not set.
+    pub mode: Option<KycMode>,
+
+    /// Accounts verified by an attestor, in the `Internal` mode.
+    pub verified: LookupSet<AccountId>,
+}
+
+impl Kyc {
+    pub fn new<S>(prefix: S, mode: Option<KycMode>) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            mode,
+            verified: LookupSet::new(prefix),
+        }
+    }
+
+    /// The external registry, in the `External` mode.
+    pub(crate) fn registry_id(&self) -> Option<AccountId> {
+        match &self.mode {
+            Some(KycMode::External { registry_id }) => Some(registry_id.clone()),
+            _ => None,
+        }
+    }
+
+    fn assert_internal(&self) {
+        require!(
+            self.mode == Some(KycMode::Internal),
+            "The KYC registry is not internal"
+        );
+    }
+}
+
+impl Contract {
+    /// Requires `account_id` to be verified before tokens are credited to it.
+    pub(crate) fn internal_assert_verified(&self, account_id: &AccountId) {
+        if account_id == &self.owner_id {
+            return;
+        }
+        match &self.kyc.mode {
+            None => {}
+            Some(KycMode::Internal) => require!(
+                self.kyc.verified.contains(account_id),
+                format!("@{} is not KYC verified", account_id)
+            ),
+            Some(KycMode::External { .. }) => env::panic_str(&format!(
+                "@{} can't be verified synchronously with an external KYC registry",
+                account_id
+            )),
+        }
+    }
+
+    /// Takes `amount` from `sender_id` and asks `registry_id` whether `receiver_id` is verified
+    /// before crediting it.
+    pub(crate) fn internal_transfer_with_registry(
+        &mut self,
+        registry_id: AccountId,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        self.internal_before_transfer(&sender_id, &receiver_id, amount, memo.as_deref());
+        self.token.internal_unwrap_balance_of(&receiver_id);
+        self.internal_lock(&sender_id, amount);
+
+        ext_kyc_registry::ext(registry_id)
+            .with_static_gas(GAS_FOR_IS_VERIFIED)
+            .is_verified(receiver_id.clone())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_KYC_TRANSFER)
+                    .kyc_resolve_transfer(sender_id, receiver_id, amount.into(), memo),
+            );
+    }
+
+    /// Same as `internal_transfer_with_registry`, calling `ft_on_transfer` on the receiver with
+    /// `receiver_gas` once the registry verified it. The gas is attached to the callback, which
+    /// forwards what is left of it to the receiver.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn internal_transfer_call_with_registry(
+        &mut self,
+        registry_id: AccountId,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: Balance,
+        memo: Option<String>,
+        msg: String,
+        receiver_gas: Gas,
+        receiver_gas_weight: u64,
+    ) -> Promise {
+        self.internal_before_transfer(&sender_id, &receiver_id, amount, memo.as_deref());
+        self.token.internal_unwrap_balance_of(&receiver_id);
+        self.internal_lock(&sender_id, amount);
+
+        ext_kyc_registry::ext(registry_id)
+            .with_static_gas(GAS_FOR_IS_VERIFIED)
+            .is_verified(receiver_id.clone())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_FT_TRANSFER_CALL.saturating_add(receiver_gas))
+                    .with_unused_gas_weight(receiver_gas_weight)
+                    .kyc_resolve_transfer_call(
+                        sender_id,
+                        receiver_id,
+                        amount.into(),
+                        memo,
+                        msg,
+                        receiver_gas_weight,
+                    ),
+            )
+    }
+
+    /// Credits `amount` locked by `sender_id` to `account_id`. With an external registry, tokens
+    /// released to an account other than the sender or the owner are only credited once the
+    /// registry verified it, and go back to the sender otherwise.
+    pub(crate) fn internal_unlock_verified(
+        &mut self,
+        sender_id: &AccountId,
+        account_id: &AccountId,
+        amount: Balance,
+    ) {
+        match self.kyc.registry_id() {
+            Some(registry_id) if account_id != sender_id && account_id != &self.owner_id => {
+                ext_kyc_registry::ext(registry_id)
+                    .with_static_gas(GAS_FOR_IS_VERIFIED)
+                    .is_verified(account_id.clone())
+                    .then(
+                        Self::ext(env::current_account_id())
+                            .with_static_gas(GAS_FOR_RESOLVE_KYC_TRANSFER)
+                            .kyc_resolve_release(
+                                sender_id.clone(),
+                                account_id.clone(),
+                                amount.into(),
+                            ),
+                    );
+            }
+            _ => self.internal_unlock(account_id, amount),
+        }
+    }
+
+    /// Credits `amount` locked from `sender_id` to `receiver_id` if the registry verified it, or
+    /// gives it back to the sender, burning it if the sender unregistered as well. Returns whether
+    /// the receiver was credited.
+    fn internal_resolve_registry(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: U128,
+    ) -> bool {
+        let verified = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice(&value).unwrap_or(false),
+            PromiseResult::Failed => false,
+        };
+        // the receiver could have unregistered in the meantime
+        if verified && self.token.accounts.contains_key(receiver_id) {
+            self.internal_unlock(receiver_id, amount.0);
+            return true;
+        }
+        if self.token.accounts.contains_key(sender_id) {
+            self.internal_unlock(sender_id, amount.0);
+            log!(
+                "@{} is not KYC verified, refunded {} to @{}",
+                receiver_id,
+                amount.0,
+                sender_id
+            );
+        } else {
+            // the sender unregistered as well, so the amount is burned
+            self.internal_burn_locked(amount.0);
+            FtBurn {
+                owner_id: sender_id,
+                amount,
+                memo: Some("refund"),
+            }
+            .emit();
+        }
+        false
+    }
+
+    /// Reports the transfer finished by the registry, and records it in the history.
+    fn internal_complete_registry_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: U128,
+        memo: Option<&str>,
+    ) {
+        FtTransfer {
+            old_owner_id: sender_id,
+            new_owner_id: receiver_id,
+            amount,
+            memo,
+        }
+        .emit();
+        if self.history.is_enabled() {
+            let keeps_history =
+                |record: Option<(u128, bool)>| record.is_some_and(|(_, keeps)| keeps);
+            let sender_keeps_history = keeps_history(self.internal_balance_record(sender_id));
+            let receiver_keeps_history = keeps_history(self.internal_balance_record(receiver_id));
+            self.internal_record_transfer(
+                (sender_id, sender_keeps_history),
+                (receiver_id, receiver_keeps_history),
+                amount.0,
+                memo,
+            );
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Credits the amount taken by `ft_transfer` to the receiver if the registry verified it, or
+    /// gives it back to the sender. Returns whether the transfer was completed.
+    #[private]
+    pub fn kyc_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) -> bool {
+        if !self.internal_resolve_registry(&sender_id, &receiver_id, amount) {
+            return false;
+        }
+        self.internal_complete_registry_transfer(&sender_id, &receiver_id, amount, memo.as_deref());
+        true
+    }
+
+    /// Credits the amount taken by `ft_transfer_call` to the receiver if the registry verified it
+    /// and calls its `ft_on_transfer` with the gas of this call left for it, or gives the amount
+    /// back to the sender. Returns the amount used by the receiver.
+    #[private]
+    pub fn kyc_resolve_transfer_call(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+        receiver_gas_weight: u64,
+    ) -> PromiseOrValue<U128> {
+        if !self.internal_resolve_registry(&sender_id, &receiver_id, amount) {
+            return PromiseOrValue::Value(U128(0));
+        }
+        self.internal_complete_registry_transfer(&sender_id, &receiver_id, amount, memo.as_deref());
+        let receiver_gas = env::prepaid_gas().saturating_sub(GAS_FOR_FT_TRANSFER_CALL);
+        Self::internal_call_receiver(
+            sender_id,
+            receiver_id,
+            amount,
+            msg,
+            receiver_gas,
+            receiver_gas_weight,
+        )
+        .into()
+    }
+
+    /// Credits tokens released from an escrow, a stream or an HTLC to the receiver if the
+    /// registry verified it, or gives them back to the sender. Returns whether the receiver was
+    /// credited.
+    #[private]
+    pub fn kyc_resolve_release(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> bool {
+        self.internal_resolve_registry(&sender_id, &receiver_id, amount)
+    }
+
+    /// Marks `account_id` as verified in the internal registry. Can only be called by an account
+    /// with the `Attestor` role. The attached deposit must cover the storage of the entry, the
+    /// excess is refunded.
+    #[payable]
+    pub fn kyc_verify(&mut self, account_id: AccountId) {
+        let initial_storage_usage = env::storage_usage();
+        self.kyc.assert_internal();
+        self.assert_role(Role::Attestor);
+        require!(
+            self.kyc.verified.insert(&account_id),
+            "The account is already verified"
+        );
+        self.internal_charge_storage(initial_storage_usage);
+        log!("Verified @{}", account_id);
+    }
+
+    /// Removes `account_id` from the internal registry. Can only be called by an account with the
+    /// `Attestor` role.
+    #[payable]
+    pub fn kyc_revoke(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        self.kyc.assert_internal();
+        self.assert_role(Role::Attestor);
+        let initial_storage_usage = env::storage_usage();
+        require!(
+            self.kyc.verified.remove(&account_id),
+            "The account is not verified"
+        );
+        self.internal_refund_storage(initial_storage_usage, env::predecessor_account_id());
+        log!("Revoked the verification of @{}", account_id);
+    }
+
+    pub fn kyc_mode(&self) -> Option<KycMode> {
+        self.kyc.mode.clone()
+    }
+
+    /// Whether `account_id` is verified in the internal registry.
+    pub fn kyc_is_verified(&self, account_id: AccountId) -> bool {
+        self.kyc.verified.contains(&account_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::U64;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken, RuntimeFeesConfig};
+
+    use super::*;
+    use crate::tests::{current, owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const AMOUNT: u128 = 1_000;
+
+    fn registry() -> AccountId {
+        "kyc-registry.near".parse().unwrap()
+    }
+
+    /// Registers user1 and user2, grants the attestor role to the contract account, and transfers
+    /// `AMOUNT` to user1 as the owner, verifying user1 first in the `Internal` mode.
+    fn setup(mode: KycMode) -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            kyc: Some(mode.clone()),
+            ..Default::default()
+        });
+
+        for account_id in [user1(), user2()].iter() {
+            testing_env!(context
+                .predecessor_account_id(account_id.clone())
+                .attached_deposit(contract.storage_balance_bounds().min)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.grant_role(Role::Attestor, current());
+        if mode == KycMode::Internal {
+            testing_env!(context.predecessor_account_id(current()).build());
+            contract.kyc_verify(user1());
+        }
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), AMOUNT.into(), None);
+        (contract, context)
+    }
+
+    fn resolve(
+        contract: &mut Contract,
+        context: &mut VMContextBuilder,
+        result: PromiseResult,
+    ) -> bool {
+        testing_env!(
+            context.predecessor_account_id(current()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+        contract.kyc_resolve_transfer(owner(), user1(), AMOUNT.into(), None)
+    }
+
+    #[test]
+    fn test_internal_registry() {
+        let (mut contract, mut context) = setup(KycMode::Internal);
+        assert!(contract.kyc_is_verified(user1()));
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+
+        // tokens can always be sent back to the owner
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_transfer(owner(), 1.into(), None);
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT - 1);
+    }
+
+    #[should_panic(expected = "@charlie is not KYC verified")]
+    #[test]
+    fn test_internal_registry_rejects_unverified_receiver() {
+        let (mut contract, mut context) = setup(KycMode::Internal);
+
+        testing_env!(context.predecessor_account_id(current()).build());
+        contract.kyc_revoke(user1());
+        assert!(!contract.kyc_is_verified(user1()));
+
+        testing_env!(context.predecessor_account_id(owner()).build());
+        contract.ft_transfer(user1(), 1.into(), None);
+    }
+
+    #[test]
+    fn test_external_registry_verifies_receiver() {
+        let (mut contract, mut context) = setup(KycMode::External {
+            registry_id: registry(),
+        });
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - AMOUNT);
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+
+        assert!(resolve(
+            &mut contract,
+            &mut context,
+            PromiseResult::Successful(b"true".to_vec())
+        ));
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[test]
+    fn test_external_registry_refunds_unverified_receiver() {
+        let (mut contract, mut context) = setup(KycMode::External {
+            registry_id: registry(),
+        });
+
+        assert!(!resolve(
+            &mut contract,
+            &mut context,
+            PromiseResult::Successful(b"false".to_vec())
+        ));
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+    }
+
+    #[test]
+    fn test_external_registry_burns_refund_of_unregistered_sender() {
+        let (mut contract, mut context) = setup(KycMode::External {
+            registry_id: registry(),
+        });
+        resolve(
+            &mut contract,
+            &mut context,
+            PromiseResult::Successful(b"true".to_vec()),
+        );
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user2(), AMOUNT.into(), None);
+        assert!(contract.storage_unregister(None));
+
+        testing_env!(
+            context.predecessor_account_id(current()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"false".to_vec())],
+        );
+        assert!(!contract.kyc_resolve_transfer(user1(), user2(), AMOUNT.into(), None));
+        assert_eq!(contract.ft_balance_of(user2()).0, 0);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY - AMOUNT);
+    }
+
+    #[should_panic(expected = "@danny is not KYC verified")]
+    #[test]
+    fn test_internal_registry_rejects_unverified_escrow_receiver() {
+        let (mut contract, mut context) = setup(KycMode::Internal);
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.escrow_create(user2(), 1.into(), owner(), U64(1), None);
+    }
+
+    #[test]
+    fn test_external_registry_verifies_transfer_call_receiver() {
+        let (mut contract, mut context) = setup(KycMode::External {
+            registry_id: registry(),
+        });
+
+        testing_env!(context
+            .prepaid_gas(Gas::from_tgas(100))
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer_call(user1(), AMOUNT.into(), None, "".to_string());
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - 2 * AMOUNT);
+
+        testing_env!(
+            context.predecessor_account_id(current()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"true".to_vec())],
+        );
+        let result = contract.kyc_resolve_transfer_call(
+            owner(),
+            user1(),
+            AMOUNT.into(),
+            None,
+            "".to_string(),
+            1,
+        );
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+    }
+
+    #[test]
+    fn test_external_registry_refunds_unverified_transfer_call_receiver() {
+        let (mut contract, mut context) = setup(KycMode::External {
+            registry_id: registry(),
+        });
+
+        testing_env!(
+            context.predecessor_account_id(current()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        let result = contract.kyc_resolve_transfer_call(
+            owner(),
+            user1(),
+            AMOUNT.into(),
+            None,
+            "".to_string(),
+            1,
+        );
+        assert!(matches!(result, PromiseOrValue::Value(U128(0))));
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+    }
+
+    #[test]
+    fn test_external_registry_refunds_htlc_of_unverified_receiver() {
+        let (mut contract, mut context) = setup(KycMode::External {
+            registry_id: registry(),
+        });
+
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        let preimage = b"secret".to_vec();
+        let id = contract.htlc_lock(
+            user1(),
+            1.into(),
+            env::sha256_array(&preimage).into(),
+            U64(1),
+        );
+        contract.htlc_claim(id, preimage.into());
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+
+        testing_env!(
+            context.predecessor_account_id(current()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"false".to_vec())],
+        );
+        assert!(!contract.kyc_resolve_release(owner(), user1(), 1.into()));
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - AMOUNT);
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+    }
+
+    #[should_panic(
+        expected = "An external KYC registry can't be combined with the bridge, force transfers or social recovery"
+    )]
+    #[test]
+    fn test_new_panics_with_external_registry_and_force_transfer() {
+        setup_with_config(ContractConfig {
+            kyc: Some(KycMode::External {
+                registry_id: registry(),
+            }),
+            force_transfer: true,
+            ..Default::default()
+        });
+    }
+
+    #[should_panic(expected = "Staking rewards are not supported with an external KYC registry")]
+    #[test]
+    fn test_external_registry_panics_on_staking_emission() {
+        let (mut contract, _) = setup(KycMode::External {
+            registry_id: registry(),
+        });
+
+        contract.staking_set_emission(U128(1), U64(u64::MAX));
+    }
+}
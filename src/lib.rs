@@ -1,7 +1,8 @@
 /*!
 Fungible Token implementation with JSON serialization.
 NOTES:
-  - The maximum balance value is limited by U128 (2**128 - 1).
+  - The maximum balance value is limited by U128 (2**128 - 1), or 2**127 - 1 with the transfer
+    history enabled.
   - JSON calls should pass U128 as a base-10 string. E.g. "100".
   - The contract optimizes the inner trie structure by hashing account IDs. It will prevent some
     abuse of deep tries. Shouldn't be an issue, once NEAR clients implement full hashing of keys.
@@ -15,27 +16,111 @@ NOTES:
   - To prevent the deployed contract from being modified or deleted, it should not have any access
     keys on its account.
 */
+pub mod admin;
+pub mod allowlist;
+pub mod bridge;
+pub mod compliance;
+pub mod council;
+pub mod decimal;
+pub mod escrow;
+pub mod flash;
+pub mod guardians;
+pub mod history;
+pub mod htlc;
+pub mod interest;
+pub mod kyc;
+pub mod limits;
+mod math;
+pub mod memo;
+pub mod metadata;
+pub mod migration;
+pub mod owner;
+pub mod rebase;
+pub mod receiver;
+pub mod recovery;
+pub mod roles;
+pub mod staking;
+pub mod stream;
+pub mod timelock;
+
+use near_contract_standards::fungible_token::events::{FtBurn, FtTransfer};
 use near_contract_standards::fungible_token::metadata::{
     FungibleTokenMetadata, FungibleTokenMetadataProvider,
 };
+use near_contract_standards::fungible_token::receiver::ext_ft_receiver;
 use near_contract_standards::fungible_token::{
-    FungibleToken, FungibleTokenCore, FungibleTokenResolver,
+    Balance, FungibleToken, FungibleTokenCore, FungibleTokenResolver,
 };
 use near_contract_standards::storage_management::{
     StorageBalance, StorageBalanceBounds, StorageManagement,
 };
 use near_sdk::borsh::BorshSerialize;
-use near_sdk::collections::LazyOption;
-use near_sdk::json_types::U128;
+use near_sdk::collections::{LazyOption, LookupSet};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde_json;
 use near_sdk::{
-    env, log, near, require, AccountId, BorshStorageKey, NearToken, PanicOnDefault, PromiseOrValue,
+    assert_one_yocto, env, log, near, require, AccountId, BorshStorageKey, CryptoHash, Gas,
+    NearToken, PanicOnDefault, Promise, PromiseOrValue, PromiseResult, StorageUsage,
 };
 
+use crate::allowlist::ReceiverAllowlist;
+use crate::bridge::{Bridge, BridgeMode};
+use crate::council::{Council, CouncilConfig};
+use crate::escrow::Escrows;
+use crate::flash::FlashLoans;
+use crate::guardians::Guardians;
+use crate::history::{TransferDirection, TransferHistory, HISTORY_FLAG};
+use crate::htlc::Htlcs;
+use crate::interest::Interest;
+use crate::kyc::{Kyc, KycMode, GAS_FOR_KYC_CHECK};
+use crate::limits::{Limiter, TransferLimits};
+use crate::metadata::assert_valid_metadata;
+use crate::migration::Migration;
+use crate::rebase::Rebasing;
+use crate::recovery::Recovery;
+use crate::roles::Role;
+use crate::staking::Staking;
+use crate::stream::Streams;
+use crate::timelock::Timelock;
+
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas::from_tgas(5);
+const GAS_FOR_FT_TRANSFER_CALL: Gas = Gas::from_tgas(30);
+/// Storage of a data record on top of its key and value, in bytes.
+const DATA_RECORD_STORAGE_USAGE: StorageUsage = 40;
+
 #[derive(PanicOnDefault)]
 #[near(contract_state)]
 pub struct Contract {
+    owner_id: AccountId,
+    roles: LookupSet<(Role, AccountId)>,
+    /// Balances in `token` are shares when set.
+    rebasing: Option<Rebasing>,
+    interest: Option<Interest>,
     token: FungibleToken,
     metadata: LazyOption<FungibleTokenMetadata>,
+    history: TransferHistory,
+    escrows: Escrows,
+    streams: Streams,
+    htlcs: Htlcs,
+    bridge: Bridge,
+    receiver_allowlist: ReceiverAllowlist,
+    limiter: Limiter,
+    recovery: Recovery,
+    migration: Migration,
+    flash_loans: FlashLoans,
+    staking: Staking,
+    /// Whether transfers are stopped by the council.
+    paused: bool,
+    council: Council,
+    /// Accounts which can't send or receive transfers.
+    denylist: LookupSet<AccountId>,
+    timelock: Timelock,
+    guardians: Guardians,
+    /// Whether the compliance role can move balances with `ft_force_transfer`.
+    force_transfer_enabled: bool,
+    kyc: Kyc,
+    /// Accounts receiving transfers only with a memo.
+    requires_memo: LookupSet<AccountId>,
 }
 
 #[derive(BorshSerialize, BorshStorageKey)]
@@ -43,6 +128,70 @@ pub struct Contract {
 enum StorageKey {
     FungibleToken,
     Metadata,
+    HistoryAccounts,
+    HistoryEntries,
+    Escrows,
+    EscrowsByParty,
+    EscrowsByPartyInner { account_hash: CryptoHash },
+    Streams,
+    StreamsByParty,
+    StreamsByPartyInner { account_hash: CryptoHash },
+    Htlcs,
+    BridgeRelayers,
+    BridgeProcessed,
+    Roles,
+    InterestCheckpoints,
+    ReceiverAllowlist,
+    LimitsExempt,
+    LimitsOutflows,
+    RecoveryDeposits,
+    MigrationSources,
+    FlashLoanDebts,
+    StakingStakes,
+    CouncilProposals,
+    Denylist,
+    TimelockQueue,
+    GuardianSettings,
+    KycVerified,
+    RequiresMemo,
+}
+
+/// Optional features of the token, which can only be switched on at initialization.
+#[derive(Default)]
+#[near(serializers = [json])]
+pub struct ContractConfig {
+    /// Maximum number of transfers an account can keep in its on-chain history.
+    /// The history is disabled when not set.
+    pub max_history_length: Option<u32>,
+    /// How tokens leave and enter the chain through the bridge.
+    /// The bridge is disabled when not set.
+    pub bridge_mode: Option<BridgeMode>,
+    /// Whether balances scale with the total supply set by the oracle with `rebase`.
+    #[serde(default)]
+    pub rebasing: bool,
+    /// Whether balances accrue interest at the APR set by the owner. Can't be combined with
+    /// `rebasing`.
+    #[serde(default)]
+    pub interest_bearing: bool,
+    /// Caps on transfers and balances, which the owner can change later.
+    /// The limits are disabled when not set.
+    pub transfer_limits: Option<TransferLimits>,
+    /// Members of the council approving the admin actions, and its threshold.
+    /// Admin actions are disabled when not set.
+    pub council: Option<CouncilConfig>,
+    /// Delay in nanoseconds of the admin actions scheduled by the owner.
+    /// The timelock is disabled when not set.
+    pub timelock_delay: Option<U64>,
+    /// Whether accounts can nominate guardians able to recover their balance.
+    #[serde(default)]
+    pub social_recovery: bool,
+    /// Whether accounts with the `Compliance` role can move balances between accounts.
+    #[serde(default)]
+    pub force_transfer: bool,
+    /// Where the KYC status of the receivers of transfers comes from.
+    /// Transfers are not gated when not set. An external registry can't be combined with
+    /// `bridge_mode`, `social_recovery` or `force_transfer`.
+    pub kyc: Option<KycMode>,
 }
 
 #[near]
@@ -51,14 +200,20 @@ impl Contract {
     /// the given fungible token metadata.
     #[init]
     pub fn new(owner_id: AccountId, total_supply: U128, metadata: FungibleTokenMetadata) -> Self {
-        require!(!env::state_exists(), "Already initialized");
-        metadata.assert_valid();
-        let mut this = Self {
-            token: FungibleToken::new(StorageKey::FungibleToken),
-            metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
-        };
+        Self::new_with_config(owner_id, total_supply, metadata, ContractConfig::default())
+    }
+
+    /// Same as `new`, additionally switching on the optional features given in `config`.
+    #[init]
+    pub fn new_with_config(
+        owner_id: AccountId,
+        total_supply: U128,
+        metadata: FungibleTokenMetadata,
+        config: ContractConfig,
+    ) -> Self {
+        let mut this = Self::internal_new(owner_id.clone(), metadata, config);
         this.token.internal_register_account(&owner_id);
-        this.token.internal_deposit(&owner_id, total_supply.into());
+        this.internal_deposit(&owner_id, total_supply.into());
 
         near_contract_standards::fungible_token::events::FtMint {
             owner_id: &owner_id,
@@ -69,13 +224,684 @@ impl Contract {
 
         this
     }
+
+    /// Initializes the contract with the given fungible token metadata, registering every account
+    /// of `allocations` with its initial balance, which should be positive. The total supply is
+    /// the sum of the allocations. The contract account pays for the storage of the registered
+    /// accounts, and must hold enough NEAR to cover all of its storage afterwards, see
+    /// `storage_cost`.
+    #[init]
+    pub fn new_with_allocations(
+        metadata: FungibleTokenMetadata,
+        allocations: Vec<(AccountId, U128)>,
+        owner_id: AccountId,
+        config: Option<ContractConfig>,
+    ) -> Self {
+        require!(!allocations.is_empty(), "Allocations should not be empty");
+        let mut account_ids = std::collections::HashSet::new();
+        let mut total_supply: Balance = 0;
+        for (account_id, amount) in &allocations {
+            require!(
+                account_ids.insert(account_id),
+                format!("Duplicate allocation to @{}", account_id)
+            );
+            require!(
+                amount.0 > 0,
+                format!(
+                    "The allocation to @{} should be a positive number",
+                    account_id
+                )
+            );
+            total_supply = total_supply
+                .checked_add(amount.0)
+                .unwrap_or_else(|| env::panic_str("Total supply overflow"));
+        }
+
+        let mut this = Self::internal_new(owner_id, metadata, config.unwrap_or_default());
+        let initial_storage_usage = env::storage_usage();
+        for (account_id, amount) in &allocations {
+            this.token.internal_register_account(account_id);
+            this.internal_check_incoming(account_id, amount.0);
+            this.internal_deposit(account_id, amount.0);
+        }
+
+        // the state of the contract itself is only written once the initialization returns
+        let state_storage_usage = (b"STATE".len()
+            + near_sdk::borsh::to_vec(&this)
+                .unwrap_or_else(|_| env::abort())
+                .len()) as StorageUsage
+            + DATA_RECORD_STORAGE_USAGE;
+        let storage_cost = env::storage_byte_cost()
+            .saturating_mul((env::storage_usage() + state_storage_usage).into());
+        require!(
+            env::account_balance() >= storage_cost,
+            format!(
+                "Registering {} accounts requires {} for the storage of the contract account, which has {}",
+                allocations.len(),
+                storage_cost.exact_amount_display(),
+                env::account_balance().exact_amount_display()
+            )
+        );
+        log!(
+            "Registered {} accounts for {} of storage",
+            allocations.len(),
+            env::storage_byte_cost()
+                .saturating_mul((env::storage_usage() - initial_storage_usage).into())
+                .exact_amount_display()
+        );
+
+        let mints: Vec<_> = allocations
+            .iter()
+            .map(
+                |(account_id, amount)| near_contract_standards::fungible_token::events::FtMint {
+                    owner_id: account_id,
+                    amount: *amount,
+                    memo: Some("new tokens are minted"),
+                },
+            )
+            .collect();
+        near_contract_standards::fungible_token::events::FtMint::emit_many(&mints);
+
+        this
+    }
+
+    /// NEAR the contract account must hold to cover the storage it uses.
+    pub fn storage_cost(&self) -> NearToken {
+        env::storage_byte_cost().saturating_mul(env::storage_usage().into())
+    }
+
+    /// Same as `ft_transfer_call`, but `ft_on_transfer` gets exactly `receiver_gas`, and the gas
+    /// left over goes to `ft_resolve_transfer`. The prepaid gas must be at least
+    /// `ft_transfer_call_min_gas(receiver_gas)`.
+    #[payable]
+    pub fn ft_transfer_call_with_gas(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+        receiver_gas: Gas,
+    ) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        require!(
+            receiver_gas.as_gas() > 0,
+            "The receiver gas should be positive"
+        );
+        let required_gas = self.ft_transfer_call_min_gas(Some(receiver_gas));
+        require!(
+            env::prepaid_gas() >= required_gas,
+            format!(
+                "More gas is required: {} is attached, {} is needed",
+                env::prepaid_gas(),
+                required_gas
+            )
+        );
+        self.internal_transfer_call(receiver_id, amount, memo, msg, receiver_gas, 0)
+    }
+
+    /// Minimum prepaid gas of `ft_transfer_call_with_gas` forwarding `receiver_gas` to the
+    /// receiver. `ft_transfer_call` requires more than the minimum for no receiver gas, and
+    /// forwards all the gas above it.
+    pub fn ft_transfer_call_min_gas(&self, receiver_gas: Option<Gas>) -> Gas {
+        self.internal_transfer_call_gas()
+            .checked_add(receiver_gas.unwrap_or(Gas::from_gas(0)))
+            .unwrap_or_else(|| env::panic_str("Gas overflow"))
+    }
+}
+
+impl Contract {
+    /// Builds the state of the contract without any tokens.
+    fn internal_new(
+        owner_id: AccountId,
+        metadata: FungibleTokenMetadata,
+        config: ContractConfig,
+    ) -> Self {
+        require!(!env::state_exists(), "Already initialized");
+        assert_valid_metadata(&metadata);
+        require!(
+            !(config.rebasing && config.interest_bearing),
+            "The rebasing and interest-bearing modes can't be combined"
+        );
+        require!(
+            config.council.is_none() || config.timelock_delay.is_none(),
+            "The council and the timelock can't be combined"
+        );
+        require!(
+            !matches!(config.kyc, Some(KycMode::External { .. }))
+                || (config.bridge_mode.is_none()
+                    && !config.force_transfer
+                    && !config.social_recovery),
+            "An external KYC registry can't be combined with the bridge, force transfers or social recovery"
+        );
+        let mut this = Self {
+            owner_id,
+            roles: LookupSet::new(StorageKey::Roles),
+            rebasing: config.rebasing.then(Rebasing::default),
+            interest: config
+                .interest_bearing
+                .then(|| Interest::new(StorageKey::InterestCheckpoints)),
+            token: FungibleToken::new(StorageKey::FungibleToken),
+            metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            history: TransferHistory::new(
+                StorageKey::HistoryAccounts,
+                StorageKey::HistoryEntries,
+                config.max_history_length.unwrap_or(0),
+            ),
+            escrows: Escrows::new(StorageKey::Escrows, StorageKey::EscrowsByParty),
+            streams: Streams::new(StorageKey::Streams, StorageKey::StreamsByParty),
+            htlcs: Htlcs::new(StorageKey::Htlcs),
+            bridge: Bridge::new(
+                StorageKey::BridgeRelayers,
+                StorageKey::BridgeProcessed,
+                config.bridge_mode,
+            ),
+            receiver_allowlist: ReceiverAllowlist::new(StorageKey::ReceiverAllowlist),
+            limiter: Limiter::new(
+                StorageKey::LimitsExempt,
+                StorageKey::LimitsOutflows,
+                config.transfer_limits,
+            ),
+            recovery: Recovery::new(StorageKey::RecoveryDeposits),
+            migration: Migration::new(StorageKey::MigrationSources),
+            flash_loans: FlashLoans::new(StorageKey::FlashLoanDebts),
+            staking: Staking::new(StorageKey::StakingStakes),
+            paused: false,
+            council: Council::new(StorageKey::CouncilProposals, config.council),
+            denylist: LookupSet::new(StorageKey::Denylist),
+            timelock: Timelock::new(
+                StorageKey::TimelockQueue,
+                config.timelock_delay.map(|delay| delay.0),
+            ),
+            guardians: Guardians::new(StorageKey::GuardianSettings, config.social_recovery),
+            force_transfer_enabled: config.force_transfer,
+            kyc: Kyc::new(StorageKey::KycVerified, config.kyc),
+            requires_memo: LookupSet::new(StorageKey::RequiresMemo),
+        };
+        if let Some(interest) = &this.interest {
+            // registration deposits cover the accrual checkpoint of the account
+            this.token.account_storage_usage += interest.checkpoint_storage_usage;
+        }
+        if this.limiter.limits.is_some() {
+            // registration deposits cover the daily outflow of the account
+            this.token.account_storage_usage += this.limiter.outflow_storage_usage;
+        }
+        if this.guardians.enabled {
+            // registration deposits cover the guardians and the pending recovery of the account
+            this.token.account_storage_usage += this.guardians.setting_storage_usage;
+        }
+        this
+    }
+
+    /// Transfers `amount` from the predecessor to `receiver_id`, and calls `ft_on_transfer` on
+    /// it with `receiver_gas` and `ft_resolve_transfer` after it. `receiver_gas_weight` is the
+    /// share of the unused gas given to the receiver, the callback has a weight of 1.
+    fn internal_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+        receiver_gas: Gas,
+        receiver_gas_weight: u64,
+    ) -> PromiseOrValue<U128> {
+        self.receiver_allowlist
+            .internal_assert_allowed(&receiver_id);
+        let sender_id = env::predecessor_account_id();
+        if let Some(registry_id) = self.kyc.registry_id() {
+            return self
+                .internal_transfer_call_with_registry(
+                    registry_id,
+                    sender_id,
+                    receiver_id,
+                    amount.into(),
+                    memo,
+                    msg,
+                    receiver_gas,
+                    receiver_gas_weight,
+                )
+                .into();
+        }
+        self.internal_transfer(&sender_id, &receiver_id, amount.into(), memo);
+        Self::internal_call_receiver(
+            sender_id,
+            receiver_id,
+            amount,
+            msg,
+            receiver_gas,
+            receiver_gas_weight,
+        )
+        .into()
+    }
+
+    /// Calls `ft_on_transfer` on the receiver of a transfer call, and `ft_resolve_transfer` once
+    /// it is done.
+    fn internal_call_receiver(
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        msg: String,
+        receiver_gas: Gas,
+        receiver_gas_weight: u64,
+    ) -> Promise {
+        // Initiating receiver's call and the callback
+        ext_ft_receiver::ext(receiver_id.clone())
+            .with_static_gas(receiver_gas)
+            .with_unused_gas_weight(receiver_gas_weight)
+            .ft_on_transfer(sender_id.clone(), amount, msg)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .ft_resolve_transfer(sender_id, receiver_id, amount),
+            )
+    }
+
+    /// Gas of a transfer call besides the gas of the receiver, which includes asking the external
+    /// KYC registry if there is one.
+    fn internal_transfer_call_gas(&self) -> Gas {
+        if self.kyc.registry_id().is_some() {
+            GAS_FOR_FT_TRANSFER_CALL.saturating_add(GAS_FOR_KYC_CHECK)
+        } else {
+            GAS_FOR_FT_TRANSFER_CALL
+        }
+    }
+
+    /// Moves `amount` from `sender_id` to `receiver_id` and records it in the history of both
+    /// accounts.
+    fn internal_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        self.internal_before_transfer(sender_id, receiver_id, amount, memo.as_deref());
+        self.internal_assert_verified(receiver_id);
+        self.internal_move(sender_id, receiver_id, amount, memo);
+    }
+
+    /// Checks the pause, the denylist, the transfer limits and the memo requirement of the
+    /// receiver before a transfer sent by `sender_id`, which vetoes its pending social recovery.
+    fn internal_before_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<&str>,
+    ) {
+        require!(
+            sender_id != receiver_id,
+            "Sender and receiver should be different"
+        );
+        require!(amount > 0, "The amount should be a positive number");
+        self.internal_assert_can_move(sender_id, receiver_id);
+        self.internal_assert_memo(receiver_id, memo);
+        self.internal_check_transfer(sender_id, receiver_id, amount);
+        self.guardians.internal_veto(sender_id);
+    }
+
+    /// Moves `amount` from `sender_id` to `receiver_id` without checking the pause, the denylist
+    /// or the transfer limits.
+    fn internal_move(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        let shares = self.internal_shares_of(amount, true);
+        self.internal_accrue(sender_id);
+        self.internal_accrue(receiver_id);
+        let sender_keeps_history = self.internal_withdraw_shares(sender_id, shares);
+        let receiver_keeps_history = self.internal_deposit_shares(receiver_id, shares);
+        self.internal_checkpoint(sender_id);
+        self.internal_checkpoint(receiver_id);
+        FtTransfer {
+            old_owner_id: sender_id,
+            new_owner_id: receiver_id,
+            amount: U128(amount),
+            memo: memo.as_deref(),
+        }
+        .emit();
+        self.internal_record_transfer(
+            (sender_id, sender_keeps_history),
+            (receiver_id, receiver_keeps_history),
+            amount,
+            memo.as_deref(),
+        );
+    }
+
+    /// Same as `FungibleToken::internal_ft_resolve_transfer`, refunding the unused amount rounded
+    /// down to whole shares of a rebasing token and recording it in the history. The refund is
+    /// burned if the sender unregistered in the meantime, with an `ft_burn` event for the sender.
+    /// Returns the used and the burned amounts.
+    fn internal_resolve_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) -> (Balance, Balance) {
+        let unused_amount = match env::promise_result(0) {
+            PromiseResult::Successful(value) => match serde_json::from_slice::<U128>(&value) {
+                Ok(unused_amount) => std::cmp::min(amount, unused_amount.0),
+                Err(_) => amount,
+            },
+            PromiseResult::Failed => amount,
+        };
+        if unused_amount == 0 {
+            return (amount, 0);
+        }
+
+        let (receiver_shares, receiver_keeps_history) = self
+            .internal_balance_record(receiver_id)
+            .unwrap_or((0, false));
+        let refund_shares = std::cmp::min(
+            receiver_shares,
+            self.internal_shares_of(unused_amount, false),
+        );
+        let refund_amount = self.internal_amount_of(refund_shares);
+        if refund_shares == 0 {
+            return (amount, 0);
+        }
+        self.internal_set_balance_record(
+            receiver_id,
+            receiver_shares - refund_shares,
+            receiver_keeps_history,
+        );
+
+        if let Some((sender_shares, sender_keeps_history)) = self.internal_balance_record(sender_id)
+        {
+            let new_sender_shares = sender_shares
+                .checked_add(refund_shares)
+                .unwrap_or_else(|| env::panic_str("Sender balance overflow"));
+            self.internal_set_balance_record(sender_id, new_sender_shares, sender_keeps_history);
+            FtTransfer {
+                old_owner_id: receiver_id,
+                new_owner_id: sender_id,
+                amount: U128(refund_amount),
+                memo: Some("refund"),
+            }
+            .emit();
+            self.internal_record_transfer(
+                (receiver_id, receiver_keeps_history),
+                (sender_id, sender_keeps_history),
+                refund_amount,
+                Some("refund"),
+            );
+            (amount - refund_amount, 0)
+        } else {
+            // the account of the sender was deleted, so the refund is burned
+            log!("The account of the sender was deleted");
+            let total_shares = self.token.total_supply - refund_shares;
+            self.token.total_supply = total_shares;
+            if let Some(rebasing) = self.rebasing.as_mut() {
+                rebasing.pooled = if total_shares == 0 {
+                    0
+                } else {
+                    rebasing.pooled.saturating_sub(refund_amount)
+                };
+            }
+            FtBurn {
+                owner_id: sender_id,
+                amount: U128(refund_amount),
+                memo: Some("refund"),
+            }
+            .emit();
+            (amount, refund_amount)
+        }
+    }
+
+    /// Records the transfer in the history of the sender and of the receiver, each given with
+    /// whether it keeps a history.
+    fn internal_record_transfer(
+        &mut self,
+        (sender_id, sender_keeps_history): (&AccountId, bool),
+        (receiver_id, receiver_keeps_history): (&AccountId, bool),
+        amount: Balance,
+        memo: Option<&str>,
+    ) {
+        if sender_keeps_history {
+            self.history.internal_record(
+                sender_id,
+                receiver_id,
+                amount,
+                TransferDirection::Outgoing,
+                memo,
+            );
+        }
+        if receiver_keeps_history {
+            self.history.internal_record(
+                receiver_id,
+                sender_id,
+                amount,
+                TransferDirection::Incoming,
+                memo,
+            );
+        }
+    }
+
+    /// Balance of `account_id`, in shares for a rebasing token, and whether the account keeps a
+    /// history. Both are stored in the same record, so that transfers between accounts which
+    /// don't keep a history read nothing else.
+    fn internal_balance_record(&self, account_id: &AccountId) -> Option<(Balance, bool)> {
+        let record = self.token.accounts.get(account_id)?;
+        if !self.history.is_enabled() {
+            return Some((record, false));
+        }
+        Some((record & !HISTORY_FLAG, record & HISTORY_FLAG != 0))
+    }
+
+    fn internal_unwrap_balance_record(&self, account_id: &AccountId) -> (Balance, bool) {
+        self.internal_balance_record(account_id).unwrap_or_else(|| {
+            env::panic_str(format!("The account {} is not registered", account_id).as_str())
+        })
+    }
+
+    /// Stores the balance record of `account_id`. With the history enabled, balances are limited
+    /// to 2**127 - 1, the top bit flagging the accounts which keep a history.
+    fn internal_set_balance_record(
+        &mut self,
+        account_id: &AccountId,
+        balance: Balance,
+        keeps_history: bool,
+    ) {
+        let mut record = balance;
+        if self.history.is_enabled() {
+            require!(balance < HISTORY_FLAG, "Balance overflow");
+            if keeps_history {
+                record |= HISTORY_FLAG;
+            }
+        }
+        self.token.accounts.insert(account_id, &record);
+    }
+
+    /// Same as `FungibleToken::internal_deposit`, keeping the history flag of the account. Returns
+    /// whether the account keeps a history.
+    fn internal_deposit_shares(&mut self, account_id: &AccountId, shares: Balance) -> bool {
+        let (balance, keeps_history) = self.internal_unwrap_balance_record(account_id);
+        let new_balance = balance
+            .checked_add(shares)
+            .unwrap_or_else(|| env::panic_str("Balance overflow"));
+        self.internal_set_balance_record(account_id, new_balance, keeps_history);
+        self.token.total_supply = self
+            .token
+            .total_supply
+            .checked_add(shares)
+            .unwrap_or_else(|| env::panic_str("Total supply overflow"));
+        keeps_history
+    }
+
+    /// Same as `FungibleToken::internal_withdraw`, keeping the history flag of the account.
+    /// Returns whether the account keeps a history.
+    fn internal_withdraw_shares(&mut self, account_id: &AccountId, shares: Balance) -> bool {
+        let (balance, keeps_history) = self.internal_unwrap_balance_record(account_id);
+        let new_balance = balance
+            .checked_sub(shares)
+            .unwrap_or_else(|| env::panic_str("The account doesn't have enough balance"));
+        self.internal_set_balance_record(account_id, new_balance, keeps_history);
+        self.token.total_supply = self
+            .token
+            .total_supply
+            .checked_sub(shares)
+            .unwrap_or_else(|| env::panic_str("Total supply overflow"));
+        keeps_history
+    }
+
+    /// Mints `amount` to `account_id`.
+    fn internal_deposit(&mut self, account_id: &AccountId, amount: Balance) {
+        if self.rebasing.is_some() {
+            self.internal_rebasing_deposit(account_id, amount);
+        } else {
+            self.internal_accrue(account_id);
+            self.internal_deposit_shares(account_id, amount);
+            self.internal_checkpoint(account_id);
+        }
+    }
+
+    /// Burns `amount` from `account_id`.
+    fn internal_withdraw(&mut self, account_id: &AccountId, amount: Balance) {
+        if self.rebasing.is_some() {
+            self.internal_rebasing_withdraw(account_id, amount);
+        } else {
+            self.internal_accrue(account_id);
+            self.internal_withdraw_shares(account_id, amount);
+            self.internal_checkpoint(account_id);
+        }
+    }
+
+    /// Checks the pause, the denylist, the transfer limits and the KYC status of `receiver_id`
+    /// before `sender_id` locks `amount` for it in an escrow, a stream or an HTLC. The locked
+    /// amount counts towards the daily outflow of the sender.
+    fn internal_before_lock(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        self.internal_assert_can_move(sender_id, receiver_id);
+        self.internal_check_transfer(sender_id, receiver_id, amount);
+        // an external registry is asked when the tokens are released instead
+        if self.kyc.registry_id().is_none() {
+            self.internal_assert_verified(receiver_id);
+        }
+    }
+
+    /// Checks the pause, the denylist and the maximum balance before `amount` locked by
+    /// `sender_id` is credited to `account_id`, which is either its receiver or the sender
+    /// getting it back. Refunds to the sender are not limited.
+    fn internal_before_unlock(
+        &self,
+        sender_id: &AccountId,
+        account_id: &AccountId,
+        amount: Balance,
+    ) {
+        self.internal_assert_can_move(sender_id, account_id);
+        if sender_id != account_id {
+            self.internal_check_incoming(account_id, amount);
+        }
+    }
+
+    /// Takes `amount` out of the balance of `account_id` without changing the total supply, so
+    /// the tokens can be held by the contract until they are unlocked again.
+    fn internal_lock(&mut self, account_id: &AccountId, amount: Balance) {
+        require!(amount > 0, "The amount should be a positive number");
+        if self.rebasing.is_some() {
+            self.internal_rebasing_withdraw(account_id, amount);
+            let rebasing = self.rebasing.as_mut().unwrap();
+            rebasing.locked += amount;
+            return;
+        }
+        self.internal_accrue(account_id);
+        let (balance, keeps_history) = self.internal_unwrap_balance_record(account_id);
+        let new_balance = balance
+            .checked_sub(amount)
+            .unwrap_or_else(|| env::panic_str("The account doesn't have enough balance"));
+        self.internal_set_balance_record(account_id, new_balance, keeps_history);
+        self.internal_checkpoint(account_id);
+    }
+
+    /// Credits `amount` of previously locked tokens to the balance of `account_id`.
+    fn internal_unlock(&mut self, account_id: &AccountId, amount: Balance) {
+        if self.rebasing.is_some() {
+            let rebasing = self.rebasing.as_mut().unwrap();
+            rebasing.locked -= amount;
+            self.internal_rebasing_deposit(account_id, amount);
+            return;
+        }
+        self.internal_accrue(account_id);
+        let (balance, keeps_history) = self.internal_unwrap_balance_record(account_id);
+        let new_balance = balance
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Balance overflow"));
+        self.internal_set_balance_record(account_id, new_balance, keeps_history);
+        self.internal_checkpoint(account_id);
+    }
+
+    /// Burns `amount` of previously locked tokens.
+    fn internal_burn_locked(&mut self, amount: Balance) {
+        if let Some(rebasing) = self.rebasing.as_mut() {
+            rebasing.locked -= amount;
+            return;
+        }
+        self.token.total_supply -= amount;
+    }
+
+    /// Balance of `account_id`, with its accrued interest or its shares converted to tokens.
+    fn internal_balance_of(&self, account_id: &AccountId) -> Balance {
+        if self.interest.is_some() {
+            return self.internal_balance_with_interest(account_id);
+        }
+        let (shares, _) = self
+            .internal_balance_record(account_id)
+            .unwrap_or((0, false));
+        self.internal_amount_of(shares)
+    }
+
+    /// Requires the attached deposit to cover the storage used since `initial_storage_usage` and
+    /// refunds the rest of it to the predecessor.
+    fn internal_charge_storage(&self, initial_storage_usage: StorageUsage) {
+        let storage_usage = env::storage_usage().saturating_sub(initial_storage_usage);
+        let storage_cost = env::storage_byte_cost().saturating_mul(storage_usage.into());
+        let attached_deposit = env::attached_deposit();
+        require!(
+            attached_deposit >= storage_cost,
+            format!(
+                "Requires attached deposit of at least {}",
+                storage_cost.exact_amount_display()
+            )
+        );
+        let refund = attached_deposit.saturating_sub(storage_cost);
+        if !refund.is_zero() {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+    }
+
+    /// Refunds the storage released since `initial_storage_usage` to `account_id`.
+    fn internal_refund_storage(&self, initial_storage_usage: StorageUsage, account_id: AccountId) {
+        let storage_released = initial_storage_usage.saturating_sub(env::storage_usage());
+        if storage_released > 0 {
+            Promise::new(account_id)
+                .transfer(env::storage_byte_cost().saturating_mul(storage_released.into()));
+        }
+    }
 }
 
 #[near]
 impl FungibleTokenCore for Contract {
     #[payable]
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
-        self.token.ft_transfer(receiver_id, amount, memo)
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        if let Some(registry_id) = self.kyc.registry_id() {
+            self.internal_transfer_with_registry(
+                registry_id,
+                sender_id,
+                receiver_id,
+                amount.into(),
+                memo,
+            );
+            return;
+        }
+        self.internal_transfer(&sender_id, &receiver_id, amount.into(), memo);
     }
 
     #[payable]
@@ -86,20 +912,35 @@ impl FungibleTokenCore for Contract {
         memo: Option<String>,
         msg: String,
     ) -> PromiseOrValue<U128> {
-        self.token.ft_transfer_call(receiver_id, amount, memo, msg)
+        assert_one_yocto();
+        let transfer_call_gas = self.internal_transfer_call_gas();
+        require!(
+            env::prepaid_gas() > transfer_call_gas,
+            "More gas is required"
+        );
+        let receiver_gas = env::prepaid_gas()
+            .checked_sub(transfer_call_gas)
+            .unwrap_or_else(|| env::panic_str("Prepaid gas overflow"));
+        self.internal_transfer_call(receiver_id, amount, memo, msg, receiver_gas, 1)
     }
 
     fn ft_total_supply(&self) -> U128 {
-        self.token.ft_total_supply()
+        match &self.rebasing {
+            Some(rebasing) => (rebasing.pooled + rebasing.locked).into(),
+            None => (self.token.total_supply + self.internal_total_accrued()).into(),
+        }
     }
 
     fn ft_balance_of(&self, account_id: AccountId) -> U128 {
-        self.token.ft_balance_of(account_id)
+        self.internal_balance_of(&account_id).into()
     }
 }
 
 #[near]
 impl FungibleTokenResolver for Contract {
+    /// Returns the amount used by the receiver. If the sender unregistered while the transfer was
+    /// in flight, the refund is burned with an `ft_burn` event for the sender, so indexers
+    /// tracking supply from events stay in sync.
     #[private]
     fn ft_resolve_transfer(
         &mut self,
@@ -107,12 +948,11 @@ impl FungibleTokenResolver for Contract {
         receiver_id: AccountId,
         amount: U128,
     ) -> U128 {
-        let (used_amount, burned_amount) =
-            self.token
-                .internal_ft_resolve_transfer(&sender_id, receiver_id, amount);
-        if burned_amount > 0 {
-            log!("Account @{} burned {}", sender_id, burned_amount);
-        }
+        self.internal_accrue(&sender_id);
+        self.internal_accrue(&receiver_id);
+        let (used_amount, _) = self.internal_resolve_transfer(&sender_id, &receiver_id, amount.0);
+        self.internal_checkpoint(&sender_id);
+        self.internal_checkpoint(&receiver_id);
         used_amount.into()
     }
 }
@@ -125,18 +965,49 @@ impl StorageManagement for Contract {
         account_id: Option<AccountId>,
         registration_only: Option<bool>,
     ) -> StorageBalance {
+        if self.history.is_enabled() {
+            return self.internal_history_storage_deposit(account_id, registration_only);
+        }
         self.token.storage_deposit(account_id, registration_only)
     }
 
     #[payable]
     fn storage_withdraw(&mut self, amount: Option<NearToken>) -> StorageBalance {
+        if self.history.is_enabled() {
+            return self.internal_history_storage_withdraw(amount);
+        }
         self.token.storage_withdraw(amount)
     }
 
     #[payable]
     fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        if self.rebasing.is_some() && force.unwrap_or(false) {
+            // the standard burns the balance in shares, which would leave the pooled supply behind
+            require!(
+                self.internal_balance_record(&env::predecessor_account_id())
+                    .map_or(0, |(shares, _)| shares)
+                    == 0,
+                "Can't force unregister an account with a positive balance of a rebasing token"
+            );
+        }
+        // credits the accrued interest and drops the accrual checkpoint of the closed account
+        let predecessor_account_id = env::predecessor_account_id();
+        self.internal_accrue(&predecessor_account_id);
+        // the standard burns the whole record, which would include the history flag
+        if let Some((balance, true)) = self.internal_balance_record(&predecessor_account_id) {
+            self.internal_set_balance_record(&predecessor_account_id, balance, false);
+        }
         #[allow(unused_variables)]
         if let Some((account_id, balance)) = self.token.internal_storage_unregister(force) {
+            if let Some(deposit) = self.history.internal_remove(&account_id) {
+                Promise::new(account_id.clone()).transfer(deposit);
+            }
+            self.limiter.internal_remove(&account_id);
+            self.guardians.internal_remove(&account_id);
+            let initial_storage_usage = env::storage_usage();
+            if self.requires_memo.remove(&account_id) {
+                self.internal_refund_storage(initial_storage_usage, account_id.clone());
+            }
             log!("Closed @{} with {}", account_id, balance);
             true
         } else {
@@ -145,10 +1016,24 @@ impl StorageManagement for Contract {
     }
 
     fn storage_balance_bounds(&self) -> StorageBalanceBounds {
-        self.token.storage_balance_bounds()
+        let bounds = self.token.storage_balance_bounds();
+        if !self.history.is_enabled() {
+            return bounds;
+        }
+        StorageBalanceBounds {
+            max: Some(
+                bounds
+                    .min
+                    .saturating_add(self.history.storage_cost(self.history.max_length)),
+            ),
+            ..bounds
+        }
     }
 
     fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        if self.history.is_enabled() {
+            return self.internal_history_storage_balance_of(&account_id);
+        }
         self.token.storage_balance_of(account_id)
     }
 }
@@ -161,7 +1046,8 @@ impl FungibleTokenMetadataProvider for Contract {
 }
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
-mod tests {
+#[allow(clippy::bool_assert_comparison)]
+pub(crate) mod tests {
     use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
     use near_contract_standards::fungible_token::Balance;
     use near_sdk::test_utils::{accounts, VMContextBuilder};
@@ -169,41 +1055,46 @@ mod tests {
 
     use super::*;
 
-    const TOTAL_SUPPLY: Balance = 1_000_000_000_000_000;
+    pub(crate) const TOTAL_SUPPLY: Balance = 1_000_000_000_000_000;
 
-    fn current() -> AccountId {
+    pub(crate) fn current() -> AccountId {
         accounts(0)
     }
 
-    fn owner() -> AccountId {
+    pub(crate) fn owner() -> AccountId {
         accounts(1)
     }
 
-    fn user1() -> AccountId {
+    pub(crate) fn user1() -> AccountId {
         accounts(2)
     }
 
-    fn user2() -> AccountId {
+    pub(crate) fn user2() -> AccountId {
         accounts(3)
     }
 
     fn setup() -> (Contract, VMContextBuilder) {
-        let mut context = VMContextBuilder::new();
+        setup_with_config(ContractConfig::default())
+    }
+
+    pub(crate) fn metadata() -> FungibleTokenMetadata {
         const DATA_IMAGE_SVG_NEAR_ICON: &str = "data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 288 288'%3E%3Cg id='l' data-name='l'%3E%3Cpath d='M187.58,79.81l-30.1,44.69a3.2,3.2,0,0,0,4.75,4.2L191.86,103a1.2,1.2,0,0,1,2,.91v80.46a1.2,1.2,0,0,1-2.12.77L102.18,77.93A15.35,15.35,0,0,0,90.47,72.5H87.34A15.34,15.34,0,0,0,72,87.84V201.16A15.34,15.34,0,0,0,87.34,216.5h0a15.35,15.35,0,0,0,13.08-7.31l30.1-44.69a3.2,3.2,0,0,0-4.75-4.2L96.14,186a1.2,1.2,0,0,1-2-.91V104.61a1.2,1.2,0,0,1,2.12-.77l89.55,107.23a15.35,15.35,0,0,0,11.71,5.43h3.13A15.34,15.34,0,0,0,216,201.16V87.84A15.34,15.34,0,0,0,200.66,72.5h0A15.35,15.35,0,0,0,187.58,79.81Z'/%3E%3C/g%3E%3C/svg%3E";
 
-        let contract = Contract::new(
-            owner(),
-            TOTAL_SUPPLY.into(),
-            FungibleTokenMetadata {
-                spec: FT_METADATA_SPEC.to_string(),
-                name: "Example NEAR fungible token".to_string(),
-                symbol: "EXAMPLE".to_string(),
-                icon: Some(DATA_IMAGE_SVG_NEAR_ICON.to_string()),
-                reference: None,
-                reference_hash: None,
-                decimals: 24,
-            },
-        );
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Example NEAR fungible token".to_string(),
+            symbol: "EXAMPLE".to_string(),
+            icon: Some(DATA_IMAGE_SVG_NEAR_ICON.to_string()),
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
+    }
+
+    pub(crate) fn setup_with_config(config: ContractConfig) -> (Contract, VMContextBuilder) {
+        let mut context = VMContextBuilder::new();
+
+        let contract = Contract::new_with_config(owner(), TOTAL_SUPPLY.into(), metadata(), config);
 
         context.storage_usage(env::storage_usage());
         context.current_account_id(current());
@@ -221,6 +1112,105 @@ mod tests {
         assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
     }
 
+    #[test]
+    fn test_new_with_allocations() {
+        let contract = Contract::new_with_allocations(
+            metadata(),
+            vec![
+                (user1(), U128(100)),
+                (user2(), U128(200)),
+                (owner(), U128(700)),
+            ],
+            owner(),
+            None,
+        );
+
+        assert_eq!(contract.ft_total_supply().0, 1_000);
+        assert_eq!(contract.ft_balance_of(user1()).0, 100);
+        assert_eq!(contract.ft_balance_of(user2()).0, 200);
+        assert!(contract.storage_balance_of(owner()).is_some());
+        assert!(contract.storage_cost() > NearToken::from_yoctonear(0));
+
+        let mint_events: Vec<_> = near_sdk::test_utils::get_logs()
+            .into_iter()
+            .filter(|log| log.contains("\"event\":\"ft_mint\""))
+            .collect();
+        assert_eq!(mint_events.len(), 1);
+        assert!(
+            mint_events[0].contains(&format!("\"owner_id\":\"{}\",\"amount\":\"200\"", user2()))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate allocation to @charlie")]
+    fn test_new_with_allocations_panics_on_duplicates() {
+        Contract::new_with_allocations(
+            metadata(),
+            vec![
+                (user1(), U128(100)),
+                (user2(), U128(200)),
+                (user1(), U128(1)),
+            ],
+            owner(),
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The allocation to @bob should be a positive number")]
+    fn test_new_with_allocations_panics_on_zero_amount() {
+        Contract::new_with_allocations(
+            metadata(),
+            vec![(user1(), U128(100)), (owner(), U128(0))],
+            owner(),
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Registering 2 accounts requires")]
+    fn test_new_with_allocations_panics_when_balance_doesnt_cover_state() {
+        // enough for the registrations, but not for the rest of the storage of the contract
+        let registrations = NearToken::from_yoctonear(10u128.pow(19)).saturating_mul(2 * 125);
+        testing_env!(VMContextBuilder::new()
+            .account_balance(registrations)
+            .storage_usage(1_000_000)
+            .build());
+
+        Contract::new_with_allocations(
+            metadata(),
+            vec![(user1(), U128(100)), (user2(), U128(200))],
+            owner(),
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Total supply overflow")]
+    fn test_new_with_allocations_panics_on_overflow() {
+        Contract::new_with_allocations(
+            metadata(),
+            vec![(user1(), U128(u128::MAX)), (user2(), U128(1))],
+            owner(),
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Registering 2 accounts requires")]
+    fn test_new_with_allocations_panics_on_insufficient_balance() {
+        testing_env!(VMContextBuilder::new()
+            .account_balance(NearToken::from_yoctonear(1))
+            .build());
+
+        Contract::new_with_allocations(
+            metadata(),
+            vec![(user1(), U128(100)), (user2(), U128(200))],
+            owner(),
+            None,
+        );
+    }
+
     #[test]
     fn test_metadata() {
         let (contract, _) = setup();
@@ -653,6 +1643,66 @@ mod tests {
         contract.ft_transfer(user1(), transfer_amount.into(), None);
     }
 
+    /// Registers user1 and flags it as requiring a memo.
+    fn setup_requires_memo() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+        contract.ft_set_requires_memo(true);
+        assert!(contract.ft_requires_memo(user1()));
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .prepaid_gas(Gas::from_tgas(100))
+            .build());
+        (contract, context)
+    }
+
+    #[test]
+    fn test_transfer_with_required_memo() {
+        let (mut contract, mut context) = setup_requires_memo();
+
+        contract.ft_transfer(user1(), 10.into(), Some("deposit 19".to_string()));
+        assert_eq!(contract.ft_balance_of(user1()).0, 10);
+
+        // the memo is no longer required once the flag is cleared
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_set_requires_memo(false);
+        assert!(!contract.ft_requires_memo(user1()));
+        testing_env!(context.predecessor_account_id(owner()).build());
+        contract.ft_transfer(user1(), 10.into(), None);
+        assert_eq!(contract.ft_balance_of(user1()).0, 20);
+    }
+
+    #[should_panic(expected = "@charlie requires a memo")]
+    #[test]
+    fn test_transfer_panics_on_missing_required_memo() {
+        let (mut contract, _) = setup_requires_memo();
+
+        contract.ft_transfer(user1(), 10.into(), None);
+    }
+
+    #[should_panic(expected = "@charlie requires a memo")]
+    #[test]
+    fn test_transfer_panics_on_empty_required_memo() {
+        let (mut contract, _) = setup_requires_memo();
+
+        contract.ft_transfer(user1(), 10.into(), Some("".to_string()));
+    }
+
+    #[should_panic(expected = "@charlie requires a memo")]
+    #[test]
+    fn test_transfer_call_panics_on_missing_required_memo() {
+        let (mut contract, _) = setup_requires_memo();
+
+        contract.ft_transfer_call(user1(), 10.into(), None, "".to_string());
+    }
+
     #[test]
     fn test_transfer_call() {
         let (mut contract, mut context) = setup();
@@ -794,26 +1844,101 @@ mod tests {
         let transfer_amount = TOTAL_SUPPLY + 10;
         contract.ft_transfer_call(user1(), transfer_amount.into(), None, "".to_string());
     }
-    #[should_panic]
-    #[test]
-    fn test_transfer_call_panics_on_unsufficient_gas() {
+
+    /// Registers user1 and prepares a call from the owner with `prepaid_gas`.
+    fn setup_transfer_call(prepaid_gas: Gas) -> Contract {
         let (mut contract, mut context) = setup();
 
         testing_env!(context
             .predecessor_account_id(user1())
             .attached_deposit(contract.storage_balance_bounds().min)
             .build());
-
         // Paying for account registration of user1, aka storage deposit
         contract.storage_deposit(None, None);
 
         testing_env!(context
             .predecessor_account_id(owner())
             .attached_deposit(NearToken::from_yoctonear(1))
-            .prepaid_gas(Gas::from_tgas(10))
+            .prepaid_gas(prepaid_gas)
             .build());
+        contract
+    }
+
+    #[should_panic(expected = "More gas is required")]
+    #[test]
+    fn test_transfer_call_panics_on_unsufficient_gas() {
+        // nothing would be left for the receiver at the minimum
+        let mut contract = setup_transfer_call(GAS_FOR_FT_TRANSFER_CALL);
+        assert_eq!(
+            contract.ft_transfer_call_min_gas(None),
+            GAS_FOR_FT_TRANSFER_CALL
+        );
         let transfer_amount = TOTAL_SUPPLY / 10;
 
         contract.ft_transfer_call(user1(), transfer_amount.into(), None, "".to_string());
     }
+
+    #[test]
+    fn test_transfer_call_above_min_gas() {
+        let prepaid_gas = GAS_FOR_FT_TRANSFER_CALL
+            .checked_add(Gas::from_gas(1))
+            .unwrap();
+        let mut contract = setup_transfer_call(prepaid_gas);
+
+        contract.ft_transfer_call(user1(), (TOTAL_SUPPLY / 10).into(), None, "".to_string());
+        assert_eq!(contract.ft_balance_of(user1()).0, TOTAL_SUPPLY / 10);
+    }
+
+    #[test]
+    fn test_transfer_call_with_gas() {
+        let receiver_gas = Gas::from_tgas(50);
+        let prepaid_gas = GAS_FOR_FT_TRANSFER_CALL.checked_add(receiver_gas).unwrap();
+        let mut contract = setup_transfer_call(prepaid_gas);
+        assert_eq!(
+            contract.ft_transfer_call_min_gas(Some(receiver_gas)),
+            prepaid_gas
+        );
+
+        contract.ft_transfer_call_with_gas(
+            user1(),
+            (TOTAL_SUPPLY / 10).into(),
+            None,
+            "".to_string(),
+            receiver_gas,
+        );
+        assert_eq!(contract.ft_balance_of(user1()).0, TOTAL_SUPPLY / 10);
+    }
+
+    #[should_panic(expected = "More gas is required")]
+    #[test]
+    fn test_transfer_call_with_gas_panics_on_unsufficient_gas() {
+        let receiver_gas = Gas::from_tgas(50);
+        let prepaid_gas = GAS_FOR_FT_TRANSFER_CALL
+            .checked_add(receiver_gas)
+            .and_then(|gas| gas.checked_sub(Gas::from_gas(1)))
+            .unwrap();
+        let mut contract = setup_transfer_call(prepaid_gas);
+
+        contract.ft_transfer_call_with_gas(
+            user1(),
+            (TOTAL_SUPPLY / 10).into(),
+            None,
+            "".to_string(),
+            receiver_gas,
+        );
+    }
+
+    #[should_panic(expected = "The receiver gas should be positive")]
+    #[test]
+    fn test_transfer_call_with_gas_panics_on_zero_receiver_gas() {
+        let mut contract = setup_transfer_call(Gas::from_tgas(300));
+
+        contract.ft_transfer_call_with_gas(
+            user1(),
+            (TOTAL_SUPPLY / 10).into(),
+            None,
+            "".to_string(),
+            Gas::from_gas(0),
+        );
+    }
 }
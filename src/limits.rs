@@ -0,0 +1,490 @@
+/*!
+Transfer velocity limits and anti-whale caps.
+
+When the contract is initialized with `transfer_limits` in its config, the owner can cap:
+  - the amount of a single `ft_transfer` or `ft_transfer_call`,
+  - the balance an account can reach through transfers and mints,
+  - the amount an account can send within any 24 hours of block time. Outflows are counted in
+    hourly buckets, so an amount stops counting between 23 and 24 hours after it was sent.
+
+Tokens locked in escrows, streams and HTLCs count as sent when they are locked, and the receiver
+is checked against the maximum balance again when they are released. `bridge_out` counts towards
+the single transfer and daily caps. The owner and the accounts on the exemption list, like
+exchanges, are not limited. Refunds of `ft_transfer_call` and of locked tokens, accrued interest
+and flash loans are not limited either.
+
+A breach fails with `Transfer limit exceeded: ` followed by the JSON of the `LimitBreach`, so that
+frontends can parse the limit and the amounts involved.
+*/
+use near_contract_standards::fungible_token::Balance;
+use near_sdk::collections::{LookupMap, LookupSet};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde_json;
+use near_sdk::{
+    assert_one_yocto, env, log, near, require, AccountId, IntoStorageKey, StorageUsage,
+};
+
+use crate::{Contract, ContractExt};
+
+const HOUR: u64 = 60 * 60 * 1_000_000_000;
+const DAY: u64 = 24 * HOUR;
+/// Number of hourly buckets in the outflow window.
+const BUCKETS: u64 = DAY / HOUR;
+
+/// Limits not set are not enforced.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct TransferLimits {
+    pub max_transfer: Option<U128>,
+    pub max_balance: Option<U128>,
+    pub max_daily_outflow: Option<U128>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [json])]
+#[serde(rename_all = "snake_case")]
+pub enum LimitBreach {
+    MaxTransfer {
+        limit: U128,
+        amount: U128,
+    },
+    MaxBalance {
+        account_id: AccountId,
+        limit: U128,
+        /// Balance the account would reach.
+        balance: U128,
+    },
+    DailyOutflow {
+        limit: U128,
+        /// Amount already sent in the last 24 hours.
+        used: U128,
+        amount: U128,
+        /// Block timestamp in nanoseconds when the oldest amount of the window stops counting.
+        resets_at: U64,
+    },
+}
+
+#[near]
+struct Outflow {
+    /// Amounts sent in the hours of the last day, as (hour since the epoch, amount), oldest
+    /// first.
+    buckets: Vec<(u64, Balance)>,
+}
+
+impl Outflow {
+    /// Removes the buckets which are more than a day older than `hour`.
+    fn expire(&mut self, hour: u64) {
+        self.buckets
+            .retain(|&(bucket_hour, _)| bucket_hour + BUCKETS > hour);
+    }
+}
+
+#[near]
+pub struct Limiter {
+    /// The limits are disabled when not set.
+    pub limits: Option<TransferLimits>,
+
+    pub exempt: LookupSet<AccountId>,
+
+    outflows: LookupMap<AccountId, Outflow>,
+
+    /// Storage of the outflow of an account, paid with its registration.
+    pub outflow_storage_usage: StorageUsage,
+}
+
+impl Limiter {
+    pub fn new<E, O>(exempt_prefix: E, outflows_prefix: O, limits: Option<TransferLimits>) -> Self
+    where
+        E: IntoStorageKey,
+        O: IntoStorageKey,
+    {
+        let mut this = Self {
+            limits,
+            exempt: LookupSet::new(exempt_prefix),
+            outflows: LookupMap::new(outflows_prefix),
+            outflow_storage_usage: 0,
+        };
+        let tmp_account_id: AccountId = "a".repeat(64).parse().unwrap();
+        let initial_storage_usage = env::storage_usage();
+        this.outflows.insert(
+            &tmp_account_id,
+            &Outflow {
+                buckets: (0..BUCKETS).map(|hour| (hour, u128::MAX)).collect(),
+            },
+        );
+        this.outflow_storage_usage = env::storage_usage() - initial_storage_usage;
+        this.outflows.remove(&tmp_account_id);
+        this
+    }
+
+    /// Outflow of `account_id` in the last 24 hours.
+    fn current_outflow(&self, account_id: &AccountId) -> Outflow {
+        let mut outflow = self
+            .outflows
+            .get(account_id)
+            .unwrap_or(Outflow { buckets: vec![] });
+        outflow.expire(env::block_timestamp() / HOUR);
+        outflow
+    }
+
+    /// Amount sent by `account_id` in the last 24 hours, and the time the oldest part of it
+    /// stops counting.
+    fn current_outflow_amount(&self, account_id: &AccountId) -> (Balance, u64) {
+        let outflow = self.current_outflow(account_id);
+        let used = outflow
+            .buckets
+            .iter()
+            .fold(0u128, |used, &(_, amount)| used.saturating_add(amount));
+        let oldest_hour = outflow
+            .buckets
+            .first()
+            .map_or(env::block_timestamp() / HOUR, |&(hour, _)| hour);
+        (used, (oldest_hour + BUCKETS) * HOUR)
+    }
+
+    pub(crate) fn internal_remove(&mut self, account_id: &AccountId) {
+        self.outflows.remove(account_id);
+    }
+
+    pub(crate) fn internal_set_limits(&mut self, limits: TransferLimits) {
+        require!(self.limits.is_some(), "The transfer limits are disabled");
+        log!("Set the transfer limits to {:?}", limits);
+        self.limits = Some(limits);
+    }
+}
+
+fn fail(breach: LimitBreach) -> ! {
+    env::panic_str(&format!(
+        "Transfer limit exceeded: {}",
+        serde_json::to_string(&breach).unwrap_or_else(|_| env::abort())
+    ))
+}
+
+impl Contract {
+    fn internal_is_exempt(&self, account_id: &AccountId) -> bool {
+        account_id == &self.owner_id || self.limiter.exempt.contains(account_id)
+    }
+
+    /// Panics if `amount` would take the balance of `account_id` above the maximum balance.
+    pub(crate) fn internal_check_incoming(&self, account_id: &AccountId, amount: Balance) {
+        let Some(TransferLimits {
+            max_balance: Some(limit),
+            ..
+        }) = self.limiter.limits
+        else {
+            return;
+        };
+        if self.internal_is_exempt(account_id) {
+            return;
+        }
+        let balance = self.internal_balance_of(account_id).saturating_add(amount);
+        if balance > limit.0 {
+            fail(LimitBreach::MaxBalance {
+                account_id: account_id.clone(),
+                limit,
+                balance: balance.into(),
+            });
+        }
+    }
+
+    /// Panics if the transfer breaches a limit, and counts it towards the daily outflow of the
+    /// sender otherwise.
+    pub(crate) fn internal_check_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        self.internal_check_incoming(receiver_id, amount);
+        self.internal_check_outgoing(sender_id, amount);
+    }
+
+    /// Panics if sending `amount` breaches the single transfer or the daily cap of `sender_id`,
+    /// and counts it towards its daily outflow otherwise.
+    pub(crate) fn internal_check_outgoing(&mut self, sender_id: &AccountId, amount: Balance) {
+        let Some(limits) = self.limiter.limits.clone() else {
+            return;
+        };
+        if self.internal_is_exempt(sender_id) {
+            return;
+        }
+        if let Some(limit) = limits.max_transfer {
+            if amount > limit.0 {
+                fail(LimitBreach::MaxTransfer {
+                    limit,
+                    amount: amount.into(),
+                });
+            }
+        }
+        if let Some(limit) = limits.max_daily_outflow {
+            let (used, resets_at) = self.limiter.current_outflow_amount(sender_id);
+            if used.saturating_add(amount) > limit.0 {
+                fail(LimitBreach::DailyOutflow {
+                    limit,
+                    used: used.into(),
+                    amount: amount.into(),
+                    resets_at: resets_at.into(),
+                });
+            }
+            let hour = env::block_timestamp() / HOUR;
+            let mut outflow = self.limiter.current_outflow(sender_id);
+            match outflow.buckets.last_mut() {
+                Some((last_hour, sent)) if *last_hour == hour => *sent += amount,
+                _ => outflow.buckets.push((hour, amount)),
+            }
+            self.limiter.outflows.insert(sender_id, &outflow);
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Replaces the transfer limits. Can only be called by the owner, without a council or
+    /// timelock, and only if the limits were enabled at initialization.
+    #[payable]
+    pub fn limits_set(&mut self, limits: TransferLimits) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        self.limiter.internal_set_limits(limits);
+    }
+
+    /// Exempts `account_id` from the transfer limits. Can only be called by the owner. The
+    /// attached deposit must cover the storage of the exemption, the excess is refunded.
+    #[payable]
+    pub fn limits_add_exempt(&mut self, account_id: AccountId) {
+        let initial_storage_usage = env::storage_usage();
+        self.assert_owner();
+        require!(
+            self.limiter.exempt.insert(&account_id),
+            "The account is already exempt"
+        );
+        self.internal_charge_storage(initial_storage_usage);
+        log!("Exempted @{} from the transfer limits", account_id);
+    }
+
+    /// Subjects `account_id` to the transfer limits again. Can only be called by the owner.
+    #[payable]
+    pub fn limits_remove_exempt(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let initial_storage_usage = env::storage_usage();
+        require!(
+            self.limiter.exempt.remove(&account_id),
+            "The account is not exempt"
+        );
+        self.internal_refund_storage(initial_storage_usage, env::predecessor_account_id());
+        log!("Removed the exemption of @{}", account_id);
+    }
+
+    pub fn limits(&self) -> Option<TransferLimits> {
+        self.limiter.limits.clone()
+    }
+
+    pub fn limits_is_exempt(&self, account_id: AccountId) -> bool {
+        self.internal_is_exempt(&account_id)
+    }
+
+    /// Amount `account_id` sent in the last 24 hours.
+    pub fn limits_outflow_of(&self, account_id: AccountId) -> U128 {
+        self.limiter.current_outflow_amount(&account_id).0.into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, user2};
+    use crate::ContractConfig;
+
+    const LIMIT: u128 = 1_000;
+
+    /// Registers user1 and user2, and gives user1 tokens with all the limits set to `LIMIT`.
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            transfer_limits: Some(TransferLimits {
+                max_transfer: Some(LIMIT.into()),
+                max_balance: Some(LIMIT.into()),
+                max_daily_outflow: Some(LIMIT.into()),
+            }),
+            ..Default::default()
+        });
+
+        for account_id in [user1(), user2()].iter() {
+            testing_env!(context
+                .predecessor_account_id(account_id.clone())
+                .attached_deposit(contract.storage_balance_bounds().min)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), LIMIT.into(), None);
+        testing_env!(context.predecessor_account_id(user1()).build());
+        (contract, context)
+    }
+
+    #[test]
+    fn test_daily_outflow_resets() {
+        let (mut contract, mut context) = setup();
+
+        contract.ft_transfer(user2(), 600.into(), None);
+        testing_env!(context.predecessor_account_id(user2()).build());
+        contract.ft_transfer(user1(), 100.into(), None);
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_transfer(user2(), 400.into(), None);
+        assert_eq!(contract.limits_outflow_of(user1()).0, LIMIT);
+
+        testing_env!(context.block_timestamp(DAY).build());
+        assert_eq!(contract.limits_outflow_of(user1()).0, 0);
+        contract.ft_transfer(user2(), 100.into(), None);
+        assert_eq!(contract.limits_outflow_of(user1()).0, 100);
+    }
+
+    #[test]
+    fn test_daily_outflow_slides() {
+        let (mut contract, mut context) = setup();
+
+        contract.ft_transfer(user2(), 500.into(), None);
+        testing_env!(context.block_timestamp(12 * HOUR).build());
+        contract.ft_transfer(user2(), 400.into(), None);
+
+        // only the first transfer left the window
+        testing_env!(context.block_timestamp(DAY).build());
+        assert_eq!(contract.limits_outflow_of(user1()).0, 400);
+        testing_env!(context.block_timestamp(36 * HOUR).build());
+        assert_eq!(contract.limits_outflow_of(user1()).0, 0);
+    }
+
+    #[should_panic(
+        expected = r#"Transfer limit exceeded: {\"daily_outflow\":{\"limit\":\"1000\",\"used\":\"900\",\"amount\":\"101\",\"resets_at\":\"129600000000000\"}}"#
+    )]
+    #[test]
+    fn test_daily_outflow_doesnt_reset_at_once() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.block_timestamp(12 * HOUR).build());
+        contract.ft_transfer(user2(), 900.into(), None);
+        testing_env!(context.predecessor_account_id(user2()).build());
+        contract.ft_transfer(user1(), 900.into(), None);
+
+        // a window anchored at the first transfer would have ended
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .block_timestamp(DAY)
+            .build());
+        contract.ft_transfer(user2(), 101.into(), None);
+    }
+
+    #[should_panic(
+        expected = r#"Transfer limit exceeded: {\"max_transfer\":{\"limit\":\"1000\",\"amount\":\"1001\"}}"#
+    )]
+    #[test]
+    fn test_escrow_create_panics_on_max_transfer() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.limits_set(TransferLimits {
+            max_balance: Some((10 * LIMIT).into()),
+            ..contract.limits().unwrap()
+        });
+        contract.ft_transfer(user1(), 1.into(), None);
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.escrow_create(user2(), 1_001.into(), owner(), U64(DAY), None);
+    }
+
+    #[should_panic(
+        expected = r#"Transfer limit exceeded: {\"max_balance\":{\"account_id\":\"danny\",\"limit\":\"1000\",\"balance\":\"1500\"}}"#
+    )]
+    #[test]
+    fn test_escrow_release_panics_on_max_balance() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.attached_deposit(NearToken::from_near(1)).build());
+        contract.escrow_create(user2(), 500.into(), owner(), U64(DAY), None);
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user2(), 1_000.into(), None);
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.escrow_release(U64(0));
+    }
+
+    #[should_panic(
+        expected = r#"Transfer limit exceeded: {\"daily_outflow\":{\"limit\":\"1000\",\"used\":\"1000\",\"amount\":\"1\",\"resets_at\":\"86400000000000\"}}"#
+    )]
+    #[test]
+    fn test_transfer_panics_on_daily_outflow() {
+        let (mut contract, mut context) = setup();
+
+        contract.ft_transfer(user2(), 500.into(), None);
+        testing_env!(context.predecessor_account_id(user2()).build());
+        contract.ft_transfer(user1(), 500.into(), None);
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_transfer(user2(), 500.into(), None);
+        contract.ft_transfer(user2(), 1.into(), None);
+    }
+
+    #[should_panic(
+        expected = r#"Transfer limit exceeded: {\"max_transfer\":{\"limit\":\"1000\",\"amount\":\"1001\"}}"#
+    )]
+    #[test]
+    fn test_transfer_panics_on_max_transfer() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(owner()).build());
+        contract.limits_set(TransferLimits {
+            max_balance: Some((10 * LIMIT).into()),
+            ..contract.limits().unwrap()
+        });
+        contract.ft_transfer(user1(), 1.into(), None);
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_transfer_call(user2(), 1_001.into(), None, "".to_string());
+    }
+
+    #[should_panic(
+        expected = r#"Transfer limit exceeded: {\"max_balance\":{\"account_id\":\"charlie\",\"limit\":\"1000\",\"balance\":\"1001\"}}"#
+    )]
+    #[test]
+    fn test_transfer_panics_on_max_balance() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(owner()).build());
+        contract.ft_transfer(user1(), 1.into(), None);
+    }
+
+    #[test]
+    fn test_exempt_accounts() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.limits_add_exempt(user1());
+        assert!(contract.limits_is_exempt(user1()));
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), (10 * LIMIT).into(), None);
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.ft_transfer(owner(), (5 * LIMIT).into(), None);
+        assert_eq!(contract.limits_outflow_of(user1()).0, 0);
+    }
+}
@@ -0,0 +1,30 @@
+/*!
+256-bit arithmetic for the products of balances which can overflow u128.
+*/
+use near_sdk::env;
+
+mod u256 {
+    #![allow(clippy::all)]
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+}
+pub(crate) use u256::U256;
+
+/// `a * b / c`, rounded up or down.
+pub(crate) fn mul_div(a: u128, b: u128, c: u128, round_up: bool) -> u128 {
+    let c = U256::from(c);
+    let product = U256::from(a) * U256::from(b);
+    let mut result = product / c;
+    if round_up && !(product % c).is_zero() {
+        result += U256::one();
+    }
+    to_u128(result)
+}
+
+pub(crate) fn to_u128(value: U256) -> u128 {
+    if value > U256::from(u128::MAX) {
+        env::panic_str("Arithmetic overflow");
+    }
+    value.as_u128()
+}
@@ -0,0 +1,52 @@
+/*!
+Memo requirement of the accounts receiving transfers.
+
+Exchanges tell their deposits apart by memo. A registered account flags itself with
+`ft_set_requires_memo`, after which `ft_transfer` and `ft_transfer_call` to it fail without a
+non-empty memo, instead of leaving the tokens unattributed. Wallets can check `ft_requires_memo`
+to ask for the memo up front. The account pays for the storage of the flag with the attached
+deposit, and gets it back when it clears the flag.
+*/
+use near_sdk::{env, near, require, AccountId};
+
+use crate::{Contract, ContractExt};
+
+impl Contract {
+    /// Requires a non-empty `memo` for transfers to `receiver_id` if it flagged itself.
+    pub(crate) fn internal_assert_memo(&self, receiver_id: &AccountId, memo: Option<&str>) {
+        if memo.unwrap_or_default().is_empty() {
+            require!(
+                !self.requires_memo.contains(receiver_id),
+                format!("@{} requires a memo", receiver_id)
+            );
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Sets whether transfers to the predecessor, which should be registered, require a memo.
+    /// Setting the flag requires a deposit covering its storage, the excess is refunded. Clearing
+    /// it requires at least 1 yoctoNEAR and refunds the storage.
+    #[payable]
+    pub fn ft_set_requires_memo(&mut self, requires_memo: bool) {
+        let initial_storage_usage = env::storage_usage();
+        require!(
+            !env::attached_deposit().is_zero(),
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        let account_id = env::predecessor_account_id();
+        self.token.internal_unwrap_balance_of(&account_id);
+        if requires_memo {
+            self.requires_memo.insert(&account_id);
+            self.internal_charge_storage(initial_storage_usage);
+        } else {
+            self.requires_memo.remove(&account_id);
+            self.internal_refund_storage(initial_storage_usage, account_id);
+        }
+    }
+
+    pub fn ft_requires_memo(&self, account_id: AccountId) -> bool {
+        self.requires_memo.contains(&account_id)
+    }
+}
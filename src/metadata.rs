@@ -0,0 +1,170 @@
+/*!
+Validation of the token metadata, stricter than `FungibleTokenMetadata::assert_valid`.
+
+`ft_metadata` returns the whole icon, so a huge icon makes every wallet pay for it. The icon must
+be a data URL of an SVG image, or of a base64-encoded PNG image, of at most `MAX_ICON_LENGTH`
+bytes. The reference and its hash must be set together, the hash being the 32-byte SHA-256 of the
+content of the reference.
+
+The token factory compiles this module too, to validate the metadata before creating a token.
+*/
+use near_contract_standards::fungible_token::metadata::{FungibleTokenMetadata, FT_METADATA_SPEC};
+use near_sdk::base64::{engine::general_purpose::STANDARD, Engine};
+use near_sdk::{env, require};
+
+/// Maximum size in bytes of the icon.
+pub const MAX_ICON_LENGTH: usize = 16 * 1024;
+
+const SVG_PREFIX: &str = "data:image/svg+xml";
+const PNG_PREFIX: &str = "data:image/png;base64";
+
+/// Validates `metadata` before it is stored by `new` or by a metadata update.
+pub fn assert_valid_metadata(metadata: &FungibleTokenMetadata) {
+    require!(
+        metadata.spec == FT_METADATA_SPEC,
+        format!("The metadata spec should be {}", FT_METADATA_SPEC)
+    );
+    if let Some(icon) = &metadata.icon {
+        assert_valid_icon(icon);
+    }
+    require!(
+        metadata.reference.is_some() == metadata.reference_hash.is_some(),
+        "The reference and the reference hash should be set together"
+    );
+    if let Some(reference_hash) = &metadata.reference_hash {
+        require!(
+            reference_hash.0.len() == 32,
+            format!(
+                "The reference hash should be the 32-byte SHA-256 of the reference content, got {} bytes",
+                reference_hash.0.len()
+            )
+        );
+    }
+}
+
+fn assert_valid_icon(icon: &str) {
+    require!(
+        icon.len() <= MAX_ICON_LENGTH,
+        format!(
+            "The icon is {} bytes, the maximum is {} bytes",
+            icon.len(),
+            MAX_ICON_LENGTH
+        )
+    );
+    let (header, data) = icon.split_once(',').unwrap_or_else(|| {
+        env::panic_str("The icon should be a data URL, with a comma before the data")
+    });
+    if header == PNG_PREFIX {
+        require!(
+            STANDARD.decode(data).is_ok(),
+            "The data of the PNG icon should be base64-encoded"
+        );
+    } else {
+        // the SVG media type can have parameters, like `;base64` or `;charset=utf-8`
+        require!(
+            header == SVG_PREFIX || header.starts_with(&format!("{};", SVG_PREFIX)),
+            format!(
+                "The icon should be a data URL starting with {} or {}",
+                SVG_PREFIX, PNG_PREFIX
+            )
+        );
+    }
+    require!(!data.is_empty(), "The icon data should not be empty");
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::json_types::Base64VecU8;
+
+    use super::*;
+
+    // the factory compiles this module as well, so the tests don't use the helpers of the token
+    fn metadata() -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Example NEAR fungible token".to_string(),
+            symbol: "EXAMPLE".to_string(),
+            icon: Some("data:image/svg+xml,<svg/>".to_string()),
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
+    }
+
+    fn with_icon(icon: &str) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            icon: Some(icon.to_string()),
+            ..metadata()
+        }
+    }
+
+    #[test]
+    fn test_valid_icons() {
+        assert_valid_metadata(&metadata());
+        assert_valid_metadata(&with_icon("data:image/svg+xml;base64,PHN2Zy8+"));
+        assert_valid_metadata(&with_icon("data:image/svg+xml;charset=utf-8,<svg/>"));
+        assert_valid_metadata(&with_icon("data:image/png;base64,iVBORw0KGgo="));
+        assert_valid_metadata(&FungibleTokenMetadata {
+            icon: None,
+            ..metadata()
+        });
+    }
+
+    #[should_panic(expected = "The icon is 16385 bytes, the maximum is 16384 bytes")]
+    #[test]
+    fn test_panics_on_large_icon() {
+        let icon = format!(
+            "{},{}",
+            SVG_PREFIX,
+            "a".repeat(MAX_ICON_LENGTH - SVG_PREFIX.len())
+        );
+        assert_valid_metadata(&with_icon(&icon));
+    }
+
+    #[should_panic(
+        expected = "The icon should be a data URL starting with data:image/svg+xml or data:image/png;base64"
+    )]
+    #[test]
+    fn test_panics_on_other_mime_type() {
+        assert_valid_metadata(&with_icon("data:image/jpeg;base64,/9j/4AAQ"));
+    }
+
+    #[should_panic(expected = "The icon should be a data URL starting with")]
+    #[test]
+    fn test_panics_on_svg_lookalike_mime_type() {
+        assert_valid_metadata(&with_icon("data:image/svg+xmlfoo,<svg/>"));
+    }
+
+    #[should_panic(expected = "The icon should be a data URL, with a comma before the data")]
+    #[test]
+    fn test_panics_on_plain_url_icon() {
+        assert_valid_metadata(&with_icon("https://example.com/icon.svg"));
+    }
+
+    #[should_panic(expected = "The data of the PNG icon should be base64-encoded")]
+    #[test]
+    fn test_panics_on_invalid_png_data() {
+        assert_valid_metadata(&with_icon("data:image/png;base64,not base64!"));
+    }
+
+    #[should_panic(
+        expected = "The reference hash should be the 32-byte SHA-256 of the reference content, got 3 bytes"
+    )]
+    #[test]
+    fn test_panics_on_short_reference_hash() {
+        assert_valid_metadata(&FungibleTokenMetadata {
+            reference: Some("https://example.com/token.json".to_string()),
+            reference_hash: Some(Base64VecU8(vec![1, 2, 3])),
+            ..metadata()
+        });
+    }
+
+    #[should_panic(expected = "The reference and the reference hash should be set together")]
+    #[test]
+    fn test_panics_on_reference_without_hash() {
+        assert_valid_metadata(&FungibleTokenMetadata {
+            reference: Some("https://example.com/token.json".to_string()),
+            ..metadata()
+        });
+    }
+}
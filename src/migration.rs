@@ -0,0 +1,299 @@
+/*!
+Migration of holders from other tokens.
+
+The owner registers the source tokens this token accepts, each with a rate and a payout. Holders
+migrate by calling `ft_transfer_call` on the source token with this contract as the receiver and
+`"migrate"` as the message. They are paid `amount * numerator / denominator` of this token,
+rounded down, which is either:
+  - `Mint`: minted, increasing the total supply,
+  - `Transfer`: transferred from the balance of the owner. The payout is capped by that balance.
+
+The part of the amount that wasn't converted is returned from `ft_on_transfer` and refunded by the
+source token. The converted source tokens stay with the contract. The holder should be registered
+with this token, otherwise the whole amount is refunded.
+*/
+use near_contract_standards::fungible_token::events::FtMint;
+use near_contract_standards::fungible_token::Balance;
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, IntoStorageKey};
+
+use crate::math::mul_div;
+use crate::{Contract, ContractExt};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub enum MigrationPayout {
+    Mint,
+    Transfer,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct MigrationSource {
+    /// Tokens paid for `denominator` source tokens.
+    pub numerator: U128,
+    pub denominator: U128,
+    pub payout: MigrationPayout,
+    /// Source tokens converted so far.
+    pub received: U128,
+    /// Tokens paid so far.
+    pub issued: U128,
+}
+
+#[near(event_json(standard = "ft-migration"))]
+pub enum MigrationEvent {
+    #[event_version("1.0.0")]
+    Migrate {
+        token_id: AccountId,
+        account_id: AccountId,
+        /// Amount of the source token converted.
+        amount: U128,
+        /// Amount of this token paid.
+        issued: U128,
+    },
+}
+
+#[near]
+pub struct Migration {
+    /// Source token account id -> source.
+    pub sources: UnorderedMap<AccountId, MigrationSource>,
+}
+
+impl Migration {
+    pub(crate) fn internal_set_source(
+        &mut self,
+        token_id: AccountId,
+        numerator: U128,
+        denominator: U128,
+        payout: MigrationPayout,
+    ) {
+        require!(
+            numerator.0 > 0 && denominator.0 > 0,
+            "The rate should be a positive number"
+        );
+        let source = match self.sources.get(&token_id) {
+            Some(source) => MigrationSource {
+                numerator,
+                denominator,
+                payout,
+                ..source
+            },
+            None => MigrationSource {
+                numerator,
+                denominator,
+                payout,
+                received: U128(0),
+                issued: U128(0),
+            },
+        };
+        self.sources.insert(&token_id, &source);
+    }
+
+    pub fn new<S>(prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            sources: UnorderedMap::new(prefix),
+        }
+    }
+}
+
+impl Contract {
+    /// Pays `sender_id` for `amount` of `token_id` received through `ft_on_transfer`. Returns the
+    /// unused amount.
+    pub(crate) fn internal_migrate(
+        &mut self,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) -> Balance {
+        let mut source = self
+            .migration
+            .sources
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("The token is not a migration source"));
+        let (numerator, denominator) = (source.numerator.0, source.denominator.0);
+        let mut issued = mul_div(amount, numerator, denominator, false);
+        if source.payout == MigrationPayout::Transfer {
+            issued = issued.min(self.internal_balance_of(&self.owner_id));
+        }
+        if issued == 0 {
+            return amount;
+        }
+        let used = mul_div(issued, denominator, numerator, true);
+
+        match source.payout {
+            MigrationPayout::Mint => {
+                self.internal_check_incoming(sender_id, issued);
+                self.internal_assert_verified(sender_id);
+                self.internal_deposit(sender_id, issued);
+                FtMint {
+                    owner_id: sender_id,
+                    amount: issued.into(),
+                    memo: Some("migration"),
+                }
+                .emit();
+            }
+            MigrationPayout::Transfer => {
+                let owner_id = self.owner_id.clone();
+                self.internal_transfer(&owner_id, sender_id, issued, Some("migration".to_string()));
+            }
+        }
+        source.received = source.received.0.saturating_add(used).into();
+        source.issued = source.issued.0.saturating_add(issued).into();
+        self.migration.sources.insert(token_id, &source);
+
+        MigrationEvent::Migrate {
+            token_id: token_id.clone(),
+            account_id: sender_id.clone(),
+            amount: used.into(),
+            issued: issued.into(),
+        }
+        .emit();
+
+        amount - used
+    }
+}
+
+#[near]
+impl Contract {
+    /// Accepts `token_id` as a migration source paying `numerator / denominator` of this token per
+    /// source token, or updates its rate and payout. Can only be called by the owner, without a
+    /// council or timelock. The attached deposit must cover the storage of a new source, the
+    /// excess is refunded.
+    #[payable]
+    pub fn migration_set_source(
+        &mut self,
+        token_id: AccountId,
+        numerator: U128,
+        denominator: U128,
+        payout: MigrationPayout,
+    ) {
+        let initial_storage_usage = env::storage_usage();
+        require!(
+            !env::attached_deposit().is_zero(),
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        self.assert_owner_setting();
+        self.migration
+            .internal_set_source(token_id, numerator, denominator, payout);
+        self.internal_charge_storage(initial_storage_usage);
+    }
+
+    /// Stops accepting `token_id` as a migration source. Can only be called by the owner.
+    #[payable]
+    pub fn migration_remove_source(&mut self, token_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let initial_storage_usage = env::storage_usage();
+        require!(
+            self.migration.sources.remove(&token_id).is_some(),
+            "The token is not a migration source"
+        );
+        self.internal_refund_storage(initial_storage_usage, env::predecessor_account_id());
+    }
+
+    pub fn migration_source(&self, token_id: AccountId) -> Option<MigrationSource> {
+        self.migration.sources.get(&token_id)
+    }
+
+    pub fn migration_sources(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<(AccountId, MigrationSource)> {
+        self.migration
+            .sources
+            .iter()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.unwrap_or(10) as usize)
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::fungible_token::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken, PromiseOrValue};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    fn legacy_token() -> AccountId {
+        "legacy-token.near".parse().unwrap()
+    }
+
+    /// Registers user1 and accepts the legacy token at 2 tokens per 3 legacy tokens.
+    fn setup(payout: MigrationPayout) -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.migration_set_source(legacy_token(), U128(2), U128(3), payout);
+
+        testing_env!(context.predecessor_account_id(legacy_token()).build());
+        (contract, context)
+    }
+
+    fn migrate(contract: &mut Contract, amount: Balance) -> Balance {
+        match contract.ft_on_transfer(user1(), amount.into(), r#""migrate""#.to_string()) {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_migrate_with_mint() {
+        let (mut contract, _) = setup(MigrationPayout::Mint);
+
+        // 1_000 legacy tokens pay 666 tokens for 999 of them
+        assert_eq!(migrate(&mut contract, 1_000), 1);
+
+        assert_eq!(contract.ft_balance_of(user1()).0, 666);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + 666);
+        let source = contract.migration_source(legacy_token()).unwrap();
+        assert_eq!((source.received.0, source.issued.0), (999, 666));
+    }
+
+    #[test]
+    fn test_migrate_with_transfer_is_capped_by_owner_balance() {
+        let (mut contract, _) = setup(MigrationPayout::Transfer);
+
+        let amount = TOTAL_SUPPLY * 2;
+        assert_eq!(
+            migrate(&mut contract, amount),
+            amount - TOTAL_SUPPLY / 2 * 3
+        );
+        assert_eq!(contract.ft_balance_of(user1()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(owner()).0, 0);
+
+        // nothing is left to pay out
+        assert_eq!(migrate(&mut contract, 3), 3);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+    }
+
+    #[should_panic(expected = "The token is not a migration source")]
+    #[test]
+    fn test_migrate_panics_on_unknown_token() {
+        let (mut contract, mut context) = setup(MigrationPayout::Mint);
+
+        testing_env!(context
+            .predecessor_account_id("other-token.near".parse().unwrap())
+            .build());
+        migrate(&mut contract, 1_000);
+    }
+}
@@ -0,0 +1,90 @@
+/*!
+Ownership of the contract.
+
+The owner is the account given as `owner_id` at initialization. It manages the privileged
+settings of the optional features, and can hand the ownership over to another account. With a
+council, the settings which can mint tokens or grant powers, and the ownership itself, are changed
+by the council with an `AdminAction` instead. With the timelock, the owner schedules them.
+*/
+use near_sdk::{assert_one_yocto, env, log, near, require, AccountId};
+
+use crate::{Contract, ContractExt};
+
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        require!(
+            env::predecessor_account_id() == self.owner_id,
+            "Only the owner can call this method"
+        );
+    }
+
+    /// Requires the predecessor to be the owner, for the settings which have an `AdminAction`
+    /// counterpart. They can't be changed directly by the owner with a council or the timelock.
+    pub(crate) fn assert_owner_setting(&self) {
+        self.assert_owner();
+        require!(
+            !self.council.is_enabled(),
+            "This setting is changed by the council"
+        );
+        require!(
+            self.timelock.delay.is_none(),
+            "This setting is changed through the timelock"
+        );
+    }
+
+    pub(crate) fn internal_set_owner(&mut self, owner_id: AccountId) {
+        log!("Owner changed from @{} to @{}", self.owner_id, owner_id);
+        self.owner_id = owner_id;
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    /// Hands the ownership of the contract over to `owner_id`. Can only be called by the owner,
+    /// without a council or timelock.
+    #[payable]
+    pub fn set_owner(&mut self, owner_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        self.internal_set_owner(owner_id);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::NearToken;
+
+    use crate::tests::{owner, setup_with_config, user1};
+    use crate::ContractConfig;
+
+    #[test]
+    fn test_set_owner() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+        assert_eq!(contract.get_owner(), owner());
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.set_owner(user1());
+
+        assert_eq!(contract.get_owner(), user1());
+    }
+
+    #[should_panic(expected = "Only the owner can call this method")]
+    #[test]
+    fn test_set_owner_panics_on_non_owner() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.set_owner(user1());
+    }
+}
@@ -0,0 +1,302 @@
+/*!
+Rebasing (elastic supply) mode.
+
+When switched on at initialization, the contract stores shares instead of balances. The balance of
+an account is its share of the pooled supply, which an account with the `Oracle` role sets with
+`rebase`. Transfers move shares, so all balances scale together when the pooled supply changes.
+
+Rounding rules:
+  - Balances are rounded down.
+  - Transfers, locks and burns take the amount rounded up to whole shares from the sender, so the
+    receiver gets at least the requested amount and no other balance decreases.
+  - Mints, unlocks and refunds of `ft_transfer_call` give the amount rounded down to whole shares,
+    so they never create tokens.
+
+Tokens locked by the contract (escrows, streams, HTLCs and the bridge) are taken out of the pool
+and don't rebase. They are part of the total supply.
+*/
+use near_contract_standards::fungible_token::{Balance, FungibleTokenCore};
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId};
+
+use crate::math::mul_div;
+use crate::roles::Role;
+use crate::{Contract, ContractExt};
+
+#[near(event_json(standard = "ft-rebase"))]
+pub enum RebaseEvent {
+    #[event_version("1.0.0")]
+    Rebase {
+        old_total_supply: U128,
+        new_total_supply: U128,
+    },
+}
+
+#[derive(Default)]
+#[near]
+pub struct Rebasing {
+    /// Amount of tokens represented by all the shares.
+    pub pooled: Balance,
+
+    /// Amount of tokens locked by the contract, which is outside of the pool.
+    pub locked: Balance,
+}
+
+impl Rebasing {
+    fn shares_of(&self, amount: Balance, total_shares: Balance, round_up: bool) -> Balance {
+        if total_shares == 0 {
+            amount
+        } else {
+            mul_div(amount, total_shares, self.pooled, round_up)
+        }
+    }
+
+    fn amount_of(&self, shares: Balance, total_shares: Balance) -> Balance {
+        if total_shares == 0 {
+            0
+        } else {
+            mul_div(shares, self.pooled, total_shares, false)
+        }
+    }
+}
+
+impl Contract {
+    /// Shares representing `amount`. The amount itself when the token isn't rebasing.
+    pub(crate) fn internal_shares_of(&self, amount: Balance, round_up: bool) -> Balance {
+        match &self.rebasing {
+            Some(rebasing) => rebasing.shares_of(amount, self.token.total_supply, round_up),
+            None => amount,
+        }
+    }
+
+    /// Amount represented by `shares`. The shares themselves when the token isn't rebasing.
+    pub(crate) fn internal_amount_of(&self, shares: Balance) -> Balance {
+        match &self.rebasing {
+            Some(rebasing) => rebasing.amount_of(shares, self.token.total_supply),
+            None => shares,
+        }
+    }
+
+    /// Mints shares worth `amount`, rounded down, to `account_id` and adds the amount to the pool.
+    pub(crate) fn internal_rebasing_deposit(&mut self, account_id: &AccountId, amount: Balance) {
+        let shares = self.internal_shares_of(amount, false);
+        self.internal_deposit_shares(account_id, shares);
+        let rebasing = self.rebasing.as_mut().unwrap();
+        rebasing.pooled = rebasing
+            .pooled
+            .checked_add(amount)
+            .unwrap_or_else(|| env::panic_str("Total supply overflow"));
+    }
+
+    /// Burns shares worth `amount`, rounded up, from `account_id` and takes the amount out of the
+    /// pool.
+    pub(crate) fn internal_rebasing_withdraw(&mut self, account_id: &AccountId, amount: Balance) {
+        let shares = self.internal_shares_of(amount, true);
+        self.internal_withdraw_shares(account_id, shares);
+        let total_shares = self.token.total_supply;
+        let rebasing = self.rebasing.as_mut().unwrap();
+        // the pool is burned with its last shares, so that no one gets the rounding dust
+        rebasing.pooled = if total_shares == 0 {
+            0
+        } else {
+            rebasing.pooled.saturating_sub(amount)
+        };
+    }
+}
+
+#[near]
+impl Contract {
+    /// Sets the total supply of the rebasing token, scaling all balances in proportion. Locked
+    /// tokens don't rebase, so the new total supply can't be below them. Requires the `Oracle`
+    /// role.
+    #[payable]
+    pub fn rebase(&mut self, new_total_supply: U128) {
+        assert_one_yocto();
+        self.assert_role(Role::Oracle);
+        let old_total_supply = self.ft_total_supply();
+        let total_shares = self.token.total_supply;
+        let rebasing = self
+            .rebasing
+            .as_mut()
+            .unwrap_or_else(|| env::panic_str("The token is not rebasing"));
+        require!(total_shares > 0, "There are no shares to rebase");
+        let pooled = new_total_supply
+            .0
+            .checked_sub(rebasing.locked)
+            .filter(|pooled| *pooled > 0)
+            .unwrap_or_else(|| {
+                env::panic_str("The new total supply should be above the locked amount")
+            });
+        rebasing.pooled = pooled;
+
+        RebaseEvent::Rebase {
+            old_total_supply,
+            new_total_supply,
+        }
+        .emit();
+    }
+
+    /// Shares of `account_id`. Same as the balance when the token isn't rebasing.
+    pub fn rebase_shares_of(&self, account_id: AccountId) -> U128 {
+        self.internal_balance_record(&account_id)
+            .map_or(0, |(shares, _)| shares)
+            .into()
+    }
+
+    pub fn rebase_total_shares(&self) -> U128 {
+        self.token.total_supply.into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken, PromiseResult, RuntimeFeesConfig};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            rebasing: true,
+            ..Default::default()
+        });
+
+        for account_id in [user1(), user2()] {
+            testing_env!(context
+                .predecessor_account_id(account_id)
+                .attached_deposit(contract.storage_balance_bounds().min)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.grant_role(Role::Oracle, user2());
+
+        (contract, context)
+    }
+
+    fn transfer(contract: &mut Contract, context: &mut VMContextBuilder, amount: u128) {
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), amount.into(), None);
+    }
+
+    fn rebase(contract: &mut Contract, context: &mut VMContextBuilder, total_supply: u128) {
+        testing_env!(context
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.rebase(total_supply.into());
+    }
+
+    #[test]
+    fn test_rebase_scales_balances() {
+        let (mut contract, mut context) = setup();
+        transfer(&mut contract, &mut context, TOTAL_SUPPLY / 4);
+
+        rebase(&mut contract, &mut context, TOTAL_SUPPLY * 2);
+
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY * 2);
+        assert_eq!(contract.ft_balance_of(user1()).0, TOTAL_SUPPLY / 2);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY * 3 / 2);
+        assert_eq!(contract.rebase_shares_of(user1()).0, TOTAL_SUPPLY / 4);
+    }
+
+    #[test]
+    fn test_transfer_rounds_shares_up() {
+        let (mut contract, mut context) = setup();
+        // 3 tokens per 2 shares
+        rebase(&mut contract, &mut context, TOTAL_SUPPLY * 3 / 2);
+
+        transfer(&mut contract, &mut context, 1);
+
+        assert_eq!(contract.rebase_shares_of(user1()).0, 1);
+        assert_eq!(contract.ft_balance_of(user1()).0, 1);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY * 3 / 2);
+    }
+
+    #[test]
+    fn test_refund_never_creates_tokens() {
+        let (mut contract, mut context) = setup();
+        // 2 tokens per 3 shares
+        rebase(&mut contract, &mut context, TOTAL_SUPPLY * 2 / 3);
+        transfer(&mut contract, &mut context, 1_000);
+        let balances = contract.ft_balance_of(owner()).0 + contract.ft_balance_of(user1()).0;
+
+        // the receiver returns an amount which doesn't map to whole shares
+        testing_env!(
+            context
+                .predecessor_account_id(crate::tests::current())
+                .build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"\"999\"".to_vec())]
+        );
+        let used = contract.ft_resolve_transfer(owner(), user1(), U128(1_000));
+
+        assert!(used.0 >= 1);
+        assert!(contract.ft_balance_of(owner()).0 + contract.ft_balance_of(user1()).0 <= balances);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY * 2 / 3);
+    }
+
+    #[test]
+    fn test_locked_tokens_dont_rebase() {
+        let (mut contract, mut context) = setup();
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.htlc_lock(
+            user1(),
+            U128(TOTAL_SUPPLY / 2),
+            env::sha256_array(b"secret").into(),
+            1_000.into(),
+        );
+
+        rebase(&mut contract, &mut context, TOTAL_SUPPLY * 3 / 2);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+
+        contract.htlc_claim(0.into(), b"secret".to_vec().into());
+        assert_eq!(contract.ft_balance_of(user1()).0, TOTAL_SUPPLY / 2);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY * 3 / 2);
+    }
+
+    #[should_panic(expected = "Requires the Oracle role")]
+    #[test]
+    fn test_rebase_panics_without_oracle_role() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.rebase(TOTAL_SUPPLY.into());
+    }
+
+    #[should_panic(expected = "The token is not rebasing")]
+    #[test]
+    fn test_rebase_panics_when_not_rebasing() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.grant_role(Role::Oracle, owner());
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.rebase(TOTAL_SUPPLY.into());
+    }
+}
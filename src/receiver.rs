@@ -0,0 +1,50 @@
+/*!
+Handling of NEP-141 tokens sent to this contract with `ft_transfer_call`.
+
+The `msg` of the transfer is a JSON-encoded [`TokenReceiverMessage`], which tells the contract
+what to do with the received tokens. Transfers with an empty message are kept as sent by mistake,
+and can be recovered by the owner with `recover_tokens`, if the owner listed the token with
+`recovery_add_token`. Transfers with a message that can't be
+parsed are refunded.
+*/
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{env, near, serde_json, AccountId, PromiseOrValue};
+
+use crate::{Contract, ContractExt};
+
+#[near(serializers = [json])]
+#[serde(rename_all = "snake_case")]
+pub enum TokenReceiverMessage {
+    /// Funds the stream with the given id, see `stream_create`.
+    FundStream { stream_id: U64 },
+    /// Swaps the tokens of a migration source for this token, see `migration_set_source`.
+    Migrate,
+}
+
+#[near]
+impl FungibleTokenReceiver for Contract {
+    /// Accepts tokens sent with one of the [`TokenReceiverMessage`] messages. Returns the amount
+    /// that wasn't used, which the token contract refunds to the sender.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let token_id = env::predecessor_account_id();
+        if msg.is_empty() {
+            let unused_amount = self.internal_record_stray_deposit(&token_id, &sender_id, amount.0);
+            return PromiseOrValue::Value(U128(unused_amount));
+        }
+        let message: TokenReceiverMessage = serde_json::from_str(&msg)
+            .unwrap_or_else(|_| env::panic_str("Invalid transfer message"));
+        let unused_amount = match message {
+            TokenReceiverMessage::FundStream { stream_id } => {
+                self.internal_fund_stream(&token_id, &sender_id, stream_id, amount.0)
+            }
+            TokenReceiverMessage::Migrate => self.internal_migrate(&token_id, &sender_id, amount.0),
+        };
+        PromiseOrValue::Value(U128(unused_amount))
+    }
+}
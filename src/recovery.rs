@@ -0,0 +1,319 @@
+/*!
+Recovery of tokens sent to the contract account by mistake.
+
+Tokens of this contract sent to its own account are simply held in its balance. Tokens of other
+NEP-141 contracts listed by the owner with `recovery_add_token` and sent with `ft_transfer_call`
+and an empty message are accepted by `ft_on_transfer` and recorded per token, separately from the
+tokens funding streams. The owner sends either back with `recover_tokens`. Unlisted tokens sent
+with an empty message are refunded, since anybody can call `ft_on_transfer` and the contract would
+otherwise pay for the entries of made-up tokens. Other tokens sent with a plain `ft_transfer`
+don't notify the contract, so they can't be told apart from the stream deposits and can't be
+recovered.
+
+The owner pays for the storage of the listed tokens, one entry per token, with the deposit
+attached to `recovery_add_token`, and gets it back with `recovery_remove_token`.
+*/
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::Balance;
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{
+    assert_one_yocto, env, log, near, require, AccountId, Gas, IntoStorageKey, NearToken,
+    PromiseResult,
+};
+
+use crate::{Contract, ContractExt};
+
+const GAS_FOR_FT_TRANSFER: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_RECOVER: Gas = Gas::from_tgas(5);
+
+#[near]
+pub struct Recovery {
+    /// Token account id -> amount received by mistake.
+    pub deposits: UnorderedMap<AccountId, Balance>,
+}
+
+impl Recovery {
+    pub fn new<S>(prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            deposits: UnorderedMap::new(prefix),
+        }
+    }
+
+    fn internal_add(&mut self, token_id: &AccountId, amount: Balance) {
+        let balance = self.deposits.get(token_id).unwrap_or(0);
+        self.deposits.insert(
+            token_id,
+            &balance
+                .checked_add(amount)
+                .unwrap_or_else(|| env::panic_str("Balance overflow")),
+        );
+    }
+}
+
+impl Contract {
+    /// Records `amount` of `token_id` received by mistake from `sender_id`, if the token is
+    /// listed. Returns the unused amount, which is the whole amount for unlisted tokens.
+    pub(crate) fn internal_record_stray_deposit(
+        &mut self,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) -> Balance {
+        // tokens of this contract are already in the balance of the contract account
+        if token_id != &env::current_account_id() {
+            if self.recovery.deposits.get(token_id).is_none() {
+                log!(
+                    "Refunded {} of @{}, which is not recoverable",
+                    amount,
+                    token_id
+                );
+                return amount;
+            }
+            self.recovery.internal_add(token_id, amount);
+        }
+        log!("Received {} of @{} from @{}", amount, token_id, sender_id);
+        0
+    }
+}
+
+#[near]
+impl Contract {
+    /// Sends `amount` of the tokens held by the contract account by mistake to `receiver_id`.
+    /// `token_id` is either this contract or another token recorded by `ft_on_transfer`. Can only
+    /// be called by the owner.
+    #[payable]
+    pub fn recover_tokens(&mut self, token_id: AccountId, receiver_id: AccountId, amount: U128) {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let current_account_id = env::current_account_id();
+        if token_id == current_account_id {
+            self.internal_transfer(
+                &current_account_id,
+                &receiver_id,
+                amount.0,
+                Some("recover".to_string()),
+            );
+            return;
+        }
+
+        let balance = self.recovery.deposits.get(&token_id).unwrap_or(0);
+        let balance = balance
+            .checked_sub(amount.0)
+            .unwrap_or_else(|| env::panic_str("Not enough tokens to recover"));
+        self.recovery.deposits.insert(&token_id, &balance);
+        ext_ft_core::ext(token_id.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(receiver_id.clone(), amount, Some("recover".to_string()))
+            .then(
+                Self::ext(current_account_id)
+                    .with_static_gas(GAS_FOR_RESOLVE_RECOVER)
+                    .resolve_recover_tokens(token_id, receiver_id, amount),
+            );
+    }
+
+    /// Records the tokens again if they couldn't be sent.
+    #[private]
+    pub fn resolve_recover_tokens(
+        &mut self,
+        token_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) {
+        if matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            log!(
+                "Recovered {} of @{} to @{}",
+                amount.0,
+                token_id,
+                receiver_id
+            );
+        } else {
+            self.recovery.internal_add(&token_id, amount.0);
+        }
+    }
+
+    /// Accepts tokens of `token_id` sent by mistake, so the owner can recover them. Can only be
+    /// called by the owner. The attached deposit must cover the storage of the token, the excess
+    /// is refunded.
+    #[payable]
+    pub fn recovery_add_token(&mut self, token_id: AccountId) {
+        let initial_storage_usage = env::storage_usage();
+        self.assert_owner();
+        require!(
+            token_id != env::current_account_id(),
+            "Tokens of this contract are always recoverable"
+        );
+        require!(
+            self.recovery.deposits.get(&token_id).is_none(),
+            "The token is already recoverable"
+        );
+        self.recovery.deposits.insert(&token_id, &0);
+        self.internal_charge_storage(initial_storage_usage);
+    }
+
+    /// Stops accepting tokens of `token_id` sent by mistake, once all of them were recovered. Can
+    /// only be called by the owner.
+    #[payable]
+    pub fn recovery_remove_token(&mut self, token_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let initial_storage_usage = env::storage_usage();
+        let balance = self
+            .recovery
+            .deposits
+            .remove(&token_id)
+            .unwrap_or_else(|| env::panic_str("The token is not recoverable"));
+        require!(balance == 0, "The token still has tokens to recover");
+        self.internal_refund_storage(initial_storage_usage, env::predecessor_account_id());
+    }
+
+    /// Amount of another token held by the contract account that the owner can recover.
+    pub fn recoverable_balance_of(&self, token_id: AccountId) -> U128 {
+        self.recovery.deposits.get(&token_id).unwrap_or(0).into()
+    }
+
+    /// Returns the tokens held by mistake with their amounts.
+    pub fn recoverable_tokens(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<(AccountId, U128)> {
+        self.recovery
+            .deposits
+            .iter()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.unwrap_or(10) as usize)
+            .map(|(token_id, amount)| (token_id, amount.into()))
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, PromiseOrValue, RuntimeFeesConfig};
+
+    use super::*;
+    use crate::tests::{current, owner, setup_with_config, user1, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const AMOUNT: u128 = 1_000;
+
+    fn other_token() -> AccountId {
+        "other-token.near".parse().unwrap()
+    }
+
+    /// Lists another token and receives `AMOUNT` of it with an empty message.
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.recovery_add_token(other_token());
+
+        testing_env!(context.predecessor_account_id(other_token()).build());
+        let unused = contract.ft_on_transfer(user1(), AMOUNT.into(), "".to_string());
+        assert!(matches!(unused, PromiseOrValue::Value(U128(0))));
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        (contract, context)
+    }
+
+    #[test]
+    fn test_recover_other_token() {
+        let (mut contract, mut context) = setup();
+        assert_eq!(contract.recoverable_balance_of(other_token()).0, AMOUNT);
+
+        contract.recover_tokens(other_token(), user1(), 400.into());
+        assert_eq!(contract.recoverable_balance_of(other_token()).0, 600);
+
+        testing_env!(
+            context.predecessor_account_id(current()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.resolve_recover_tokens(other_token(), user1(), 400.into());
+        assert_eq!(
+            contract.recoverable_tokens(None, None),
+            vec![(other_token(), U128(AMOUNT))]
+        );
+    }
+
+    #[test]
+    fn test_unlisted_token_is_refunded() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id("unknown-token.near".parse().unwrap())
+            .build());
+        let unused = contract.ft_on_transfer(user1(), AMOUNT.into(), "".to_string());
+        assert!(matches!(unused, PromiseOrValue::Value(U128(AMOUNT))));
+        assert_eq!(
+            contract.recoverable_tokens(None, None),
+            vec![(other_token(), U128(AMOUNT))]
+        );
+    }
+
+    #[should_panic(expected = "The token still has tokens to recover")]
+    #[test]
+    fn test_remove_token_panics_with_recoverable_tokens() {
+        let (mut contract, _) = setup();
+
+        contract.recovery_remove_token(other_token());
+    }
+
+    #[should_panic(expected = "Not enough tokens to recover")]
+    #[test]
+    fn test_recover_panics_on_unrecorded_amount() {
+        let (mut contract, _) = setup();
+
+        contract.recover_tokens(other_token(), user1(), (AMOUNT + 1).into());
+    }
+
+    #[test]
+    fn test_recover_own_token() {
+        let (mut contract, mut context) = setup();
+
+        for account_id in [current(), user1()].iter() {
+            testing_env!(context
+                .predecessor_account_id(account_id.clone())
+                .attached_deposit(contract.storage_balance_bounds().min)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(current(), AMOUNT.into(), None);
+
+        contract.recover_tokens(current(), user1(), AMOUNT.into());
+        assert_eq!(contract.ft_balance_of(current()).0, 0);
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - AMOUNT);
+    }
+
+    #[should_panic(expected = "Only the owner can call this method")]
+    #[test]
+    fn test_recover_panics_on_non_owner() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.recover_tokens(other_token(), user1(), AMOUNT.into());
+    }
+}
@@ -0,0 +1,112 @@
+/*!
+Roles granted by the owner to the accounts operating parts of the contract.
+
+The owner pays for the storage of a role with the deposit attached to `grant_role`, and gets it
+back on `revoke_role`. With a council, roles are granted and revoked by the council instead.
+*/
+use near_sdk::{assert_one_yocto, env, log, near, require, AccountId};
+
+use crate::{Contract, ContractExt};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub enum Role {
+    /// Reports the total supply of the rebasing token with `rebase`.
+    Oracle,
+    /// Moves balances between accounts with `ft_force_transfer`, when enabled at initialization.
+    Compliance,
+    /// Writes the internal KYC registry with `kyc_verify` and `kyc_revoke`.
+    Attestor,
+}
+
+impl Contract {
+    pub(crate) fn assert_role(&self, role: Role) {
+        require!(
+            self.roles.contains(&(role, env::predecessor_account_id())),
+            format!("Requires the {:?} role", role)
+        );
+    }
+
+    pub(crate) fn internal_grant_role(&mut self, role: Role, account_id: AccountId) {
+        require!(
+            self.roles.insert(&(role, account_id.clone())),
+            "The account already has the role"
+        );
+        log!("Granted the {:?} role to @{}", role, account_id);
+    }
+
+    pub(crate) fn internal_revoke_role(&mut self, role: Role, account_id: AccountId) {
+        require!(
+            self.roles.remove(&(role, account_id.clone())),
+            "The account doesn't have the role"
+        );
+        log!("Revoked the {:?} role from @{}", role, account_id);
+    }
+}
+
+#[near]
+impl Contract {
+    /// Grants `role` to `account_id`. Can only be called by the owner, without a council or
+    /// timelock. The attached deposit must cover the storage of the role, the excess is refunded.
+    #[payable]
+    pub fn grant_role(&mut self, role: Role, account_id: AccountId) {
+        let initial_storage_usage = env::storage_usage();
+        self.assert_owner_setting();
+        self.internal_grant_role(role, account_id);
+        self.internal_charge_storage(initial_storage_usage);
+    }
+
+    /// Revokes `role` from `account_id`. Can only be called by the owner, without a council or
+    /// timelock.
+    #[payable]
+    pub fn revoke_role(&mut self, role: Role, account_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        let initial_storage_usage = env::storage_usage();
+        self.internal_revoke_role(role, account_id);
+        self.internal_refund_storage(initial_storage_usage, env::predecessor_account_id());
+    }
+
+    pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+        self.roles.contains(&(role, account_id))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1};
+    use crate::ContractConfig;
+
+    #[test]
+    fn test_grant_and_revoke_role() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.grant_role(Role::Oracle, user1());
+        assert!(contract.has_role(Role::Oracle, user1()));
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.revoke_role(Role::Oracle, user1());
+        assert!(!contract.has_role(Role::Oracle, user1()));
+    }
+
+    #[should_panic(expected = "Only the owner can call this method")]
+    #[test]
+    fn test_grant_role_panics_on_non_owner() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.grant_role(Role::Oracle, user1());
+    }
+}
@@ -0,0 +1,649 @@
+/*!
+Staking of the token in the token contract itself.
+
+`stake` moves tokens from the balance of the caller into its staked balance. Staked tokens earn
+rewards, emitted at the rate per second set by the owner until the end of the emission, and shared
+between the stakers in proportion to their stakes. `unstake` moves staked tokens into the unstaked
+balance, where they stop earning rewards and become available to `withdraw_unstaked` after the
+unbonding period, counted in epochs. Unstaking again restarts the unbonding period of the whole
+unstaked balance.
+
+Staked and unstaked tokens are part of the total supply, but not of `ft_balance_of`. See
+`staked_balance_of` instead. Rewards are minted when they are claimed with
+`staking_claim_rewards`.
+
+Staked balances are the receipt of the staked tokens: `staking_transfer` moves them to another
+registered account, which earns their rewards from then on and can unstake them. The transfer
+checks the pause, the denylist and the KYC status of the receiver, and the rewards earned before
+it stay with the sender. The transfer limits only apply to liquid balances, so they are checked
+when the tokens are withdrawn and the rewards claimed instead.
+
+The deposit attached to the first `stake` of an account pays for the storage of its stake, and is
+refunded once everything was withdrawn and claimed.
+*/
+use near_contract_standards::fungible_token::events::FtMint;
+use near_contract_standards::fungible_token::Balance;
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{
+    assert_one_yocto, env, near, require, AccountId, EpochHeight, IntoStorageKey, Timestamp,
+};
+
+use crate::math::{to_u128, U256};
+use crate::{Contract, ContractExt};
+
+const DEFAULT_UNBONDING_EPOCHS: EpochHeight = 4;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+/// Scale of the rewards per staked token.
+const REWARD_SCALE: u128 = 1_000_000_000_000_000_000_000_000;
+
+#[derive(Default)]
+#[near]
+pub struct Stake {
+    pub staked: Balance,
+    /// Rewards per staked token when the rewards of the stake were last updated.
+    pub reward_per_token_paid: [u64; 4],
+    /// Rewards earned and not claimed yet.
+    pub rewards: Balance,
+    pub unstaked: Balance,
+    /// Epoch from which the unstaked balance can be withdrawn.
+    pub unstaked_available_epoch: EpochHeight,
+}
+
+impl Stake {
+    fn is_empty(&self) -> bool {
+        self.staked == 0 && self.rewards == 0 && self.unstaked == 0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[near(serializers = [json])]
+pub struct StakedBalance {
+    pub staked: U128,
+    pub unstaked: U128,
+    pub unstaked_available_epoch: U64,
+    /// Rewards claimable with `staking_claim_rewards`.
+    pub rewards: U128,
+}
+
+#[near(serializers = [json])]
+pub struct StakingInfo {
+    pub total_staked: U128,
+    /// Rewards emitted per second.
+    pub reward_rate: U128,
+    /// Block timestamp in nanoseconds when the emission ends.
+    pub emission_end: U64,
+    pub unbonding_epochs: U64,
+}
+
+#[near(event_json(standard = "ft-staking"))]
+pub enum StakingEvent {
+    #[event_version("1.0.0")]
+    Stake { account_id: AccountId, amount: U128 },
+    #[event_version("1.0.0")]
+    Unstake {
+        account_id: AccountId,
+        amount: U128,
+        available_epoch: U64,
+    },
+    #[event_version("1.0.0")]
+    WithdrawUnstaked { account_id: AccountId, amount: U128 },
+    #[event_version("1.0.0")]
+    Transfer {
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        memo: Option<String>,
+    },
+}
+
+#[near]
+pub struct Staking {
+    pub stakes: LookupMap<AccountId, Stake>,
+    pub total_staked: Balance,
+    pub reward_rate: Balance,
+    pub emission_end: Timestamp,
+    pub unbonding_epochs: EpochHeight,
+    /// Rewards per staked token emitted so far, scaled by `REWARD_SCALE`.
+    pub reward_per_token: [u64; 4],
+    pub updated_at: Timestamp,
+}
+
+impl Staking {
+    pub fn new<S>(prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            stakes: LookupMap::new(prefix),
+            total_staked: 0,
+            reward_rate: 0,
+            emission_end: 0,
+            unbonding_epochs: DEFAULT_UNBONDING_EPOCHS,
+            reward_per_token: [0; 4],
+            updated_at: env::block_timestamp(),
+        }
+    }
+
+    /// Rewards per staked token at the current block timestamp.
+    fn current_reward_per_token(&self) -> U256 {
+        let reward_per_token = U256(self.reward_per_token);
+        let now = env::block_timestamp().min(self.emission_end);
+        if self.total_staked == 0 || now <= self.updated_at {
+            return reward_per_token;
+        }
+        let emitted = U256::from(self.reward_rate) * U256::from(now - self.updated_at);
+        reward_per_token
+            + emitted * U256::from(REWARD_SCALE)
+                / (U256::from(NANOS_PER_SECOND) * U256::from(self.total_staked))
+    }
+
+    /// Brings the rewards per staked token up to date, before the stakes or the emission change.
+    fn internal_update(&mut self) {
+        self.reward_per_token = self.current_reward_per_token().0;
+        self.updated_at = env::block_timestamp();
+    }
+
+    pub(crate) fn internal_set_emission(&mut self, reward_rate: Balance, emission_end: Timestamp) {
+        self.internal_update();
+        self.reward_rate = reward_rate;
+        self.emission_end = emission_end;
+    }
+
+    /// Rewards of `stake` at `reward_per_token`, including the ones already credited to it.
+    fn rewards_of(stake: &Stake, reward_per_token: U256) -> Balance {
+        let earned = U256::from(stake.staked)
+            * (reward_per_token - U256(stake.reward_per_token_paid))
+            / U256::from(REWARD_SCALE);
+        stake.rewards.saturating_add(to_u128(earned))
+    }
+
+    /// Returns the stake of `account_id` with its rewards brought up to date.
+    fn internal_get_stake(&mut self, account_id: &AccountId) -> Stake {
+        self.internal_update();
+        let mut stake = self.stakes.get(account_id).unwrap_or_default();
+        stake.rewards = Self::rewards_of(&stake, U256(self.reward_per_token));
+        stake.reward_per_token_paid = self.reward_per_token;
+        stake
+    }
+}
+
+impl Contract {
+    /// Sets the emission of the rewards, which can't be credited to stakers verified by an
+    /// external KYC registry.
+    pub(crate) fn internal_set_staking_emission(
+        &mut self,
+        reward_rate: Balance,
+        emission_end: Timestamp,
+    ) {
+        require!(
+            reward_rate == 0 || self.kyc.registry_id().is_none(),
+            "Staking rewards are not supported with an external KYC registry"
+        );
+        self.staking
+            .internal_set_emission(reward_rate, emission_end);
+    }
+
+    /// Saves the stake of `account_id`, or removes it and refunds its storage when it is empty.
+    fn internal_save_stake(&mut self, account_id: &AccountId, stake: &Stake) {
+        if stake.is_empty() {
+            let initial_storage_usage = env::storage_usage();
+            if self.staking.stakes.remove(account_id).is_some() {
+                self.internal_refund_storage(initial_storage_usage, account_id.clone());
+            }
+        } else {
+            self.staking.stakes.insert(account_id, stake);
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Stakes `amount` of the predecessor's tokens. The first stake of an account must attach a
+    /// deposit covering the storage of the stake, the excess is refunded.
+    #[payable]
+    pub fn stake(&mut self, amount: U128) {
+        let initial_storage_usage = env::storage_usage();
+        require!(
+            !env::attached_deposit().is_zero(),
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        let account_id = env::predecessor_account_id();
+        self.internal_assert_not_paused();
+        self.internal_assert_not_denied(&account_id);
+        let mut stake = self.staking.internal_get_stake(&account_id);
+        self.internal_lock(&account_id, amount.0);
+        stake.staked += amount.0;
+        self.staking.total_staked += amount.0;
+        self.staking.stakes.insert(&account_id, &stake);
+        self.internal_charge_storage(initial_storage_usage);
+
+        StakingEvent::Stake { account_id, amount }.emit();
+    }
+
+    /// Unstakes `amount` of the predecessor's staked tokens, which can be withdrawn after the
+    /// unbonding period.
+    #[payable]
+    pub fn unstake(&mut self, amount: U128) {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let account_id = env::predecessor_account_id();
+        let mut stake = self.staking.internal_get_stake(&account_id);
+        stake.staked = stake
+            .staked
+            .checked_sub(amount.0)
+            .unwrap_or_else(|| env::panic_str("Not enough staked tokens"));
+        stake.unstaked += amount.0;
+        stake.unstaked_available_epoch = env::epoch_height() + self.staking.unbonding_epochs;
+        self.staking.total_staked -= amount.0;
+        self.staking.stakes.insert(&account_id, &stake);
+
+        StakingEvent::Unstake {
+            account_id,
+            amount,
+            available_epoch: stake.unstaked_available_epoch.into(),
+        }
+        .emit();
+    }
+
+    /// Moves the predecessor's unstaked tokens back to its balance once the unbonding period is
+    /// over. Returns the withdrawn amount.
+    #[payable]
+    pub fn withdraw_unstaked(&mut self) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.internal_assert_not_paused();
+        self.internal_assert_not_denied(&account_id);
+        let mut stake = self.staking.internal_get_stake(&account_id);
+        require!(stake.unstaked > 0, "There are no unstaked tokens");
+        require!(
+            env::epoch_height() >= stake.unstaked_available_epoch,
+            format!(
+                "The unstaked tokens are available from epoch {}",
+                stake.unstaked_available_epoch
+            )
+        );
+        let amount = stake.unstaked;
+        stake.unstaked = 0;
+        self.internal_check_incoming(&account_id, amount);
+        self.internal_unlock(&account_id, amount);
+        self.internal_save_stake(&account_id, &stake);
+
+        StakingEvent::WithdrawUnstaked {
+            account_id,
+            amount: amount.into(),
+        }
+        .emit();
+        amount.into()
+    }
+
+    /// Transfers `amount` of the predecessor's staked tokens to `receiver_id`, which should be
+    /// registered. The first stake of the receiver must be paid by the attached deposit, the
+    /// excess is refunded. The transfer limits don't apply to staked balances.
+    #[payable]
+    pub fn staking_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
+        let initial_storage_usage = env::storage_usage();
+        require!(
+            !env::attached_deposit().is_zero(),
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        let sender_id = env::predecessor_account_id();
+        require!(
+            sender_id != receiver_id,
+            "Sender and receiver should be different"
+        );
+        require!(amount.0 > 0, "The amount should be a positive number");
+        self.internal_assert_can_move(&sender_id, &receiver_id);
+        self.token.internal_unwrap_balance_of(&receiver_id);
+        self.internal_assert_verified(&receiver_id);
+
+        let mut sender_stake = self.staking.internal_get_stake(&sender_id);
+        sender_stake.staked = sender_stake
+            .staked
+            .checked_sub(amount.0)
+            .unwrap_or_else(|| env::panic_str("Not enough staked tokens"));
+        let mut receiver_stake = self.staking.internal_get_stake(&receiver_id);
+        receiver_stake.staked += amount.0;
+        self.staking.stakes.insert(&receiver_id, &receiver_stake);
+        self.internal_charge_storage(initial_storage_usage);
+        self.internal_save_stake(&sender_id, &sender_stake);
+
+        StakingEvent::Transfer {
+            sender_id,
+            receiver_id,
+            amount,
+            memo,
+        }
+        .emit();
+    }
+
+    /// Mints the rewards of the predecessor to its balance. Returns the minted amount.
+    #[payable]
+    pub fn staking_claim_rewards(&mut self) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.internal_assert_not_paused();
+        self.internal_assert_not_denied(&account_id);
+        let mut stake = self.staking.internal_get_stake(&account_id);
+        let rewards = stake.rewards;
+        stake.rewards = 0;
+        if rewards > 0 {
+            self.internal_assert_verified(&account_id);
+            self.internal_check_incoming(&account_id, rewards);
+            self.internal_deposit(&account_id, rewards);
+            FtMint {
+                owner_id: &account_id,
+                amount: rewards.into(),
+                memo: Some("staking rewards"),
+            }
+            .emit();
+        }
+        self.internal_save_stake(&account_id, &stake);
+        rewards.into()
+    }
+
+    /// Emits `reward_rate` tokens per second until the block timestamp `emission_end`, replacing
+    /// the current emission. Can only be called by the owner, without a council or timelock.
+    #[payable]
+    pub fn staking_set_emission(&mut self, reward_rate: U128, emission_end: U64) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        self.internal_set_staking_emission(reward_rate.0, emission_end.0);
+    }
+
+    /// Sets the number of epochs unstaked tokens take to become available. Only applies to tokens
+    /// unstaked afterwards. Can only be called by the owner, without a council or timelock.
+    #[payable]
+    pub fn staking_set_unbonding_epochs(&mut self, unbonding_epochs: U64) {
+        assert_one_yocto();
+        self.assert_owner_setting();
+        self.staking.unbonding_epochs = unbonding_epochs.0;
+    }
+
+    pub fn staked_balance_of(&self, account_id: AccountId) -> StakedBalance {
+        let stake = self.staking.stakes.get(&account_id).unwrap_or_default();
+        StakedBalance {
+            staked: stake.staked.into(),
+            unstaked: stake.unstaked.into(),
+            unstaked_available_epoch: stake.unstaked_available_epoch.into(),
+            rewards: Staking::rewards_of(&stake, self.staking.current_reward_per_token()).into(),
+        }
+    }
+
+    pub fn staking_info(&self) -> StakingInfo {
+        StakingInfo {
+            total_staked: self.staking.total_staked.into(),
+            reward_rate: self.staking.reward_rate.into(),
+            emission_end: self.staking.emission_end.into(),
+            unbonding_epochs: self.staking.unbonding_epochs.into(),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::limits::TransferLimits;
+    use crate::tests::{owner, setup_with_config, user1, user2, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const AMOUNT: u128 = 1_000_000;
+    const SECOND: u64 = 1_000_000_000;
+
+    fn setup() -> (Contract, VMContextBuilder) {
+        setup_with(ContractConfig::default())
+    }
+
+    /// Registers user1 and user2 with `AMOUNT` each, and emits 100 tokens per second for 100
+    /// seconds.
+    fn setup_with(config: ContractConfig) -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(config);
+
+        for account_id in [user1(), user2()].iter() {
+            testing_env!(context
+                .predecessor_account_id(account_id.clone())
+                .attached_deposit(contract.storage_balance_bounds().min)
+                .build());
+            contract.storage_deposit(None, None);
+        }
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), AMOUNT.into(), None);
+        contract.ft_transfer(user2(), AMOUNT.into(), None);
+        contract.staking_set_emission(U128(100), U64(100 * SECOND));
+        (contract, context)
+    }
+
+    fn stake(contract: &mut Contract, context: &mut VMContextBuilder, account_id: AccountId) {
+        testing_env!(context
+            .predecessor_account_id(account_id)
+            .attached_deposit(NearToken::from_millinear(10))
+            .build());
+        contract.stake(AMOUNT.into());
+    }
+
+    #[test]
+    fn test_stake_unstake_withdraw() {
+        let (mut contract, mut context) = setup();
+        stake(&mut contract, &mut context, user1());
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .epoch_height(10)
+            .build());
+        contract.unstake(AMOUNT.into());
+        let balance = contract.staked_balance_of(user1());
+        assert_eq!((balance.staked.0, balance.unstaked.0), (0, AMOUNT));
+        assert_eq!(balance.unstaked_available_epoch.0, 14);
+
+        testing_env!(context.epoch_height(14).build());
+        assert_eq!(contract.withdraw_unstaked().0, AMOUNT);
+        assert_eq!(contract.ft_balance_of(user1()).0, AMOUNT);
+        assert_eq!(contract.staking_info().total_staked.0, 0);
+    }
+
+    #[should_panic(expected = "The unstaked tokens are available from epoch 4")]
+    #[test]
+    fn test_withdraw_panics_during_unbonding() {
+        let (mut contract, mut context) = setup();
+        stake(&mut contract, &mut context, user1());
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .epoch_height(0)
+            .build());
+        contract.unstake(AMOUNT.into());
+        testing_env!(context.epoch_height(3).build());
+        contract.withdraw_unstaked();
+    }
+
+    #[test]
+    fn test_rewards_are_shared_by_stake() {
+        let (mut contract, mut context) = setup();
+        stake(&mut contract, &mut context, user1());
+
+        // user1 earns alone for 10 seconds, then user2 stakes as much
+        testing_env!(context.block_timestamp(10 * SECOND).build());
+        stake(&mut contract, &mut context, user2());
+
+        // the emission ends at 100 seconds
+        testing_env!(context
+            .block_timestamp(200 * SECOND)
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        assert_eq!(contract.staked_balance_of(user1()).rewards.0, 1_000 + 4_500);
+        assert_eq!(contract.staking_claim_rewards().0, 4_500);
+        assert_eq!(contract.ft_balance_of(user2()).0, 4_500);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + 4_500);
+    }
+
+    #[test]
+    fn test_staking_transfer() {
+        let (mut contract, mut context) = setup();
+        stake(&mut contract, &mut context, user1());
+
+        // user1 earns alone for 10 seconds, then sends half of its stake to user2
+        testing_env!(context
+            .block_timestamp(10 * SECOND)
+            .attached_deposit(NearToken::from_millinear(10))
+            .build());
+        contract.staking_transfer(user2(), (AMOUNT / 2).into(), None);
+        assert_eq!(contract.staked_balance_of(user1()).staked.0, AMOUNT / 2);
+        assert_eq!(contract.staked_balance_of(user2()).staked.0, AMOUNT / 2);
+        assert_eq!(contract.staking_info().total_staked.0, AMOUNT);
+
+        testing_env!(context.block_timestamp(20 * SECOND).build());
+        assert_eq!(contract.staked_balance_of(user1()).rewards.0, 1_000 + 500);
+        assert_eq!(contract.staked_balance_of(user2()).rewards.0, 500);
+
+        // the receiver can unstake its receipt
+        testing_env!(context
+            .predecessor_account_id(user2())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.unstake((AMOUNT / 2).into());
+        assert_eq!(contract.staked_balance_of(user2()).unstaked.0, AMOUNT / 2);
+    }
+
+    #[should_panic(expected = "The token is paused")]
+    #[test]
+    fn test_staking_transfer_panics_when_paused() {
+        let (mut contract, mut context) = setup();
+        stake(&mut contract, &mut context, user1());
+        contract.paused = true;
+
+        contract.staking_transfer(user2(), AMOUNT.into(), None);
+    }
+
+    #[should_panic(expected = "The token is paused")]
+    #[test]
+    fn test_stake_panics_when_paused() {
+        let (mut contract, mut context) = setup();
+        contract.paused = true;
+
+        stake(&mut contract, &mut context, user1());
+    }
+
+    #[should_panic(expected = "@charlie is denied")]
+    #[test]
+    fn test_withdraw_panics_when_denied() {
+        let (mut contract, mut context) = setup();
+        stake(&mut contract, &mut context, user1());
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.unstake(AMOUNT.into());
+        contract.denylist.insert(&user1());
+
+        testing_env!(context.epoch_height(4).build());
+        contract.withdraw_unstaked();
+    }
+
+    #[should_panic(expected = "@charlie is denied")]
+    #[test]
+    fn test_claim_rewards_panics_when_denied() {
+        let (mut contract, mut context) = setup();
+        stake(&mut contract, &mut context, user1());
+        contract.denylist.insert(&user1());
+
+        testing_env!(context
+            .block_timestamp(10 * SECOND)
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.staking_claim_rewards();
+    }
+
+    /// Caps balances at `AMOUNT`, and has user1 stake its tokens and then receive `AMOUNT` again.
+    fn setup_at_max_balance() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with(ContractConfig {
+            transfer_limits: Some(TransferLimits {
+                max_transfer: None,
+                max_balance: Some(AMOUNT.into()),
+                max_daily_outflow: None,
+            }),
+            ..Default::default()
+        });
+        stake(&mut contract, &mut context, user1());
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), AMOUNT.into(), None);
+        (contract, context)
+    }
+
+    #[should_panic(expected = "Transfer limit exceeded")]
+    #[test]
+    fn test_withdraw_panics_above_max_balance() {
+        let (mut contract, mut context) = setup_at_max_balance();
+
+        testing_env!(context.predecessor_account_id(user1()).build());
+        contract.unstake(AMOUNT.into());
+        testing_env!(context.epoch_height(4).build());
+        contract.withdraw_unstaked();
+    }
+
+    #[should_panic(expected = "Transfer limit exceeded")]
+    #[test]
+    fn test_claim_rewards_panics_above_max_balance() {
+        let (mut contract, mut context) = setup_at_max_balance();
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .block_timestamp(10 * SECOND)
+            .build());
+        contract.staking_claim_rewards();
+    }
+
+    #[test]
+    fn test_staking_transfer_ignores_transfer_limits() {
+        let (mut contract, mut context) = setup_at_max_balance();
+
+        // user2 already holds `AMOUNT` of liquid tokens
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(NearToken::from_millinear(10))
+            .build());
+        contract.staking_transfer(user2(), AMOUNT.into(), None);
+        assert_eq!(contract.staked_balance_of(user2()).staked.0, AMOUNT);
+    }
+
+    #[should_panic(expected = "This setting is changed through the timelock")]
+    #[test]
+    fn test_set_unbonding_epochs_panics_with_timelock() {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            timelock_delay: Some(U64(SECOND)),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.staking_set_unbonding_epochs(U64(1));
+    }
+
+    #[should_panic(expected = "Not enough staked tokens")]
+    #[test]
+    fn test_unstake_panics_above_stake() {
+        let (mut contract, mut context) = setup();
+        stake(&mut contract, &mut context, user1());
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.unstake((AMOUNT + 1).into());
+    }
+}
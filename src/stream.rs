@@ -0,0 +1,698 @@
+/*!
+Per-second payment streams.
+
+The sender locks a deposit that is streamed to the receiver at a constant rate between the start
+and the stop time. The receiver can withdraw the streamed amount at any time. Either party can
+cancel the stream, which pays out the streamed amount to the receiver and the rest back to the
+sender.
+
+Streams of this token lock the deposit on `stream_create`. Streams of other NEP-141 tokens are
+created with `stream_create` as well, and then funded by sending the deposit with
+`ft_transfer_call` on the streamed token, passing `{"fund_stream": {"stream_id": "<id>"}}` as the
+message. The sender pays for the storage of the stream with the deposit attached to
+`stream_create`, and gets it back once the stream is settled.
+*/
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{
+    assert_one_yocto, env, near, require, AccountId, Gas, IntoStorageKey, NearToken, PromiseResult,
+};
+
+use crate::{Contract, ContractExt, StorageKey};
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+const GAS_FOR_FT_TRANSFER: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_PAYOUT: Gas = Gas::from_tgas(5);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub enum StreamParty {
+    Sender,
+    Receiver,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[near(serializers = [borsh, json])]
+pub struct Stream {
+    pub id: U64,
+    /// The streamed token. This contract for streams of this token.
+    pub token_id: AccountId,
+    pub sender_id: AccountId,
+    pub receiver_id: AccountId,
+    /// Amount streamed per second.
+    pub rate: U128,
+    /// Block timestamp in nanoseconds at which the stream starts.
+    pub start_time: U64,
+    /// Block timestamp in nanoseconds at which the stream stops.
+    pub stop_time: U64,
+    /// Total amount streamed between the start and the stop time.
+    pub deposit: U128,
+    /// Amount already withdrawn by the receiver.
+    pub withdrawn: U128,
+    /// Amount owed to the sender after a cancellation, which couldn't be transferred yet.
+    pub refund: U128,
+    /// Whether the deposit was received. Always true for streams of this token.
+    pub funded: bool,
+    /// Number of payouts of another token waiting for the transfer result.
+    pub pending_payouts: u32,
+}
+
+impl Stream {
+    /// Amount streamed to the receiver by `timestamp`, rounded down.
+    pub fn streamed_at(&self, timestamp: u64) -> u128 {
+        if timestamp <= self.start_time.0 {
+            return 0;
+        }
+        let elapsed = u128::from(std::cmp::min(timestamp, self.stop_time.0) - self.start_time.0);
+        // can't overflow, as `rate * (stop_time - start_time)` was checked on creation
+        self.rate.0 * elapsed / NANOS_PER_SECOND
+    }
+
+    /// Whether the whole deposit was paid out, which can only happen once the stream stopped.
+    fn is_settled(&self) -> bool {
+        self.withdrawn == self.deposit && self.refund.0 == 0 && self.pending_payouts == 0
+    }
+}
+
+#[near(event_json(standard = "ft-stream"))]
+pub enum StreamEvent {
+    #[event_version("1.0.0")]
+    StreamCreate {
+        id: U64,
+        token_id: AccountId,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        rate: U128,
+        start_time: U64,
+        stop_time: U64,
+        deposit: U128,
+    },
+    #[event_version("1.0.0")]
+    StreamFund { id: U64, amount: U128 },
+    #[event_version("1.0.0")]
+    StreamWithdraw {
+        id: U64,
+        receiver_id: AccountId,
+        amount: U128,
+    },
+    #[event_version("1.0.0")]
+    StreamCancel {
+        id: U64,
+        canceled_by: AccountId,
+        receiver_amount: U128,
+        sender_amount: U128,
+    },
+}
+
+#[near]
+pub struct Streams {
+    /// Id assigned to the next stream.
+    pub next_id: u64,
+
+    /// Stream id -> stream.
+    pub streams: UnorderedMap<u64, Stream>,
+
+    /// AccountID -> ids of the streams where the account is the sender or the receiver.
+    pub by_party: LookupMap<AccountId, UnorderedSet<u64>>,
+}
+
+impl Streams {
+    pub fn new<S, P>(streams_prefix: S, by_party_prefix: P) -> Self
+    where
+        S: IntoStorageKey,
+        P: IntoStorageKey,
+    {
+        Self {
+            next_id: 0,
+            streams: UnorderedMap::new(streams_prefix),
+            by_party: LookupMap::new(by_party_prefix),
+        }
+    }
+
+    fn internal_unwrap_stream(&self, id: u64) -> Stream {
+        self.streams
+            .get(&id)
+            .unwrap_or_else(|| env::panic_str(format!("Stream {} doesn't exist", id).as_str()))
+    }
+
+    fn internal_add(&mut self, stream: &Stream) {
+        self.streams.insert(&stream.id.0, stream);
+        for account_id in [&stream.sender_id, &stream.receiver_id] {
+            let mut ids = self.by_party.get(account_id).unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::StreamsByPartyInner {
+                    account_hash: env::sha256_array(account_id.as_bytes()),
+                })
+            });
+            ids.insert(&stream.id.0);
+            self.by_party.insert(account_id, &ids);
+        }
+    }
+
+    fn internal_remove(&mut self, stream: &Stream) {
+        self.streams.remove(&stream.id.0);
+        for account_id in [&stream.sender_id, &stream.receiver_id] {
+            if let Some(mut ids) = self.by_party.get(account_id) {
+                ids.remove(&stream.id.0);
+                if ids.is_empty() {
+                    self.by_party.remove(account_id);
+                } else {
+                    self.by_party.insert(account_id, &ids);
+                }
+            }
+        }
+    }
+}
+
+impl Contract {
+    fn is_native_stream(&self, stream: &Stream) -> bool {
+        stream.token_id == env::current_account_id()
+    }
+
+    /// Saves the stream, or removes it and refunds its storage to the sender once it's settled.
+    fn internal_save_stream(&mut self, stream: &Stream) {
+        if stream.is_settled() {
+            let initial_storage_usage = env::storage_usage();
+            self.streams.internal_remove(stream);
+            self.internal_refund_storage(initial_storage_usage, stream.sender_id.clone());
+        } else {
+            self.streams.streams.insert(&stream.id.0, stream);
+        }
+    }
+
+    /// Pays `amount` of the streamed token out to the given party of the stream. Payouts of other
+    /// tokens are put back into the stream if the transfer fails.
+    fn internal_stream_payout(&mut self, stream: &mut Stream, party: StreamParty, amount: u128) {
+        let account_id = match party {
+            StreamParty::Sender => &stream.sender_id,
+            StreamParty::Receiver => &stream.receiver_id,
+        };
+        if self.is_native_stream(stream) {
+            self.internal_before_unlock(&stream.sender_id, account_id, amount);
+            self.internal_unlock_verified(&stream.sender_id, account_id, amount);
+        } else {
+            ext_ft_core::ext(stream.token_id.clone())
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .with_static_gas(GAS_FOR_FT_TRANSFER)
+                .ft_transfer(account_id.clone(), U128(amount), None)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_RESOLVE_PAYOUT)
+                        .stream_resolve_payout(stream.id, party, U128(amount)),
+                );
+            stream.pending_payouts += 1;
+        }
+    }
+
+    /// Funds a stream of another token with `amount` received from `sender_id` through
+    /// `ft_on_transfer`. Returns the unused amount.
+    pub(crate) fn internal_fund_stream(
+        &mut self,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        stream_id: U64,
+        amount: u128,
+    ) -> u128 {
+        let mut stream = self.streams.internal_unwrap_stream(stream_id.0);
+        require!(
+            &stream.token_id == token_id,
+            "The stream is for a different token"
+        );
+        require!(
+            &stream.sender_id == sender_id,
+            "Only the sender can fund the stream"
+        );
+        require!(!stream.funded, "The stream is already funded");
+        let unused_amount = amount
+            .checked_sub(stream.deposit.0)
+            .unwrap_or_else(|| env::panic_str("The amount is less than the stream deposit"));
+
+        stream.funded = true;
+        self.streams.streams.insert(&stream_id.0, &stream);
+
+        StreamEvent::StreamFund {
+            id: stream_id,
+            amount: stream.deposit,
+        }
+        .emit();
+
+        unused_amount
+    }
+}
+
+#[near]
+impl Contract {
+    /// Creates a stream of `rate` tokens per second from the predecessor to `receiver_id` between
+    /// `start_time` and `stop_time`. When `token_id` is another token, the stream has to be
+    /// funded through `ft_transfer_call` before anything is streamed. The attached deposit must
+    /// cover the storage of the stream, the excess is refunded. Returns the id of the stream.
+    #[payable]
+    pub fn stream_create(
+        &mut self,
+        receiver_id: AccountId,
+        rate: U128,
+        start_time: U64,
+        stop_time: U64,
+        token_id: Option<AccountId>,
+    ) -> U64 {
+        let initial_storage_usage = env::storage_usage();
+        let sender_id = env::predecessor_account_id();
+        let token_id = token_id.unwrap_or_else(env::current_account_id);
+        require!(
+            sender_id != receiver_id,
+            "Sender and receiver should be different"
+        );
+        require!(rate.0 > 0, "The rate should be a positive number");
+        require!(
+            start_time.0 >= env::block_timestamp(),
+            "Start time should not be in the past"
+        );
+        require!(
+            stop_time.0 > start_time.0,
+            "Stop time should be after the start time"
+        );
+        let deposit = rate
+            .0
+            .checked_mul((stop_time.0 - start_time.0).into())
+            .unwrap_or_else(|| env::panic_str("Stream deposit overflow"))
+            / NANOS_PER_SECOND;
+        require!(
+            deposit > 0,
+            "The stream deposit should be a positive number"
+        );
+
+        let funded = token_id == env::current_account_id();
+        if funded {
+            self.token.internal_unwrap_balance_of(&receiver_id);
+            self.internal_before_lock(&sender_id, &receiver_id, deposit);
+            self.internal_lock(&sender_id, deposit);
+        }
+
+        let stream = Stream {
+            id: U64(self.streams.next_id),
+            token_id,
+            sender_id,
+            receiver_id,
+            rate,
+            start_time,
+            stop_time,
+            deposit: U128(deposit),
+            withdrawn: U128(0),
+            refund: U128(0),
+            funded,
+            pending_payouts: 0,
+        };
+        self.streams.next_id += 1;
+        self.streams.internal_add(&stream);
+        self.internal_charge_storage(initial_storage_usage);
+
+        StreamEvent::StreamCreate {
+            id: stream.id,
+            token_id: stream.token_id,
+            sender_id: stream.sender_id,
+            receiver_id: stream.receiver_id,
+            rate: stream.rate,
+            start_time: stream.start_time,
+            stop_time: stream.stop_time,
+            deposit: stream.deposit,
+        }
+        .emit();
+
+        stream.id
+    }
+
+    /// Withdraws `amount` (everything withdrawable by default) of the streamed tokens. Can only
+    /// be called by the receiver.
+    #[payable]
+    pub fn stream_withdraw(&mut self, id: U64, amount: Option<U128>) -> U128 {
+        assert_one_yocto();
+        let mut stream = self.streams.internal_unwrap_stream(id.0);
+        require!(
+            env::predecessor_account_id() == stream.receiver_id,
+            "Only the receiver can withdraw from the stream"
+        );
+        require!(stream.funded, "The stream is not funded");
+        let withdrawable = stream.streamed_at(env::block_timestamp()) - stream.withdrawn.0;
+        let amount = amount.map_or(withdrawable, |amount| amount.0);
+        require!(amount > 0, "Nothing to withdraw");
+        require!(
+            amount <= withdrawable,
+            "The amount exceeds the withdrawable amount"
+        );
+
+        stream.withdrawn = U128(stream.withdrawn.0 + amount);
+        self.internal_stream_payout(&mut stream, StreamParty::Receiver, amount);
+        self.internal_save_stream(&stream);
+
+        StreamEvent::StreamWithdraw {
+            id,
+            receiver_id: stream.receiver_id,
+            amount: U128(amount),
+        }
+        .emit();
+
+        U128(amount)
+    }
+
+    /// Stops the stream, paying the streamed tokens out to the receiver and the rest back to the
+    /// sender. Can be called by the sender or the receiver. The streamed tokens of a denied
+    /// receiver are kept in the stream, to be withdrawn if it is allowed again.
+    #[payable]
+    pub fn stream_cancel(&mut self, id: U64) {
+        assert_one_yocto();
+        let mut stream = self.streams.internal_unwrap_stream(id.0);
+        let predecessor_id = env::predecessor_account_id();
+        require!(
+            predecessor_id == stream.sender_id || predecessor_id == stream.receiver_id,
+            "Only the sender or the receiver can cancel the stream"
+        );
+
+        if !stream.funded {
+            let initial_storage_usage = env::storage_usage();
+            self.streams.internal_remove(&stream);
+            self.internal_refund_storage(initial_storage_usage, stream.sender_id.clone());
+            StreamEvent::StreamCancel {
+                id,
+                canceled_by: predecessor_id,
+                receiver_amount: U128(0),
+                sender_amount: U128(0),
+            }
+            .emit();
+            return;
+        }
+
+        // stopping the stream now keeps the streamed amount, and releases the rest of the deposit
+        let now = env::block_timestamp().clamp(stream.start_time.0, stream.stop_time.0);
+        let streamed = stream.streamed_at(now);
+        let sender_amount = stream.deposit.0 - streamed + stream.refund.0;
+        let mut receiver_amount = streamed - stream.withdrawn.0;
+        stream.stop_time = U64(now);
+        stream.deposit = U128(streamed);
+        stream.refund = U128(0);
+        // the share of a denied receiver stays in the stream, so the sender still gets the rest
+        if self.is_native_stream(&stream) && self.denylist.contains(&stream.receiver_id) {
+            receiver_amount = 0;
+        } else {
+            stream.withdrawn = U128(streamed);
+        }
+        if receiver_amount > 0 {
+            self.internal_stream_payout(&mut stream, StreamParty::Receiver, receiver_amount);
+        }
+        if sender_amount > 0 {
+            self.internal_stream_payout(&mut stream, StreamParty::Sender, sender_amount);
+        }
+        self.internal_save_stream(&stream);
+
+        StreamEvent::StreamCancel {
+            id,
+            canceled_by: predecessor_id,
+            receiver_amount: U128(receiver_amount),
+            sender_amount: U128(sender_amount),
+        }
+        .emit();
+    }
+
+    /// Puts a failed payout of another token back into the stream, so it can be paid out again
+    /// with `stream_withdraw` or `stream_cancel`.
+    #[private]
+    pub fn stream_resolve_payout(&mut self, id: U64, party: StreamParty, amount: U128) {
+        // the stream isn't removed while it has pending payouts
+        let mut stream = self.streams.internal_unwrap_stream(id.0);
+        stream.pending_payouts -= 1;
+        if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+            match party {
+                StreamParty::Sender => stream.refund = U128(stream.refund.0 + amount.0),
+                StreamParty::Receiver => stream.withdrawn = U128(stream.withdrawn.0 - amount.0),
+            }
+        }
+        self.internal_save_stream(&stream);
+    }
+
+    pub fn stream(&self, id: U64) -> Option<Stream> {
+        self.streams.streams.get(&id.0)
+    }
+
+    /// Returns the open streams where `account_id` is the sender or the receiver.
+    pub fn streams_of(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<Stream> {
+        let Some(ids) = self.streams.by_party.get(&account_id) else {
+            return vec![];
+        };
+        ids.iter()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.unwrap_or(10) as usize)
+            .map(|id| self.streams.internal_unwrap_stream(id))
+            .collect()
+    }
+
+    /// Amount streamed to the receiver so far, including the withdrawn amount.
+    pub fn stream_streamed(&self, id: U64) -> U128 {
+        let stream = self.streams.internal_unwrap_stream(id.0);
+        U128(stream.streamed_at(env::block_timestamp()))
+    }
+
+    /// Amount the receiver can withdraw now.
+    pub fn stream_withdrawable(&self, id: U64) -> U128 {
+        let stream = self.streams.internal_unwrap_stream(id.0);
+        if !stream.funded {
+            return U128(0);
+        }
+        U128(stream.streamed_at(env::block_timestamp()) - stream.withdrawn.0)
+    }
+
+    /// Amount of the deposit that is still to be streamed.
+    pub fn stream_remaining(&self, id: U64) -> U128 {
+        let stream = self.streams.internal_unwrap_stream(id.0);
+        U128(stream.deposit.0 - stream.streamed_at(env::block_timestamp()))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseOrValue, RuntimeFeesConfig};
+
+    use super::*;
+    use crate::tests::{owner, setup_with_config, user1, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const SECOND: u64 = 1_000_000_000;
+    const START: u64 = 100 * SECOND;
+    const STOP: u64 = 200 * SECOND;
+    const RATE: u128 = 10;
+    const DEPOSIT: u128 = RATE * 100;
+
+    fn other_token() -> AccountId {
+        accounts(5)
+    }
+
+    fn setup(token_id: Option<AccountId>) -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig::default());
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        contract.stream_create(user1(), RATE.into(), U64(START), U64(STOP), token_id);
+
+        (contract, context)
+    }
+
+    fn call_at(context: &mut VMContextBuilder, account_id: AccountId, timestamp: u64) {
+        testing_env!(context
+            .predecessor_account_id(account_id)
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .block_timestamp(timestamp)
+            .build());
+    }
+
+    #[test]
+    fn test_create_locks_deposit() {
+        let (contract, _) = setup(None);
+
+        let stream = contract.stream(U64(0)).unwrap();
+        assert_eq!(stream.deposit.0, DEPOSIT);
+        assert!(stream.funded);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - DEPOSIT);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert_eq!(contract.streams_of(user1(), None, None), vec![stream]);
+    }
+
+    #[test]
+    fn test_amounts_over_time() {
+        let (contract, mut context) = setup(None);
+
+        for (timestamp, streamed) in [
+            (0, 0),
+            (START, 0),
+            (START + SECOND / 2, RATE / 2),
+            (START + 30 * SECOND, 30 * RATE),
+            (STOP, DEPOSIT),
+            (STOP + 1000 * SECOND, DEPOSIT),
+        ] {
+            testing_env!(context.block_timestamp(timestamp).build());
+            assert_eq!(contract.stream_streamed(U64(0)).0, streamed);
+            assert_eq!(contract.stream_withdrawable(U64(0)).0, streamed);
+            assert_eq!(contract.stream_remaining(U64(0)).0, DEPOSIT - streamed);
+        }
+    }
+
+    #[test]
+    fn test_withdraw() {
+        let (mut contract, mut context) = setup(None);
+
+        call_at(&mut context, user1(), START + 10 * SECOND);
+        assert_eq!(contract.stream_withdraw(U64(0), Some(U128(40))).0, 40);
+        assert_eq!(contract.stream_withdraw(U64(0), None).0, 10 * RATE - 40);
+        assert_eq!(contract.ft_balance_of(user1()).0, 10 * RATE);
+        assert_eq!(contract.stream_withdrawable(U64(0)).0, 0);
+
+        // the stream is removed once everything was withdrawn
+        call_at(&mut context, user1(), STOP);
+        contract.stream_withdraw(U64(0), None);
+        assert_eq!(contract.ft_balance_of(user1()).0, DEPOSIT);
+        assert!(contract.stream(U64(0)).is_none());
+        assert!(contract.streams_of(owner(), None, None).is_empty());
+    }
+
+    #[should_panic(expected = "The amount exceeds the withdrawable amount")]
+    #[test]
+    fn test_withdraw_panics_on_amount_greater_than_streamed() {
+        let (mut contract, mut context) = setup(None);
+
+        call_at(&mut context, user1(), START + 10 * SECOND);
+        contract.stream_withdraw(U64(0), Some(U128(10 * RATE + 1)));
+    }
+
+    #[should_panic(expected = "Only the receiver can withdraw from the stream")]
+    #[test]
+    fn test_withdraw_panics_on_sender() {
+        let (mut contract, mut context) = setup(None);
+
+        call_at(&mut context, owner(), STOP);
+        contract.stream_withdraw(U64(0), None);
+    }
+
+    #[test]
+    fn test_cancel_splits_pro_rata() {
+        let (mut contract, mut context) = setup(None);
+
+        call_at(&mut context, user1(), START + 25 * SECOND);
+        contract.stream_withdraw(U64(0), Some(U128(100)));
+
+        call_at(&mut context, owner(), START + 40 * SECOND);
+        contract.stream_cancel(U64(0));
+
+        assert_eq!(contract.ft_balance_of(user1()).0, 40 * RATE);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - 40 * RATE);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
+        assert!(contract.stream(U64(0)).is_none());
+    }
+
+    #[test]
+    fn test_cancel_keeps_share_of_denied_receiver() {
+        let (mut contract, mut context) = setup(None);
+        contract.denylist.insert(&user1());
+
+        call_at(&mut context, owner(), START + 40 * SECOND);
+        contract.stream_cancel(U64(0));
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY - 40 * RATE);
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+
+        contract.denylist.remove(&user1());
+        call_at(&mut context, user1(), START + 50 * SECOND);
+        assert_eq!(contract.stream_withdraw(U64(0), None).0, 40 * RATE);
+        assert_eq!(contract.ft_balance_of(user1()).0, 40 * RATE);
+        assert!(contract.stream(U64(0)).is_none());
+    }
+
+    #[test]
+    fn test_cancel_before_start_refunds_everything() {
+        let (mut contract, mut context) = setup(None);
+
+        call_at(&mut context, user1(), START - SECOND);
+        contract.stream_cancel(U64(0));
+
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+        assert_eq!(contract.ft_balance_of(user1()).0, 0);
+        assert!(contract.stream(U64(0)).is_none());
+    }
+
+    #[test]
+    fn test_other_token_stream_is_funded_through_ft_on_transfer() {
+        let (mut contract, mut context) = setup(Some(other_token()));
+        assert!(!contract.stream(U64(0)).unwrap().funded);
+        assert_eq!(contract.ft_balance_of(owner()).0, TOTAL_SUPPLY);
+
+        testing_env!(context
+            .predecessor_account_id(other_token())
+            .attached_deposit(NearToken::from_yoctonear(0))
+            .build());
+        let unused_amount = contract.ft_on_transfer(
+            owner(),
+            U128(DEPOSIT + 5),
+            r#"{"fund_stream": {"stream_id": "0"}}"#.to_string(),
+        );
+        assert!(matches!(unused_amount, PromiseOrValue::Value(U128(5))));
+        assert!(contract.stream(U64(0)).unwrap().funded);
+
+        // the payout is put back into the stream if the transfer fails
+        call_at(&mut context, user1(), STOP);
+        contract.stream_withdraw(U64(0), None);
+        assert_eq!(contract.stream(U64(0)).unwrap().pending_payouts, 1);
+
+        testing_env!(
+            context.predecessor_account_id(contract_account()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![near_sdk::PromiseResult::Failed],
+        );
+        contract.stream_resolve_payout(U64(0), StreamParty::Receiver, U128(DEPOSIT));
+        assert_eq!(contract.stream_withdrawable(U64(0)).0, DEPOSIT);
+
+        // and the stream is removed after a successful payout
+        call_at(&mut context, user1(), STOP);
+        contract.stream_withdraw(U64(0), None);
+        testing_env!(
+            context.predecessor_account_id(contract_account()).build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![near_sdk::PromiseResult::Successful(vec![])],
+        );
+        contract.stream_resolve_payout(U64(0), StreamParty::Receiver, U128(DEPOSIT));
+        assert!(contract.stream(U64(0)).is_none());
+    }
+
+    #[should_panic(expected = "Only the sender can fund the stream")]
+    #[test]
+    fn test_ft_on_transfer_panics_on_other_sender() {
+        let (mut contract, mut context) = setup(Some(other_token()));
+
+        testing_env!(context.predecessor_account_id(other_token()).build());
+        contract.ft_on_transfer(
+            user1(),
+            U128(DEPOSIT),
+            r#"{"fund_stream": {"stream_id": "0"}}"#.to_string(),
+        );
+    }
+
+    fn contract_account() -> AccountId {
+        crate::tests::current()
+    }
+}
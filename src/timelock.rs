@@ -0,0 +1,330 @@
+/*!
+Delay queue for the admin actions of the owner.
+
+The owner schedules an `AdminAction` with `schedule`, which anybody can `execute` once the delay
+has passed, giving holders time to react. The owner can `cancel` a scheduled action until it is
+executed. The delay of an action is fixed when it is scheduled, and changing the delay is itself
+an admin action going through the queue.
+
+The timelock is enabled at initialization with `new_with_config` and `timelock_delay`, and can't be
+combined with a council, which approves the admin actions instead. While it is enabled, the owner
+settings which have an `AdminAction` counterpart, like the fees, the interest rate, the staking
+emission or the transfer limits, can't be changed directly by the owner. The owner pays for the
+storage of a scheduled action with the deposit attached to `schedule`, which also pays for the
+storage used by the action. The owner gets the rest back when the action is executed, or all of it
+when the action is cancelled.
+*/
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::U64;
+use near_sdk::{assert_one_yocto, env, near, require, AccountId, Duration, IntoStorageKey};
+
+use crate::admin::AdminAction;
+use crate::{Contract, ContractExt};
+
+#[derive(Clone)]
+#[near(serializers = [borsh, json])]
+pub struct ScheduledAction {
+    pub id: U64,
+    pub action: AdminAction,
+    pub scheduled_by: AccountId,
+    /// Block timestamp in nanoseconds from which the action can be executed.
+    pub execute_after: U64,
+}
+
+#[near(event_json(standard = "ft-timelock"))]
+pub enum TimelockEvent {
+    #[event_version("1.0.0")]
+    ActionSchedule { id: U64, execute_after: U64 },
+    #[event_version("1.0.0")]
+    ActionExecute { id: U64 },
+    #[event_version("1.0.0")]
+    ActionCancel { id: U64 },
+}
+
+#[near]
+pub struct Timelock {
+    /// Delay of the scheduled actions in nanoseconds. The timelock is disabled when not set.
+    pub delay: Option<Duration>,
+
+    /// Id assigned to the next scheduled action.
+    pub next_id: u64,
+
+    /// Action id -> scheduled action.
+    pub queue: UnorderedMap<u64, ScheduledAction>,
+}
+
+impl Timelock {
+    pub fn new<S>(prefix: S, delay: Option<Duration>) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            delay,
+            next_id: 0,
+            queue: UnorderedMap::new(prefix),
+        }
+    }
+
+    fn internal_unwrap_action(&self, id: u64) -> ScheduledAction {
+        self.queue.get(&id).unwrap_or_else(|| {
+            env::panic_str(format!("Scheduled action {} doesn't exist", id).as_str())
+        })
+    }
+}
+
+#[near]
+impl Contract {
+    /// Schedules `action` to be executable after the delay of the timelock. Can only be called by
+    /// the owner. The attached deposit must cover the storage of the action, the excess is
+    /// refunded. Returns the id of the scheduled action.
+    #[payable]
+    pub fn schedule(&mut self, action: AdminAction) -> U64 {
+        let initial_storage_usage = env::storage_usage();
+        self.assert_owner();
+        // the owner alone can't bypass the council
+        require!(
+            !self.council.is_enabled(),
+            "The admin actions are approved by the council"
+        );
+        let delay = self
+            .timelock
+            .delay
+            .unwrap_or_else(|| env::panic_str("The timelock is disabled"));
+        self.internal_assert_admin_action(&action);
+        let scheduled = ScheduledAction {
+            id: U64(self.timelock.next_id),
+            action,
+            scheduled_by: env::predecessor_account_id(),
+            execute_after: U64(env::block_timestamp().saturating_add(delay)),
+        };
+        self.timelock.next_id += 1;
+        self.timelock.queue.insert(&scheduled.id.0, &scheduled);
+        self.internal_charge_storage(initial_storage_usage);
+
+        TimelockEvent::ActionSchedule {
+            id: scheduled.id,
+            execute_after: scheduled.execute_after,
+        }
+        .emit();
+        scheduled.id
+    }
+
+    /// Executes the scheduled action `id` once its delay has passed. Can be called by anybody.
+    pub fn execute(&mut self, id: U64) {
+        let scheduled = self.timelock.internal_unwrap_action(id.0);
+        require!(
+            env::block_timestamp() >= scheduled.execute_after.0,
+            format!(
+                "The action can be executed from {}",
+                scheduled.execute_after.0
+            )
+        );
+        let initial_storage_usage = env::storage_usage();
+        self.timelock.queue.remove(&id.0);
+
+        TimelockEvent::ActionExecute { id }.emit();
+        self.internal_execute_paid_admin_action(
+            scheduled.action,
+            initial_storage_usage,
+            scheduled.scheduled_by,
+        );
+    }
+
+    /// Removes the scheduled action `id`. Can only be called by the owner.
+    #[payable]
+    pub fn cancel(&mut self, id: U64) {
+        assert_one_yocto();
+        self.assert_owner();
+        let scheduled = self.timelock.internal_unwrap_action(id.0);
+        let initial_storage_usage = env::storage_usage();
+        self.timelock.queue.remove(&id.0);
+        self.internal_refund_storage(initial_storage_usage, scheduled.scheduled_by);
+
+        TimelockEvent::ActionCancel { id }.emit();
+    }
+
+    pub fn timelock_delay(&self) -> Option<U64> {
+        self.timelock.delay.map(U64)
+    }
+
+    pub fn scheduled_action(&self, id: U64) -> Option<ScheduledAction> {
+        self.timelock.queue.get(&id.0)
+    }
+
+    pub fn scheduled_actions(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<ScheduledAction> {
+        self.timelock
+            .queue
+            .values()
+            .skip(from_index.map_or(0, |from_index| from_index.0) as usize)
+            .take(limit.unwrap_or(10) as usize)
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::council::CouncilConfig;
+    use crate::tests::{owner, setup_with_config, user1, TOTAL_SUPPLY};
+    use crate::ContractConfig;
+
+    const DELAY: Duration = 1_000_000;
+
+    /// Registers user1 and schedules to mint 1_000 tokens to it.
+    fn setup() -> (Contract, VMContextBuilder) {
+        let (mut contract, mut context) = setup_with_config(ContractConfig {
+            timelock_delay: Some(U64(DELAY)),
+            ..Default::default()
+        });
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .attached_deposit(contract.storage_balance_bounds().min)
+            .build());
+        contract.storage_deposit(None, None);
+
+        testing_env!(context
+            .predecessor_account_id(owner())
+            .attached_deposit(NearToken::from_near(1))
+            .build());
+        let id = contract.schedule(AdminAction::Mint {
+            account_id: user1(),
+            amount: U128(1_000),
+        });
+        assert_eq!(id.0, 0);
+        (contract, context)
+    }
+
+    #[test]
+    fn test_execute_after_delay() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .predecessor_account_id(user1())
+            .block_timestamp(DELAY)
+            .build());
+        contract.execute(U64(0));
+        assert_eq!(contract.ft_balance_of(user1()).0, 1_000);
+        assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY + 1_000);
+        assert!(contract.scheduled_action(U64(0)).is_none());
+    }
+
+    #[should_panic(expected = "The action can be executed from 1000000")]
+    #[test]
+    fn test_execute_panics_before_delay() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context.block_timestamp(DELAY - 1).build());
+        contract.execute(U64(0));
+    }
+
+    #[should_panic(expected = "Scheduled action 0 doesn't exist")]
+    #[test]
+    fn test_cancelled_action_cant_be_executed() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.cancel(U64(0));
+        assert!(contract.scheduled_actions(None, None).is_empty());
+
+        testing_env!(context.block_timestamp(DELAY).build());
+        contract.execute(U64(0));
+    }
+
+    #[should_panic(expected = "@charlie is denied")]
+    #[test]
+    fn test_denied_account_cant_receive() {
+        let (mut contract, mut context) = setup();
+
+        contract.schedule(AdminAction::Deny {
+            account_id: user1(),
+        });
+        testing_env!(context.block_timestamp(DELAY).build());
+        contract.execute(U64(1));
+        assert!(contract.is_denied(user1()));
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.ft_transfer(user1(), U128(1), None);
+    }
+
+    #[test]
+    fn test_fee_and_delay_changes_go_through_timelock() {
+        let (mut contract, mut context) = setup();
+
+        contract.schedule(AdminAction::SetFlashFee { fee: 9 });
+        contract.schedule(AdminAction::SetTimelockDelay {
+            delay: U64(2 * DELAY),
+        });
+        testing_env!(context.block_timestamp(DELAY).build());
+        contract.execute(U64(1));
+        contract.execute(U64(2));
+        assert_eq!(contract.flash_fee(U128(10_000)).0, 9);
+        assert_eq!(contract.timelock_delay(), Some(U64(2 * DELAY)));
+
+        let id = contract.schedule(AdminAction::SetPaused { paused: true });
+        assert_eq!(
+            contract.scheduled_action(id).unwrap().execute_after.0,
+            3 * DELAY
+        );
+    }
+
+    #[should_panic(expected = "This setting is changed through the timelock")]
+    #[test]
+    fn test_set_fee_panics_with_timelock() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.flash_set_fee(9);
+    }
+
+    #[should_panic(expected = "This setting is changed through the timelock")]
+    #[test]
+    fn test_supply_settings_panic_with_timelock() {
+        let (mut contract, mut context) = setup();
+
+        testing_env!(context
+            .attached_deposit(NearToken::from_yoctonear(1))
+            .build());
+        contract.staking_set_emission(U128(u128::MAX), U64(u64::MAX));
+    }
+
+    #[should_panic(expected = "The council and the timelock can't be combined")]
+    #[test]
+    fn test_schedule_panics_on_council() {
+        let (mut contract, _) = setup();
+
+        contract.schedule(AdminAction::SetCouncil {
+            members: vec![owner()],
+            threshold: 1,
+        });
+    }
+
+    #[should_panic(expected = "The council and the timelock can't be combined")]
+    #[test]
+    fn test_new_panics_with_council_and_timelock() {
+        setup_with_config(ContractConfig {
+            council: Some(CouncilConfig {
+                members: vec![owner()],
+                threshold: 1,
+            }),
+            timelock_delay: Some(U64(DELAY)),
+            ..Default::default()
+        });
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::expect_fun_call, clippy::needless_return)]
+
 use std::sync::LazyLock;
 
 use cargo_near_build::BuildOpts;
@@ -1,13 +1,23 @@
 /*!
 Some hypothetical DeFi contract that will do smart things with the transferred tokens
 */
+use near_contract_standards::fungible_token::core::ext_ft_core;
 use near_contract_standards::fungible_token::{receiver::FungibleTokenReceiver, Balance};
 use near_sdk::json_types::U128;
-use near_sdk::{env, log, near, require, AccountId, Gas, PanicOnDefault, PromiseOrValue};
+use near_sdk::{
+    env, ext_contract, log, near, require, AccountId, Gas, NearToken, PanicOnDefault, Promise,
+    PromiseOrValue,
+};
 
 const BASE_GAS: u64 = 5_000_000_000_000;
 const PROMISE_CALL: u64 = 5_000_000_000_000;
 const GAS_FOR_FT_ON_TRANSFER: Gas = Gas::from_gas(BASE_GAS + PROMISE_CALL);
+const GAS_FOR_BORROW: Gas = Gas::from_gas(BASE_GAS + PROMISE_CALL);
+
+#[ext_contract(ext_flash_lender)]
+trait FlashLender {
+    fn flash_loan(&mut self, receiver_id: AccountId, amount: U128, msg: String) -> Promise;
+}
 
 #[derive(PanicOnDefault)]
 #[near(contract_state)]
@@ -61,6 +71,48 @@ impl FungibleTokenReceiver for DeFi {
     }
 }
 
+/// Flash borrower mode, used to test `flash_loan`
+#[near]
+impl DeFi {
+    /// Borrows `amount` from the fungible token contract, passing `msg` to `on_flash_loan`
+    #[payable]
+    pub fn borrow(&mut self, amount: U128, msg: String) -> Promise {
+        let prepaid_gas = env::prepaid_gas();
+        ext_flash_lender::ext(self.fungible_token_account_id.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(prepaid_gas.saturating_sub(GAS_FOR_BORROW))
+            .flash_loan(env::current_account_id(), amount, msg)
+    }
+
+    /// If given `msg: "repay"`, keeps the borrowed tokens so that the token contract burns them
+    /// back along with the fee, which has to be in the balance already.
+    /// If given `msg: "forward"`, sends the borrowed tokens away so that they can't be repaid.
+    /// Otherwise, panics so that the loan is rolled back
+    pub fn on_flash_loan(
+        &mut self,
+        initiator_id: AccountId,
+        amount: U128,
+        fee: U128,
+        msg: String,
+    ) -> PromiseOrValue<()> {
+        require!(
+            env::predecessor_account_id() == self.fungible_token_account_id,
+            "Only supports the one fungible token contract"
+        );
+        // only pays fees for the loans it asked for
+        require!(initiator_id == env::current_account_id(), "Only borrows for itself");
+        log!("in on_flash_loan, {} tokens for a fee of {}, msg = {}", amount.0, fee.0, msg);
+        match msg.as_str() {
+            "repay" => PromiseOrValue::Value(()),
+            "forward" => ext_ft_core::ext(self.fungible_token_account_id.clone())
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .ft_transfer(self.fungible_token_account_id.clone(), amount, None)
+                .into(),
+            _ => env::panic_str("The flash loan is not repaid"),
+        }
+    }
+}
+
 #[near]
 impl ValueReturnTrait for DeFi {
     fn value_please(&self, amount_to_return: String) -> PromiseOrValue<U128> {
@@ -0,0 +1,112 @@
+use std::sync::LazyLock;
+
+use cargo_near_build::BuildOpts;
+use near_contract_standards::fungible_token::metadata::{FungibleTokenMetadata, FT_METADATA_SPEC};
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::Value;
+use near_sdk::{AccountId, NearToken};
+
+static TOKEN_FACTORY_WASM: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    let artifact = cargo_near_build::build(BuildOpts {
+        manifest_path: Some("factory/Cargo.toml".into()),
+        no_abi: true,
+        no_embed_abi: true,
+        ..Default::default()
+    })
+    .expect("Could not compile Token Factory contract for tests");
+
+    std::fs::read(&artifact.path).unwrap_or_else(|_| {
+        panic!(
+            "Could not read Token Factory WASM file from {}",
+            artifact.path
+        )
+    })
+});
+
+fn metadata(symbol: &str, decimals: u8) -> FungibleTokenMetadata {
+    FungibleTokenMetadata {
+        spec: FT_METADATA_SPEC.to_string(),
+        name: format!("{} token", symbol),
+        symbol: symbol.to_string(),
+        icon: None,
+        reference: None,
+        reference_hash: None,
+        decimals,
+    }
+}
+
+#[tokio::test]
+async fn factory_creates_tokens() -> anyhow::Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let factory = worker.dev_deploy(&TOKEN_FACTORY_WASM).await?;
+    let res = factory
+        .call("new")
+        .args_json((root.id(),))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_success());
+
+    let tokens = [
+        (metadata("ABC", 24), 1_000u128),
+        (metadata("XYZ", 18), 2_000),
+        (metadata("USD", 6), 3_000),
+    ];
+    for (metadata, total_supply) in tokens.iter() {
+        let deposit = factory
+            .view("get_required_deposit")
+            .args_json((metadata,))
+            .await?
+            .json::<NearToken>()?;
+        let res = root
+            .call(factory.id(), "create_token")
+            .args_json((root.id(), U128(*total_supply), metadata))
+            .max_gas()
+            .deposit(deposit)
+            .transact()
+            .await?;
+        assert!(res.is_success());
+        assert!(res.json::<bool>()?);
+    }
+
+    assert_eq!(
+        factory.view("get_tokens_count").await?.json::<u64>()?,
+        tokens.len() as u64
+    );
+    for (metadata, total_supply) in tokens.iter() {
+        let token_id: AccountId =
+            format!("{}.{}", metadata.symbol.to_lowercase(), factory.id()).parse()?;
+        let res = root
+            .view(&token_id, "ft_metadata")
+            .await?
+            .json::<FungibleTokenMetadata>()?;
+        assert_eq!(res.symbol, metadata.symbol);
+        assert_eq!(res.decimals, metadata.decimals);
+        let res = root
+            .view(&token_id, "ft_balance_of")
+            .args_json((root.id(),))
+            .await?
+            .json::<U128>()?;
+        assert_eq!(res.0, *total_supply);
+
+        let info = factory
+            .view("get_token")
+            .args_json((token_id,))
+            .await?
+            .json::<Value>()?;
+        assert_eq!(info["owner_id"], root.id().as_str());
+    }
+
+    // the symbol is already taken
+    let res = root
+        .call(factory.id(), "create_token")
+        .args_json((root.id(), U128(1_000), metadata("abc", 24)))
+        .max_gas()
+        .deposit(NearToken::from_near(10))
+        .transact()
+        .await?;
+    assert!(res.is_failure());
+
+    Ok(())
+}
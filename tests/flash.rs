@@ -0,0 +1,152 @@
+pub mod common;
+
+use near_sdk::json_types::U128;
+use near_sdk::NearToken;
+
+use common::{init_accounts, init_contracts, register_user, ONE_YOCTO};
+
+#[tokio::test]
+async fn flash_loan_repaid_and_rolled_back() -> anyhow::Result<()> {
+    let initial_balance = U128::from(NearToken::from_near(10000).as_yoctonear());
+    let loan_amount = U128::from(NearToken::from_near(1000).as_yoctonear());
+    let fee = U128::from(NearToken::from_near(10).as_yoctonear());
+
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let (alice, _, _, _) = init_accounts(&root).await?;
+    let (ft_contract, defi_contract) = init_contracts(&worker, initial_balance, &alice).await?;
+    register_user(&ft_contract, defi_contract.id()).await?;
+
+    // the borrower holds enough to pay the 1% fee
+    for (method, args) in [
+        (
+            "ft_transfer",
+            near_sdk::serde_json::json!([defi_contract.id(), fee, null]),
+        ),
+        (
+            "flash_set_max_loan",
+            near_sdk::serde_json::json!([loan_amount]),
+        ),
+        ("flash_set_fee", near_sdk::serde_json::json!([100])),
+    ]
+    .iter()
+    {
+        let res = ft_contract
+            .call(method)
+            .args_json(args)
+            .max_gas()
+            .deposit(ONE_YOCTO)
+            .transact()
+            .await?;
+        assert!(res.is_success());
+    }
+
+    let res = alice
+        .call(defi_contract.id(), "borrow")
+        .args_json((loan_amount, "repay"))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    let logs = res.logs();
+    assert!(logs
+        .iter()
+        .any(|log| log.contains(r#""event":"ft_burn""#) && log.contains("flash loan repayment")));
+    assert!(logs
+        .iter()
+        .any(|log| log.contains(r#""event":"flash_loan""#) && log.contains(r#""repaid":true"#)));
+    assert!(res.json::<bool>()?);
+
+    let defi_balance = ft_contract
+        .view("ft_balance_of")
+        .args_json((defi_contract.id(),))
+        .await?
+        .json::<U128>()?;
+    assert_eq!(defi_balance.0, 0);
+    let total_supply = ft_contract.view("ft_total_supply").await?.json::<U128>()?;
+    assert_eq!(total_supply.0, initial_balance.0 - fee.0);
+
+    // the borrower fails, so nothing is left minted
+    let res = alice
+        .call(defi_contract.id(), "borrow")
+        .args_json((loan_amount, "keep"))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    assert!(res
+        .logs()
+        .iter()
+        .any(|log| log.contains(r#""event":"ft_burn""#) && log.contains("flash loan rollback")));
+    assert!(!res.json::<bool>()?);
+    let total_supply = ft_contract.view("ft_total_supply").await?.json::<U128>()?;
+    assert_eq!(total_supply.0, initial_balance.0 - fee.0);
+
+    // loans above the maximum are rejected
+    let res = alice
+        .call(defi_contract.id(), "borrow")
+        .args_json((U128(loan_amount.0 + 1), "repay"))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_failure());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn flash_loan_forwarded_is_recorded_as_debt() -> anyhow::Result<()> {
+    let initial_balance = U128::from(NearToken::from_near(10000).as_yoctonear());
+    let loan_amount = U128::from(NearToken::from_near(1000).as_yoctonear());
+
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let (alice, _, _, _) = init_accounts(&root).await?;
+    let (ft_contract, defi_contract) = init_contracts(&worker, initial_balance, &alice).await?;
+    register_user(&ft_contract, defi_contract.id()).await?;
+
+    let res = ft_contract
+        .call("flash_set_max_loan")
+        .args_json((loan_amount,))
+        .max_gas()
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_success());
+
+    // the borrower sends the loan to the token owner instead of repaying it
+    let res = alice
+        .call(defi_contract.id(), "borrow")
+        .args_json((loan_amount, "forward"))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    assert!(res
+        .logs()
+        .iter()
+        .any(|log| log.contains(r#""event":"flash_loan""#) && log.contains(r#""repaid":false"#)));
+    assert!(!res.json::<bool>()?);
+
+    let debt = ft_contract
+        .view("flash_debt_of")
+        .args_json((defi_contract.id(),))
+        .await?
+        .json::<U128>()?;
+    assert_eq!(debt, loan_amount);
+    let total_supply = ft_contract.view("ft_total_supply").await?.json::<U128>()?;
+    assert_eq!(total_supply.0, initial_balance.0 + loan_amount.0);
+
+    // the receiver can't borrow again
+    let res = alice
+        .call(defi_contract.id(), "borrow")
+        .args_json((loan_amount, "repay"))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.receipt_failures().iter().any(|failure| {
+        format!("{:?}", failure).contains("The receiver has an outstanding flash loan debt")
+    }));
+
+    Ok(())
+}
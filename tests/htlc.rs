@@ -0,0 +1,144 @@
+pub mod common;
+
+use near_sdk::json_types::{Base58CryptoHash, Base64VecU8, U128, U64};
+use near_sdk::NearToken;
+
+use common::{init_accounts, init_contracts, ONE_YOCTO};
+
+const PREIMAGE: &[u8] = b"atomic swap secret";
+
+fn hashlock() -> Base58CryptoHash {
+    near_sdk::env::sha256_array(PREIMAGE).into()
+}
+
+#[tokio::test]
+async fn htlc_claim_with_preimage() -> anyhow::Result<()> {
+    let initial_balance = U128::from(NearToken::from_near(10000).as_yoctonear());
+    let lock_amount = U128::from(NearToken::from_near(100).as_yoctonear());
+
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let (alice, bob, _, _) = init_accounts(&root).await?;
+    let (ft_contract, _) = init_contracts(&worker, initial_balance, &alice).await?;
+
+    let timelock = worker.view_block().await?.timestamp() + 3_600_000_000_000;
+    let res = ft_contract
+        .call("htlc_lock")
+        .args_json((alice.id(), lock_amount, hashlock(), U64(timelock)))
+        .max_gas()
+        .deposit(NearToken::from_millinear(10))
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    assert!(res
+        .logs()
+        .iter()
+        .any(|log| log.contains("htlc_lock") && log.contains(&String::from(&hashlock()))));
+    let id = res.json::<U64>()?;
+
+    // locked tokens can't be spent
+    let res = ft_contract
+        .call("ft_transfer")
+        .args_json((alice.id(), initial_balance, Option::<String>::None))
+        .max_gas()
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_failure());
+
+    let res = bob
+        .call(ft_contract.id(), "htlc_claim")
+        .args_json((id, Base64VecU8::from(b"wrong guess".to_vec())))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_failure());
+
+    // anyone knowing the preimage can claim for the receiver
+    let res = bob
+        .call(ft_contract.id(), "htlc_claim")
+        .args_json((id, Base64VecU8::from(PREIMAGE.to_vec())))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    assert!(res
+        .logs()
+        .iter()
+        .any(|log| log.contains("htlc_claim") && log.contains(&String::from(&hashlock()))));
+
+    let alice_balance = ft_contract
+        .call("ft_balance_of")
+        .args_json((alice.id(),))
+        .view()
+        .await?
+        .json::<U128>()?;
+    assert_eq!(alice_balance, lock_amount);
+    let res = ft_contract.call("ft_total_supply").view().await?;
+    assert_eq!(res.json::<U128>()?, initial_balance);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn htlc_refund_after_timelock() -> anyhow::Result<()> {
+    let initial_balance = U128::from(NearToken::from_near(10000).as_yoctonear());
+    let lock_amount = U128::from(NearToken::from_near(100).as_yoctonear());
+
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let (alice, _, _, _) = init_accounts(&root).await?;
+    let (ft_contract, _) = init_contracts(&worker, initial_balance, &alice).await?;
+
+    let timelock = worker.view_block().await?.timestamp() + 10_000_000_000;
+    let res = ft_contract
+        .call("htlc_lock")
+        .args_json((alice.id(), lock_amount, hashlock(), U64(timelock)))
+        .max_gas()
+        .deposit(NearToken::from_millinear(10))
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    let id = res.json::<U64>()?;
+
+    let res = ft_contract
+        .call("htlc_refund")
+        .args_json((id,))
+        .max_gas()
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_failure());
+
+    while worker.view_block().await?.timestamp() < timelock {
+        worker.fast_forward(100).await?;
+    }
+
+    let res = ft_contract
+        .call("htlc_refund")
+        .args_json((id,))
+        .max_gas()
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_success());
+
+    // the preimage can't be used after the refund
+    let res = alice
+        .call(ft_contract.id(), "htlc_claim")
+        .args_json((id, Base64VecU8::from(PREIMAGE.to_vec())))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(res.is_failure());
+
+    let root_balance = ft_contract
+        .call("ft_balance_of")
+        .args_json((ft_contract.id(),))
+        .view()
+        .await?
+        .json::<U128>()?;
+    assert_eq!(root_balance, initial_balance);
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 pub mod common;
 
-use near_sdk::{json_types::U128, NearToken};
+use near_sdk::{json_types::U128, Gas, NearToken};
 use near_workspaces::{operations::Function, result::ValueOrReceiptId};
 
 use common::{init_accounts, init_contracts, register_user, ONE_YOCTO};
@@ -81,10 +81,19 @@ async fn transfer_call_with_burned_amount() -> anyhow::Result<()> {
     assert!(res.is_success());
 
     let logs = res.logs();
-    let expected = format!("Account @{} burned {}", ft_contract.id(), 10);
+    let expected = format!(
+        r#"EVENT_JSON:{{"standard":"nep141","version":"1.0.0","event":"ft_burn","data":[{{"owner_id":"{}","amount":"10","memo":"refund"}}]}}"#,
+        ft_contract.id()
+    );
     assert!(logs.len() >= 2);
     assert!(logs.contains(&"The account of the sender was deleted"));
     assert!(logs.contains(&(expected.as_str())));
+    // the burn is reported once, for the sender whose refund was burned
+    let burns = logs
+        .iter()
+        .filter(|log| log.contains(r#""event":"ft_burn""#))
+        .count();
+    assert_eq!(burns, 1);
 
     match res.receipt_outcomes()[5].clone().into_result()? {
         ValueOrReceiptId::Value(val) => {
@@ -247,6 +256,68 @@ async fn transfer_call_with_promise_and_refund() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn transfer_call_with_gas_and_refund() -> anyhow::Result<()> {
+    let initial_balance = U128::from(NearToken::from_near(10000).as_yoctonear());
+    let refund_amount = U128::from(NearToken::from_near(50).as_yoctonear());
+    let transfer_amount = U128::from(NearToken::from_near(100).as_yoctonear());
+    let receiver_gas = Gas::from_tgas(100);
+
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let (alice, _, _, _) = init_accounts(&root).await?;
+    let (ft_contract, defi_contract) = init_contracts(&worker, initial_balance, &alice).await?;
+    register_user(&ft_contract, defi_contract.id()).await?;
+
+    let min_gas = ft_contract
+        .view("ft_transfer_call_min_gas")
+        .args_json((receiver_gas,))
+        .await?
+        .json::<Gas>()?;
+
+    // less than the minimum gas is rejected before any tokens move
+    let res = ft_contract
+        .call("ft_transfer_call_with_gas")
+        .args_json((
+            defi_contract.id(),
+            transfer_amount,
+            Option::<String>::None,
+            refund_amount.0.to_string(),
+            receiver_gas,
+        ))
+        .gas(min_gas.checked_sub(Gas::from_gas(1)).unwrap())
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_failure());
+
+    let res = ft_contract
+        .call("ft_transfer_call_with_gas")
+        .args_json((
+            defi_contract.id(),
+            transfer_amount,
+            Option::<String>::None,
+            refund_amount.0.to_string(),
+            receiver_gas,
+        ))
+        .gas(min_gas)
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    assert_eq!(res.json::<U128>()?.0, transfer_amount.0 - refund_amount.0);
+
+    let defi_balance = ft_contract
+        .call("ft_balance_of")
+        .args_json((defi_contract.id(),))
+        .view()
+        .await?
+        .json::<U128>()?;
+    assert_eq!(transfer_amount.0 - refund_amount.0, defi_balance.0);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn transfer_call_promise_panics_for_a_full_refund() -> anyhow::Result<()> {
     let initial_balance = U128::from(NearToken::from_near(10000).as_yoctonear());